@@ -0,0 +1,534 @@
+use std::{collections::HashMap, io::Write};
+
+use color_eyre::eyre;
+
+use crate::core::{Environment, MEMORY_SIZE, OPCODE_COUNT};
+
+/// Mnemonic, opcode and operand count for every instruction. Instructions
+/// always occupy four words; missing operands assemble as 0.
+pub const INSTRUCTIONS: &[(&str, i32, usize)] = &[
+  ("halt", 0, 0),
+  ("add", 1, 3),
+  ("sub", 2, 3),
+  ("mult", 3, 3),
+  ("div", 4, 3),
+  ("cp", 5, 2),
+  ("and", 6, 3),
+  ("or", 7, 3),
+  ("not", 8, 2),
+  ("sl", 9, 3),
+  ("sr", 10, 3),
+  ("cpfa", 11, 3),
+  ("cpta", 12, 3),
+  ("be", 13, 3),
+  ("bne", 14, 3),
+  ("blt", 15, 3),
+  ("call", 16, 2),
+  ("ret", 17, 1),
+  ("adc", 18, 3),
+  ("sbb", 19, 3),
+  ("cpwait", 20, 2),
+  ("cpfi", 21, 2),
+  ("cpti", 22, 2),
+  ("ldb", 23, 3),
+  ("stb", 24, 3),
+  ("ldh", 25, 3),
+  ("sth", 26, 3),
+  ("bltu", 27, 3),
+  ("rem", 28, 3),
+  ("srl", 29, 3),
+  ("bo", 30, 1),
+];
+
+/// `cpdata dest constant` assembles to `cp dest <own last word>` with the
+/// constant stored in that last word.
+const CPDATA: &str = "cpdata";
+
+pub struct Assembly {
+  pub environment: Environment,
+  /// Labels in definition order.
+  pub labels: Vec<(String, u32)>,
+  /// Every source line with the address it assembled at and the words it
+  /// emitted, which are empty for label-only, blank and comment lines.
+  pub listing: Vec<ListingLine>,
+}
+
+pub struct ListingLine {
+  pub addr: u32,
+  pub words: Vec<i32>,
+  pub source: String,
+}
+
+enum Item<'a> {
+  Instruction { opcode: i32, operands: Vec<&'a str> },
+  CpData { operands: Vec<&'a str> },
+  Data { values: Vec<&'a str> },
+}
+
+struct Line<'a> {
+  number: usize,
+  addr: u32,
+  item: Option<Item<'a>>,
+}
+
+/// A problem found while assembling, tied to its 1-based source line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+  pub line: usize,
+  pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+/// Assembles source where labels start in the first column, instructions
+/// and data are indented or follow a label, and `//` starts a comment. Fails
+/// with every diagnostic if there are any.
+pub fn assemble(source: &str) -> eyre::Result<Assembly> {
+  assemble_at(source, 0)
+}
+
+/// `assemble`, laying the program out from `base` rather than 0 so labels
+/// resolve to where it will actually sit.
+pub fn assemble_at(source: &str, base: u32) -> eyre::Result<Assembly> {
+  let (assembly, diagnostics) = assemble_all(source, base);
+  if !diagnostics.is_empty() {
+    eyre::bail!(
+      "{}",
+      diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+    );
+  }
+  Ok(assembly)
+}
+
+/// Every error in `source`, in line order, without stopping at the first.
+pub fn check(source: &str) -> Vec<Diagnostic> {
+  assemble_all(source, 0).1
+}
+
+/// Assembles `source` at `base` and writes it over `environment`, leaving
+/// every cell the snippet doesn't occupy as it was, for replacing a single
+/// routine in a loaded program.
+pub fn assemble_into(
+  source: &str,
+  environment: &mut Environment,
+  base: u32,
+) -> eyre::Result<Assembly> {
+  let assembly = assemble_at(source, base)?;
+  environment.load_segment(&assembly.environment, 0)?;
+  Ok(assembly)
+}
+
+/// Assembles as much as possible, standing in zeroes for anything that
+/// couldn't be resolved so later lines keep their addresses.
+fn assemble_all(source: &str, base: u32) -> (Assembly, Vec<Diagnostic>) {
+  let mut diagnostics = Vec::new();
+  let mut labels = Vec::new();
+  let mut label_addrs = HashMap::new();
+  let mut lines = Vec::new();
+  let mut addr = base;
+
+  // first pass: lay out every line and record where labels land
+  for (index, text) in source.lines().enumerate() {
+    let number = index + 1;
+    let text = text.split("//").next().unwrap_or_default();
+    let mut tokens = text.split_whitespace();
+    let mut error = |message: String| {
+      diagnostics.push(Diagnostic {
+        line: number,
+        message,
+      })
+    };
+
+    if !text.starts_with(char::is_whitespace) {
+      if let Some(label) = tokens.next() {
+        if label_addrs.contains_key(label) {
+          error(format!("duplicate label `{label}`"));
+        } else {
+          label_addrs.insert(label, addr);
+          labels.push((label.to_owned(), addr));
+        }
+      }
+    }
+
+    let tokens = tokens.collect::<Vec<_>>();
+    let Some((&first, rest)) = tokens.split_first() else {
+      lines.push(Line {
+        number,
+        addr,
+        item: None,
+      });
+      continue;
+    };
+
+    let (item, len) = if first == CPDATA {
+      (
+        expect_operands(first, rest, 2)
+          .map_err(&mut error)
+          .ok()
+          .map(|_| Item::CpData {
+            operands: rest.to_vec(),
+          }),
+        4,
+      )
+    } else if let Some(&(name, opcode, count)) =
+      INSTRUCTIONS.iter().find(|(name, ..)| *name == first)
+    {
+      (
+        expect_operands(name, rest, count)
+          .map_err(&mut error)
+          .ok()
+          .map(|_| Item::Instruction {
+            opcode,
+            operands: rest.to_vec(),
+          }),
+        4,
+      )
+    } else {
+      (
+        Some(Item::Data {
+          values: tokens.clone(),
+        }),
+        tokens.len() as u32,
+      )
+    };
+
+    if addr as usize + len as usize > MEMORY_SIZE {
+      error("program does not fit in memory".to_owned());
+      break;
+    }
+    lines.push(Line { number, addr, item });
+    addr += len;
+  }
+
+  // second pass: resolve operands now that every label is known
+  let mut environment = Environment::default();
+  let mut listing = Vec::new();
+  let sources = source.lines().collect::<Vec<_>>();
+  for Line { number, addr, item } in lines {
+    // a word where an instruction would start that's neither a number nor
+    // a label is most likely a misspelt mnemonic
+    let item = match item {
+      Some(Item::Data { values })
+        if values[0].starts_with(char::is_alphabetic)
+          && !label_addrs.contains_key(values[0]) =>
+      {
+        diagnostics.push(Diagnostic {
+          line: number,
+          message: format!("unknown mnemonic `{}`", values[0]),
+        });
+        Some(Item::Data {
+          values: vec!["0"; values.len()],
+        })
+      }
+      item => item,
+    };
+    let mut resolve = |operand: &str| {
+      resolve_operand(operand, &label_addrs).unwrap_or_else(|message| {
+        diagnostics.push(Diagnostic {
+          line: number,
+          message,
+        });
+        0
+      })
+    };
+
+    let words = match item {
+      None => Vec::new(),
+      Some(Item::Instruction { opcode, operands }) => {
+        let mut words = vec![opcode, 0, 0, 0];
+        for (word, operand) in words[1..].iter_mut().zip(operands) {
+          *word = resolve(operand);
+        }
+        words
+      }
+      Some(Item::CpData { operands }) => {
+        vec![
+          5,
+          resolve(operands[0]),
+          addr as i32 + 3,
+          resolve(operands[1]),
+        ]
+      }
+      Some(Item::Data { values }) => values.into_iter().map(resolve).collect(),
+    };
+
+    let start = addr as usize;
+    environment.memory[start..start + words.len()].copy_from_slice(&words);
+    environment.initialized[start..start + words.len()].fill(true);
+    listing.push(ListingLine {
+      addr,
+      words,
+      source: sources[number - 1].to_owned(),
+    });
+  }
+
+  // the passes find problems in different orders
+  diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+  (
+    Assembly {
+      environment,
+      labels,
+      listing,
+    },
+    diagnostics,
+  )
+}
+
+fn expect_operands(
+  mnemonic: &str,
+  operands: &[&str],
+  count: usize,
+) -> Result<(), String> {
+  if operands.len() != count {
+    return Err(format!(
+      "`{mnemonic}` takes {count} operands, found {}",
+      operands.len()
+    ));
+  }
+  Ok(())
+}
+
+/// Numbers may be decimal or `0x` hex, and anything that fits in 32 bits
+/// signed or unsigned is accepted; everything else must be a label.
+fn resolve_operand(
+  operand: &str,
+  labels: &HashMap<&str, u32>,
+) -> Result<i32, String> {
+  let (negative, digits) = match operand.strip_prefix('-') {
+    Some(digits) => (true, digits),
+    None => (false, operand),
+  };
+  let parsed = match digits.strip_prefix("0x") {
+    Some(hex) => i64::from_str_radix(hex, 16).ok(),
+    None if digits.starts_with(|c: char| c.is_ascii_digit()) => {
+      digits.parse::<i64>().ok()
+    }
+    None => {
+      return labels
+        .get(operand)
+        .map(|addr| *addr as i32)
+        .ok_or_else(|| format!("undefined label `{operand}`"));
+    }
+  };
+
+  let value = parsed.ok_or_else(|| format!("invalid number `{operand}`"))?;
+  let value = if negative { -value } else { value };
+  if value < i32::MIN as i64 || value > u32::MAX as i64 {
+    return Err(format!("operand `{operand}` out of range"));
+  }
+  Ok(value as i32)
+}
+
+impl Assembly {
+  /// Writes a MIF image of the program, preceded by its symbol table as
+  /// comments.
+  pub fn write_mif(&self, writer: &mut impl Write) -> eyre::Result<()> {
+    for (label, addr) in self.labels.iter() {
+      writeln!(writer, "-- {label}\t{addr}\t(0x{addr:x})")?;
+    }
+    self.environment.to_mif(writer)
+  }
+
+  /// Writes `addr: words ; source` for every source line, with labels
+  /// already resolved in the words, followed by the symbol table.
+  pub fn write_listing(&self, writer: &mut impl Write) -> eyre::Result<()> {
+    for line in self.listing.iter() {
+      let words = line.words.iter().map(|word| word.to_string());
+      writeln!(
+        writer,
+        "{:>5}: {:<40} ; {}",
+        line.addr,
+        words.collect::<Vec<_>>().join(" "),
+        line.source
+      )?;
+    }
+
+    writeln!(writer)?;
+    for (label, addr) in self.labels.iter() {
+      writeln!(writer, "{label}\t{addr}\t(0x{addr:x})")?;
+    }
+
+    Ok(())
+  }
+}
+
+/// Renders `words` as `mnemonic operands...`, or as the raw words when the
+/// opcode isn't a known instruction.
+pub fn disassemble(words: [i32; 4]) -> String {
+  let [opcode, ref operands @ ..] = words;
+  match INSTRUCTIONS.iter().find(|(_, op, _)| *op == opcode) {
+    Some((mnemonic, _, count)) => std::iter::once(mnemonic.to_string())
+      .chain(operands[..*count].iter().map(|operand| operand.to_string()))
+      .collect::<Vec<_>>()
+      .join(" "),
+    None => format!("?? {}", words.map(|word| word.to_string()).join(" ")),
+  }
+}
+
+/// Disassembles the instructions in `[iar - 8, iar + 12)`, keeping to
+/// `iar`'s alignment. Instructions that would start or end outside memory
+/// are left out, so the window shrinks at either edge.
+pub fn fault_context(
+  environment: &Environment,
+  iar: u32,
+) -> Vec<(u32, String)> {
+  (-2..3)
+    .filter_map(|offset: i64| {
+      let addr = u32::try_from(iar as i64 + 4 * offset).ok()?;
+      let words = environment.memory.get(addr as usize..addr as usize + 4)?;
+      Some((addr, disassemble(words.try_into().unwrap())))
+    })
+    .collect()
+}
+
+/// Reads a cycle cost table of one `mnemonic cost` pair per line. Listed
+/// instructions take the given number of cycles and the rest take one.
+pub fn parse_costs(text: &str) -> eyre::Result<[u64; OPCODE_COUNT]> {
+  let mut costs = [1; OPCODE_COUNT];
+  for (index, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+
+    let Some((mnemonic, cost)) = line.split_once(char::is_whitespace) else {
+      eyre::bail!("line {}: expected `mnemonic cost`", index + 1);
+    };
+    let Some((_, opcode, _)) =
+      INSTRUCTIONS.iter().find(|(name, ..)| *name == mnemonic)
+    else {
+      eyre::bail!("line {}: unknown instruction `{mnemonic}`", index + 1);
+    };
+    costs[*opcode as usize] = cost.trim().parse()?;
+  }
+  Ok(costs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn check_reports_every_error() {
+    let source = "\
+start\tadd 10 11 12
+\tad 10 11 12
+\tcp 10 nowhere
+\tsub 10 11
+\tcp 10 0x100000000
+start\thalt
+";
+    let diagnostics = check(source)
+      .into_iter()
+      .map(|diagnostic| diagnostic.to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(
+      diagnostics,
+      [
+        "line 2: unknown mnemonic `ad`",
+        "line 3: undefined label `nowhere`",
+        "line 4: `sub` takes 3 operands, found 2",
+        "line 5: operand `0x100000000` out of range",
+        "line 6: duplicate label `start`",
+      ]
+    );
+  }
+
+  #[test]
+  fn data_and_labels_still_assemble() {
+    let assembly = assemble("\thalt\nlist\tlist 7 -1\n").unwrap();
+    assert_eq!(assembly.environment.memory[4..7], [4, 7, -1]);
+  }
+
+  #[test]
+  fn assemble_into_leaves_the_rest_of_memory_alone() {
+    let mut env = Environment::default();
+    env.fill(crate::core::FillPattern::Constant(9));
+    let assembly =
+      assemble_into("loop\tbe loop 0 0\n\thalt\n", &mut env, 200).unwrap();
+
+    assert_eq!(assembly.environment.memory[200..204], [13, 200, 0, 0]);
+    assert_eq!(env.memory[200..208], [13, 200, 0, 0, 0, 0, 0, 0]);
+    assert!(env.memory[..199].iter().all(|&value| value == 9));
+    assert!(env.memory[208..].iter().all(|&value| value == 9));
+  }
+
+  #[test]
+  fn listing_shows_every_line_with_its_words() {
+    let source = "// spin\nloop\tbe loop 0 0\n\thalt\n";
+    let assembly = assemble_at(source, 8).unwrap();
+    let listing = assembly
+      .listing
+      .iter()
+      .map(|line| (line.addr, line.words.clone(), line.source.as_str()))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      listing,
+      [
+        (8, vec![], "// spin"),
+        (8, vec![13, 8, 0, 0], "loop\tbe loop 0 0"),
+        (12, vec![0, 0, 0, 0], "\thalt"),
+      ]
+    );
+
+    let mut text = Vec::new();
+    assembly.write_listing(&mut text).unwrap();
+    let text = String::from_utf8(text).unwrap();
+    let lines = text.lines().collect::<Vec<_>>();
+    assert!(lines[1].starts_with("    8: 13 8 0 0 "));
+    assert!(lines[1].ends_with(" ; loop\tbe loop 0 0"));
+    assert_eq!(lines[4], "loop\t8\t(0x8)");
+  }
+
+  #[test]
+  fn fault_context_shrinks_at_the_edges_of_memory() {
+    let mut env = Environment::default();
+    env.memory[..8].copy_from_slice(&[5, 10, 11, 0, 99, 1, 2, 3]);
+
+    let context = fault_context(&env, 4);
+    assert_eq!(
+      context,
+      [
+        (0, "cp 10 11".to_owned()),
+        (4, "?? 99 1 2 3".to_owned()),
+        (8, "halt".to_owned()),
+        (12, "halt".to_owned()),
+      ]
+    );
+
+    let end = MEMORY_SIZE as u32 - 4;
+    let addrs = fault_context(&env, end)
+      .into_iter()
+      .map(|(addr, _)| addr)
+      .collect::<Vec<_>>();
+    assert_eq!(addrs, [end - 8, end - 4, end]);
+  }
+
+  #[test]
+  fn cost_table_prices_each_instruction() {
+    let costs = parse_costs("mult 5\n\n  div 12\n").unwrap();
+    assert_eq!((costs[3], costs[4], costs[1]), (5, 12, 1));
+    assert!(parse_costs("mul 5").is_err());
+    assert!(parse_costs("mult").is_err());
+    assert!(parse_costs("mult -1").is_err());
+
+    let mut assembly = assemble("\tmult 20 21 22\n\tadd 20 20 20\n").unwrap();
+    let env = &mut assembly.environment;
+    env.cycle_costs = costs;
+    let outcome =
+      crate::headless::run_to_halt(env, &mut Default::default(), 10);
+    assert!(matches!(
+      outcome,
+      crate::headless::RunOutcome::Halted { steps: 2 }
+    ));
+    assert_eq!(env.cycles, 6);
+  }
+}
@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+use crate::core::{StepReport, MEMORY_SIZE};
+
+/// Instructions kept in the IAR history.
+pub const HISTORY_LEN: usize = 8;
+/// Frames kept on the shadow call stack; deeper calls forget the outermost.
+pub const MAX_CALL_DEPTH: usize = 64;
+/// Distinct memory cells remembered as recently written.
+pub const RECENT_WRITES: usize = 8;
+
+/// How a program reached a fault: the call sites still waiting on a `ret`,
+/// outermost first, and the addresses of the last few instructions run,
+/// oldest first and ending at the faulting one.
+#[derive(Debug, Clone, Default)]
+pub struct Backtrace {
+  pub calls: Vec<u32>,
+  pub history: Vec<u32>,
+}
+
+impl std::fmt::Display for Backtrace {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let join = |addrs: &[u32]| {
+      addrs
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(" > ")
+    };
+    write!(f, "calls: {}", join(&self.calls))?;
+    write!(f, "\nrecent: {}", join(&self.history))
+  }
+}
+
+/// Follows calls, returns and interrupts alongside the executor so a fault
+/// can be explained without re-running the program.
+#[derive(Default)]
+pub struct Tracer {
+  calls: Vec<u32>,
+  history: VecDeque<u32>,
+  /// Most recent first, each cell at most once.
+  writes: VecDeque<u32>,
+}
+
+impl Tracer {
+  /// Records a completed step that started at `iar`, where `opcode` was
+  /// fetched before the step could overwrite it.
+  pub fn record(&mut self, iar: u32, opcode: i32, report: &StepReport) {
+    if self.history.len() == HISTORY_LEN {
+      self.history.pop_front();
+    }
+    self.history.push_back(iar);
+
+    // an interrupt returns through its link cell just like a call
+    if report.interrupt.is_some() || opcode == 16 {
+      if self.calls.len() == MAX_CALL_DEPTH {
+        self.calls.remove(0);
+      }
+      self.calls.push(iar);
+    } else if opcode == 17 {
+      self.calls.pop();
+    }
+
+    if let Some(addr) = report.changed.filter(|addr| *addr < MEMORY_SIZE as u32)
+    {
+      self.writes.retain(|write| *write != addr);
+      self.writes.push_front(addr);
+      self.writes.truncate(RECENT_WRITES);
+    }
+  }
+
+  /// The memory cells written most recently, newest first.
+  pub fn recent_writes(&self) -> Vec<u32> {
+    self.writes.iter().copied().collect()
+  }
+
+  /// The backtrace for a fault at `iar`.
+  pub fn backtrace(&self, iar: u32) -> Backtrace {
+    let mut history = self.history.iter().copied().collect::<Vec<_>>();
+    if history.len() == HISTORY_LEN {
+      history.remove(0);
+    }
+    history.push(iar);
+
+    Backtrace {
+      calls: self.calls.clone(),
+      history,
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.calls.clear();
+    self.history.clear();
+    self.writes.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn backtrace_follows_calls_and_recent_steps() {
+    let mut tracer = Tracer::default();
+    let wrote = |addr| StepReport {
+      changed: Some(addr),
+      ..Default::default()
+    };
+    tracer.record(0, 16, &wrote(50));
+    tracer.record(40, 16, &wrote(51));
+    tracer.record(60, 1, &wrote(50));
+    tracer.record(64, 17, &StepReport::default());
+    for iar in (44..60).step_by(4) {
+      tracer.record(iar, 5, &wrote(0x80000080));
+    }
+
+    let backtrace = tracer.backtrace(64);
+    assert_eq!(backtrace.calls, [0]);
+    // the history keeps the newest steps, ending at the faulting one
+    assert_eq!(backtrace.history, [40, 60, 64, 44, 48, 52, 56, 64]);
+    assert_eq!(
+      backtrace.to_string(),
+      "calls: 0\nrecent: 40 > 60 > 64 > 44 > 48 > 52 > 56 > 64"
+    );
+    // device registers aren't remembered, and a rewrite moves to the front
+    assert_eq!(tracer.recent_writes(), [50, 51]);
+
+    tracer.reset();
+    assert_eq!(tracer.backtrace(8).history, [8]);
+    assert!(tracer.recent_writes().is_empty());
+  }
+}
@@ -1,4 +1,5 @@
-use std::io::Read;
+use std::cell::Cell;
+use std::io::{Read, Write};
 
 use color_eyre::eyre;
 use regex::Regex;
@@ -7,61 +8,801 @@ use regex::Regex;
 pub enum StepFatal {
   Halted,
   AlreadyPoisoned,
-  InvalidInstruction { instr: i32 },
-  InvalidIAR { iar: u32 },
-  InvalidIndex { index: u32 },
-  DeviceFailure { error: crate::devices::DeviceError },
+  InvalidInstruction {
+    instr: i32,
+  },
+  InvalidIAR {
+    iar: u32,
+  },
+  InvalidIndex {
+    index: u32,
+  },
+  /// A negative address that isn't a device register, reported as written
+  /// rather than reinterpreted as an unsigned address.
+  NegativeAddress {
+    operand: i32,
+  },
+  /// A call's link slot is a device register. Links must be in memory,
+  /// where the matching `ret` can read them back.
+  DeviceLink {
+    register: u32,
+  },
+  /// A byte or halfword access selected a sub-word the word doesn't have.
+  InvalidSubword {
+    offset: i32,
+  },
+  /// An array op's base plus offset doesn't fit in an address.
+  IndexOverflow {
+    base: i32,
+    offset: i32,
+  },
+  DeviceFailure {
+    error: crate::devices::DeviceError,
+    register: u32,
+  },
   DivisionByZero,
+  /// A signed add, sub, mul or div overflowed under
+  /// `ArithmeticMode::Checked`.
+  Overflow {
+    instr: i32,
+  },
+  UninitializedHalt {
+    iar: u32,
+  },
+  /// A device raised an interrupt whose vector holds no handler.
+  UnhandledInterrupt {
+    irq: u32,
+  },
+  /// `step` panicked. The environment stays poisoned since the panic may
+  /// have left it half-updated.
+  InternalError {
+    message: String,
+  },
+}
+
+impl std::fmt::Display for StepFatal {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      StepFatal::Halted => write!(f, "halted"),
+      StepFatal::AlreadyPoisoned => {
+        write!(f, "environment poisoned by an earlier fault")
+      }
+      StepFatal::InvalidInstruction { instr } => {
+        write!(f, "invalid instruction {instr}")
+      }
+      StepFatal::InvalidIAR { iar } => write!(f, "IAR {iar} out of range"),
+      StepFatal::InvalidIndex { index } => {
+        write!(f, "address {index} is not mapped")
+      }
+      StepFatal::NegativeAddress { operand } => {
+        write!(f, "negative address {operand}")
+      }
+      StepFatal::DeviceLink { register } => {
+        write!(f, "call links into device register 0x{register:08x}")
+      }
+      StepFatal::InvalidSubword { offset } => {
+        write!(f, "sub-word offset {offset} out of range")
+      }
+      StepFatal::IndexOverflow { base, offset } => {
+        write!(f, "index {base} + {offset} overflows")
+      }
+      StepFatal::DeviceFailure { error, register } => {
+        write!(f, "register 0x{register:08x} {error}")
+      }
+      StepFatal::DivisionByZero => write!(f, "division by zero"),
+      StepFatal::Overflow { instr } => {
+        write!(f, "arithmetic overflow in instruction {instr}")
+      }
+      StepFatal::UninitializedHalt { iar } => {
+        write!(f, "ran into unwritten memory at {iar} without a HALT")
+      }
+      StepFatal::UnhandledInterrupt { irq } => {
+        write!(f, "interrupt {irq} raised with no handler installed")
+      }
+      StepFatal::InternalError { message } => {
+        write!(f, "internal error: {message}")
+      }
+    }
+  }
 }
 
 pub const MEMORY_SIZE: usize = 16384;
+/// First address routed to devices. Everything from `MEMORY_SIZE` up to here
+/// is unmapped.
+pub const DEVICE_BASE: u32 = 0x80000000;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"C100";
+const SNAPSHOT_VERSION: u32 = 5;
+
+/// One past the highest opcode, for tables indexed by opcode.
+pub const OPCODE_COUNT: usize = 31;
+
+/// Cycles `cpwait` (20) keeps retrying a busy device for before faulting.
+pub const DEVICE_READ_RETRIES: u64 = 64;
+
+/// Number of interrupt lines a device can raise.
+pub const INTERRUPT_COUNT: usize = 8;
+/// Interrupt `n` vectors through the two words at
+/// `INTERRUPT_VECTOR_BASE + 2 * n`: the first holds the handler address (0
+/// for none), the second receives the interrupted IAR so the handler can
+/// `ret` through it.
+pub const INTERRUPT_VECTOR_BASE: usize = MEMORY_SIZE - 2 * INTERRUPT_COUNT;
+
+/// Most program arguments a run can be given.
+pub const ARGS_MAX: usize = 15;
+/// Program arguments sit just below the interrupt vectors: the count at
+/// `ARGS_BASE`, then the arguments themselves from `ARGS_BASE + 1`.
+pub const ARGS_BASE: usize = INTERRUPT_VECTOR_BASE - ARGS_MAX - 1;
 
 #[derive(Default)]
 pub struct StepReport {
   pub changed: Option<u32>,
-  pub redraw: bool,
+  /// The interrupt taken in place of an instruction, if any.
+  pub interrupt: Option<u32>,
+  /// The device written this step, if it asked for a redraw.
+  pub redraw: Option<crate::devices::DeviceKind>,
+  /// Addresses read this step, in order. No instruction reads more than
+  /// three.
+  pub reads: [Option<u32>; 3],
+  /// What the `changed` cell held before this step, for undoing it. `None`
+  /// when it's a device register.
+  pub previous: Option<i32>,
+  /// The device register the instruction is still waiting on. Nothing else
+  /// in the report is set, and the instruction runs again next step.
+  pub stalled: Option<u32>,
+}
+
+impl StepReport {
+  fn note_read(&mut self, addr: u32) {
+    if let Some(slot) = self.reads.iter_mut().find(|slot| slot.is_none()) {
+      *slot = Some(addr);
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FillPattern {
+  /// Each cell holds its own address.
+  Incrementing,
+  Constant(i32),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endianness {
+  #[default]
+  Little,
+  Big,
+}
+
+/// Layout of a raw memory dump: one 32-bit word per cell from address 0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DumpFormat {
+  pub endianness: Endianness,
+  /// Stop after the highest nonzero cell instead of dumping all of memory.
+  pub trim: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellDiff {
+  pub addr: u32,
+  pub left: i32,
+  pub right: i32,
+}
+
+/// How add (1), sub (2), mul (3) and div (4) treat a signed result that
+/// doesn't fit. adc and sbb always wrap, since the carry carries what's lost,
+/// and rem can't overflow.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArithmeticMode {
+  /// add, sub and div wrap while mul saturates, as the ISA always has. The
+  /// default so existing programs keep their results; the other modes treat
+  /// every instruction alike.
+  #[default]
+  Legacy,
+  Wrapping,
+  Saturating,
+  /// Overflow faults with `StepFatal::Overflow`.
+  Checked,
+}
+
+impl std::str::FromStr for ArithmeticMode {
+  type Err = eyre::Report;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "legacy" => Ok(ArithmeticMode::Legacy),
+      "wrapping" => Ok(ArithmeticMode::Wrapping),
+      "saturating" => Ok(ArithmeticMode::Saturating),
+      "checked" => Ok(ArithmeticMode::Checked),
+      _ => eyre::bail!(
+        "unknown arithmetic mode {s:?}, expected legacy, wrapping, \
+        saturating or checked"
+      ),
+    }
+  }
+}
+
+impl std::fmt::Display for ArithmeticMode {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ArithmeticMode::Legacy => write!(f, "legacy"),
+      ArithmeticMode::Wrapping => write!(f, "wrapping"),
+      ArithmeticMode::Saturating => write!(f, "saturating"),
+      ArithmeticMode::Checked => write!(f, "checked"),
+    }
+  }
+}
+
+impl ArithmeticMode {
+  /// The mode after this one, wrapping round, for cycling through them.
+  pub fn next(self) -> Self {
+    match self {
+      ArithmeticMode::Legacy => ArithmeticMode::Wrapping,
+      ArithmeticMode::Wrapping => ArithmeticMode::Saturating,
+      ArithmeticMode::Saturating => ArithmeticMode::Checked,
+      ArithmeticMode::Checked => ArithmeticMode::Legacy,
+    }
+  }
+
+  /// Applies add (1), sub (2), mul (3) or div (4) to `a` and `b` under this
+  /// mode. Division by zero is for the caller to rule out.
+  fn apply(self, instruction: i32, a: i32, b: i32) -> Result<i32, StepFatal> {
+    let mode = match (self, instruction) {
+      (ArithmeticMode::Legacy, 3) => ArithmeticMode::Saturating,
+      (ArithmeticMode::Legacy, _) => ArithmeticMode::Wrapping,
+      (mode, _) => mode,
+    };
+
+    Ok(match (mode, instruction) {
+      (ArithmeticMode::Wrapping, 1) => a.wrapping_add(b),
+      (ArithmeticMode::Wrapping, 2) => a.wrapping_sub(b),
+      (ArithmeticMode::Wrapping, 3) => a.wrapping_mul(b),
+      (ArithmeticMode::Wrapping, 4) => a.wrapping_div(b),
+      (ArithmeticMode::Saturating, 1) => a.saturating_add(b),
+      (ArithmeticMode::Saturating, 2) => a.saturating_sub(b),
+      (ArithmeticMode::Saturating, 3) => a.saturating_mul(b),
+      (ArithmeticMode::Saturating, 4) => a.saturating_div(b),
+      (ArithmeticMode::Checked, _) => match instruction {
+        1 => a.checked_add(b),
+        2 => a.checked_sub(b),
+        3 => a.checked_mul(b),
+        4 => a.checked_div(b),
+        _ => unreachable!(),
+      }
+      .ok_or(StepFatal::Overflow { instr: instruction })?,
+      _ => unreachable!(),
+    })
+  }
 }
 
 #[derive(Clone)]
 pub struct Environment {
   pub iar: u32,
   pub memory: Box<[i32; 16384]>,
+  /// Cells written by the loader or the program since reset.
+  pub initialized: Box<[bool; MEMORY_SIZE]>,
+  /// Treat a HALT fetched from never-written memory as a fault rather than
+  /// an intentional stop.
+  pub strict_halt: bool,
+  /// Unsigned carry out of the last add/adc, or borrow out of the last
+  /// sub/sbb. adc (18) and sbb (19) also consume it as their carry/borrow in.
+  pub carry: bool,
+  /// Set when add, sub, adc or sbb overflows as signed, and left set until
+  /// bo (30) branches on it or the machine is reset.
+  pub overflow: bool,
+  pub arithmetic: ArithmeticMode,
+  /// The interrupt whose handler is running. Others stay pending until it
+  /// `ret`s through its vector's return cell.
+  pub handling: Option<u32>,
+  /// Cycles spent so far, each instruction costing its entry in
+  /// `cycle_costs` and each interrupt entry costing one.
+  pub cycles: u64,
+  /// Instructions run since reset. Unlike `cycles`, interrupt entries don't
+  /// count and every instruction counts once whatever it costs.
+  pub steps: u64,
+  /// Cycles each opcode takes; all 1 by default, so `cycles` counts steps.
+  pub cycle_costs: [u64; OPCODE_COUNT],
   poison: bool,
 }
 
+impl Environment {
+  /// Returns to address 0, zeroes the cycle count, clears the flags and
+  /// lifts the poison left by a fault or halt, so the program can run again
+  /// from the top with memory as it stands.
+  pub fn reset(&mut self) {
+    self.iar = 0;
+    self.cycles = 0;
+    self.steps = 0;
+    self.carry = false;
+    self.overflow = false;
+    self.handling = None;
+    self.poison = false;
+  }
+
+  /// The state a step is about to change, besides the cell it writes, which
+  /// `Checkpoint::complete` fills in afterwards.
+  pub fn checkpoint(&self) -> Checkpoint {
+    Checkpoint {
+      iar: self.iar,
+      carry: self.carry,
+      overflow: self.overflow,
+      handling: self.handling,
+      cycles: self.cycles,
+      steps: self.steps,
+      write: None,
+    }
+  }
+
+  /// Puts back everything `checkpoint` recorded, lifting any poison since
+  /// the machine is back to before whatever caused it.
+  pub fn undo(&mut self, checkpoint: &Checkpoint) {
+    self.iar = checkpoint.iar;
+    self.carry = checkpoint.carry;
+    self.overflow = checkpoint.overflow;
+    self.handling = checkpoint.handling;
+    self.cycles = checkpoint.cycles;
+    self.steps = checkpoint.steps;
+    if let Some((addr, value)) = checkpoint.write {
+      self.memory[addr as usize] = value;
+    }
+    self.poison = false;
+  }
+}
+
+/// Enough of an environment to undo one step.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkpoint {
+  iar: u32,
+  carry: bool,
+  overflow: bool,
+  handling: Option<u32>,
+  cycles: u64,
+  steps: u64,
+  /// The cell the step overwrote and what it held.
+  write: Option<(u32, i32)>,
+}
+
+impl Checkpoint {
+  /// Adds the cell the step wrote, or returns `None` if it wrote a device
+  /// register, which can't be put back.
+  pub fn complete(mut self, report: &StepReport) -> Option<Checkpoint> {
+    if let Some(addr) = report.changed {
+      self.write = Some((addr, report.previous?));
+    }
+    Some(self)
+  }
+
+  /// The memory cell undoing this puts back, if any.
+  pub fn restores(&self) -> Option<u32> {
+    self.write.map(|(addr, _)| addr)
+  }
+}
+
 impl Default for Environment {
   fn default() -> Self {
     Environment {
       iar: 0,
       memory: Box::new([0; MEMORY_SIZE]),
+      initialized: Box::new([false; MEMORY_SIZE]),
+      strict_halt: false,
+      carry: false,
+      overflow: false,
+      arithmetic: ArithmeticMode::default(),
+      handling: None,
+      cycles: 0,
+      steps: 0,
+      cycle_costs: [1; OPCODE_COUNT],
       poison: false,
     }
   }
 }
 
+/// The base a MIF radix directive names.
+fn mif_radix(name: &str) -> Option<u32> {
+  match name {
+    "BIN" => Some(2),
+    "OCT" => Some(8),
+    "DEC" | "UNS" => Some(10),
+    "HEX" => Some(16),
+    _ => None,
+  }
+}
+
+/// A data word in `radix`. Anything that fits in 32 bits signed or unsigned
+/// is accepted, so `FFFFFFFF` in hex reads as -1.
+fn mif_data(token: &str, radix: u32) -> Option<i32> {
+  let value = i64::from_str_radix(token, radix).ok()?;
+  if value < i32::MIN as i64 || value > u32::MAX as i64 {
+    return None;
+  }
+  Some(value as i32)
+}
+
 impl Environment {
+  /// Reads the content of a MIF image: `addr : value;` for one cell, or
+  /// `[first..last] : value;` for a run of cells holding the same value.
+  /// Numbers are read in the radix the `ADDRESS_RADIX` and `DATA_RADIX`
+  /// directives declare, decimal if they're missing. `%...%` and `--`
+  /// comments are ignored. Errors name the 1-based line they're on.
   pub fn parse(reader: &mut impl Read) -> eyre::Result<Self> {
     let mut env = Environment::default();
     let mut buf = String::new();
     reader.read_to_string(&mut buf)?;
+    // `%...%` comments may span lines, so keep their newlines to leave line
+    // numbers as they were
+    let buf = Regex::new(r"%[^%]*%").unwrap().replace_all(
+      &buf,
+      |captures: &regex::Captures| {
+        captures[0].matches('\n').collect::<String>()
+      },
+    );
 
-    let regex = Regex::new(r"\s*\d+\s*:\s*(-?\d+);").unwrap();
-    for (index, val) in regex
-      .captures_iter(&buf)
-      .map(|s| s.get(1).unwrap().as_str().parse::<i32>())
-      .enumerate()
-    {
-      env.memory[index] = val?;
+    let mut address_radix = 10;
+    let mut data_radix = 10;
+    for (index, line) in buf.lines().enumerate() {
+      let number = index + 1;
+      let line = line.split("--").next().unwrap_or_default();
+      let mut statements = line.split(';');
+      let rest = statements.next_back().unwrap_or_default().trim();
+      if rest.contains(':') {
+        eyre::bail!("line {number}: missing `;` after {rest:?}");
+      }
+
+      for statement in statements {
+        if let Some((name, value)) = statement.split_once('=') {
+          let radix = match name.trim() {
+            "ADDRESS_RADIX" => &mut address_radix,
+            "DATA_RADIX" => &mut data_radix,
+            _ => continue,
+          };
+          let value = value.trim();
+          *radix = mif_radix(value).ok_or_else(|| {
+            eyre::eyre!("line {number}: unknown radix {value:?}")
+          })?;
+          continue;
+        }
+        let Some((cells, value)) = statement.split_once(':') else {
+          continue;
+        };
+
+        let address = |token: &str| {
+          let token = token.trim();
+          usize::from_str_radix(token, address_radix).map_err(|_| {
+            eyre::eyre!("line {number}: invalid address {token:?}")
+          })
+        };
+        let cells = cells.trim();
+        let (first, last) = match cells
+          .strip_prefix('[')
+          .and_then(|range| range.strip_suffix(']'))
+        {
+          Some(range) => {
+            let Some((first, last)) = range.split_once("..") else {
+              eyre::bail!("line {number}: invalid range {cells:?}");
+            };
+            (address(first)?, address(last)?)
+          }
+          None => (address(cells)?, address(cells)?),
+        };
+        let value = value.trim();
+        let value = mif_data(value, data_radix).ok_or_else(|| {
+          eyre::eyre!("line {number}: invalid data value {value:?}")
+        })?;
+
+        if first > last || last >= MEMORY_SIZE {
+          eyre::bail!(
+            "line {number}: cells {first}..{last} are not in memory: {:?}",
+            statement.trim()
+          );
+        }
+        env.memory[first..=last].fill(value);
+        env.initialized[first..=last].fill(true);
+      }
+    }
+
+    Ok(env)
+  }
+
+  /// Places `args` in the argument region, overwriting whatever the program
+  /// image had there.
+  pub fn set_args(&mut self, args: &[i32]) -> eyre::Result<()> {
+    if args.len() > ARGS_MAX {
+      eyre::bail!("at most {ARGS_MAX} program arguments, got {}", args.len());
+    }
+
+    self.memory[ARGS_BASE] = args.len() as i32;
+    self.memory[ARGS_BASE + 1..][..args.len()].copy_from_slice(args);
+    self.initialized[ARGS_BASE..=ARGS_BASE + args.len()].fill(true);
+    Ok(())
+  }
+
+  /// Copies every written cell of `segment` into this environment, shifted
+  /// up by `base`. Cells already written here are overwritten, and their
+  /// addresses are returned so the caller can warn about the overlap.
+  pub fn load_segment(
+    &mut self,
+    segment: &Environment,
+    base: u32,
+  ) -> eyre::Result<Vec<u32>> {
+    let mut overlaps = Vec::new();
+    for (addr, value) in segment.memory.iter().enumerate() {
+      if !segment.initialized[addr] {
+        continue;
+      }
+
+      let target = base as usize + addr;
+      if target >= MEMORY_SIZE {
+        eyre::bail!("segment at {base} runs past the end of memory");
+      }
+      if self.initialized[target] {
+        overlaps.push(target as u32);
+      }
+      self.memory[target] = *value;
+      self.initialized[target] = true;
+    }
+
+    Ok(overlaps)
+  }
+
+  /// Writes the IAR, flags, cycle and step counts and all of memory in a
+  /// compact little-endian layout, behind a magic header and format version.
+  pub fn save(&self, writer: &mut impl Write) -> eyre::Result<()> {
+    writer.write_all(SNAPSHOT_MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    writer.write_all(&self.iar.to_le_bytes())?;
+    // the interrupt being handled, if any, is flagged in bit 2 with its
+    // number from bit 3
+    let handling = self.handling.map_or(0, |irq| 4 | irq << 3);
+    let flags = self.carry as u32 | (self.overflow as u32) << 1 | handling;
+    writer.write_all(&flags.to_le_bytes())?;
+    writer.write_all(&self.cycles.to_le_bytes())?;
+    writer.write_all(&self.steps.to_le_bytes())?;
+    for value in self.memory.iter() {
+      writer.write_all(&value.to_le_bytes())?;
+    }
+
+    Ok(())
+  }
+
+  /// Takes the run settings, which snapshots don't record, from `other`: the
+  /// arithmetic mode, cycle costs and strict halting.
+  pub fn keep_settings(&mut self, other: &Environment) {
+    self.arithmetic = other.arithmetic;
+    self.cycle_costs = other.cycle_costs;
+    self.strict_halt = other.strict_halt;
+  }
+
+  /// Reads a snapshot written by `save`. The result is never poisoned,
+  /// whatever the saved machine was doing, and a short or foreign file is an
+  /// error rather than a partly loaded environment.
+  pub fn load(reader: &mut impl Read) -> eyre::Result<Self> {
+    let mut env = Environment::default();
+    let mut word = [0; 4];
+
+    reader.read_exact(&mut word)?;
+    if &word != SNAPSHOT_MAGIC {
+      eyre::bail!("not an environment snapshot");
+    }
+
+    reader.read_exact(&mut word)?;
+    let version = u32::from_le_bytes(word);
+    if version != SNAPSHOT_VERSION {
+      eyre::bail!("unsupported snapshot version {version}");
+    }
+
+    reader.read_exact(&mut word)?;
+    env.iar = u32::from_le_bytes(word);
+    reader.read_exact(&mut word)?;
+    let flags = u32::from_le_bytes(word);
+    env.carry = flags & 1 != 0;
+    env.overflow = flags & 2 != 0;
+    env.handling = (flags & 4 != 0).then_some(flags >> 3);
+    let mut cycles = [0; 8];
+    reader.read_exact(&mut cycles)?;
+    env.cycles = u64::from_le_bytes(cycles);
+    reader.read_exact(&mut cycles)?;
+    env.steps = u64::from_le_bytes(cycles);
+    for value in env.memory.iter_mut() {
+      reader.read_exact(&mut word)?;
+      *value = i32::from_le_bytes(word);
     }
+    // snapshots don't record which cells were written, so trust all of them
+    env.initialized.fill(true);
 
     Ok(env)
   }
+
+  /// Writes memory as a MIF image, up to the last cell that was ever
+  /// written, with runs of equal cells collapsed into ranges.
+  pub fn to_mif(&self, writer: &mut impl Write) -> eyre::Result<()> {
+    let len = self
+      .initialized
+      .iter()
+      .rposition(|written| *written)
+      .map_or(0, |last| last + 1);
+
+    writeln!(writer, "DEPTH = {MEMORY_SIZE};")?;
+    writeln!(writer, "WIDTH = 32;")?;
+    writeln!(writer, "ADDRESS_RADIX = DEC;")?;
+    writeln!(writer, "DATA_RADIX = DEC;")?;
+    writeln!(writer, "CONTENT")?;
+    writeln!(writer, "BEGIN")?;
+    let mut addr = 0;
+    for run in self.memory[..len].chunk_by(|a, b| a == b) {
+      let value = run[0];
+      match run.len() {
+        1 => writeln!(writer, "\t{addr}\t:\t{value};")?,
+        len => writeln!(writer, "\t[{addr}..{}]\t:\t{value};", addr + len - 1)?,
+      }
+      addr += run.len();
+    }
+    writeln!(writer, "END;")?;
+
+    Ok(())
+  }
+
+  /// Writes memory as bare words with no header, for tools that want a flat
+  /// image.
+  pub fn dump(
+    &self,
+    writer: &mut impl Write,
+    format: DumpFormat,
+  ) -> eyre::Result<()> {
+    let len = if format.trim {
+      self
+        .memory
+        .iter()
+        .rposition(|value| *value != 0)
+        .map_or(0, |last| last + 1)
+    } else {
+      MEMORY_SIZE
+    };
+
+    self.dump_range(writer, format, 0, len)
+  }
+
+  /// Dumps just the cells in `[start, end)`, ignoring `format.trim`.
+  pub fn dump_range(
+    &self,
+    writer: &mut impl Write,
+    format: DumpFormat,
+    start: usize,
+    end: usize,
+  ) -> eyre::Result<()> {
+    let Some(cells) = self.memory.get(start..end) else {
+      eyre::bail!("range {start}..{end} is outside memory");
+    };
+
+    for value in cells.iter() {
+      writer.write_all(&match format.endianness {
+        Endianness::Little => value.to_le_bytes(),
+        Endianness::Big => value.to_be_bytes(),
+      })?;
+    }
+
+    Ok(())
+  }
+
+  /// A new environment holding only the cells in `[start, end)`, moved down
+  /// to address 0 and all marked written, for saving a table on its own.
+  pub fn extract(&self, start: usize, end: usize) -> eyre::Result<Self> {
+    let Some(cells) = self.memory.get(start..end) else {
+      eyre::bail!("range {start}..{end} is outside memory");
+    };
+
+    let mut env = Environment::default();
+    env.memory[..cells.len()].copy_from_slice(cells);
+    env.initialized[..cells.len()].fill(true);
+    Ok(env)
+  }
+
+  /// Reads a dump written by `dump`. Trimmed dumps leave the remaining cells
+  /// zeroed and unwritten.
+  pub fn undump(
+    reader: &mut impl Read,
+    format: DumpFormat,
+  ) -> eyre::Result<Self> {
+    let mut env = Environment::default();
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+
+    if data.len() % 4 != 0 {
+      eyre::bail!("dump is not a whole number of words");
+    }
+    if data.len() / 4 > MEMORY_SIZE {
+      eyre::bail!("dump is larger than memory");
+    }
+
+    for (addr, word) in data.chunks_exact(4).enumerate() {
+      let word = word.try_into().unwrap();
+      env.memory[addr] = match format.endianness {
+        Endianness::Little => i32::from_le_bytes(word),
+        Endianness::Big => i32::from_be_bytes(word),
+      };
+      env.initialized[addr] = true;
+    }
+
+    Ok(env)
+  }
+
+  /// Overwrites all of memory with `pattern`, for exercising the memory view
+  /// and addressing without a program.
+  pub fn fill(&mut self, pattern: FillPattern) {
+    for (addr, value) in self.memory.iter_mut().enumerate() {
+      *value = match pattern {
+        FillPattern::Incrementing => addr as i32,
+        FillPattern::Constant(constant) => constant,
+      };
+    }
+    self.initialized.fill(true);
+  }
+
+  /// Addresses whose value agrees with `value` in every bit set in `mask`, at
+  /// most `limit` of them.
+  pub fn find(&self, value: i32, mask: i32, limit: usize) -> Vec<u32> {
+    self
+      .memory
+      .iter()
+      .enumerate()
+      .filter(|(_, cell)| (*cell ^ value) & mask == 0)
+      .map(|(addr, _)| addr as u32)
+      .take(limit)
+      .collect()
+  }
+
+  /// Every memory cell whose value differs between `self` (left) and `other`
+  /// (right), in address order.
+  pub fn diff(&self, other: &Environment) -> Vec<CellDiff> {
+    self
+      .memory
+      .iter()
+      .zip(other.memory.iter())
+      .enumerate()
+      .filter(|(_, (left, right))| left != right)
+      .map(|(addr, (&left, &right))| CellDiff {
+        addr: addr as u32,
+        left,
+        right,
+      })
+      .collect()
+  }
 }
 
-//#[tracing::instrument(skip(environment, device_array))]
+/// Runs the instruction at IAR, or takes a pending interrupt instead.
+///
+/// A device that isn't ready yet stalls the instruction rather than faulting
+/// it: the environment is left as it was but for a cycle spent waiting, and
+/// the same instruction is tried again next step. Registers are written
+/// last, so a stalled instruction has changed nothing, though any device it
+/// read before stalling is read again.
 pub fn step(
   environment: &mut Environment,
   device_array: &mut crate::devices::DeviceArray,
+) -> Result<StepReport, StepFatal> {
+  let checkpoint = environment.checkpoint();
+  match execute(environment, device_array) {
+    Err(StepFatal::DeviceFailure {
+      error: crate::devices::DeviceError::Stalled,
+      register,
+    }) => {
+      environment.undo(&checkpoint);
+      environment.cycles += 1;
+      Ok(StepReport {
+        stalled: Some(register),
+        ..Default::default()
+      })
+    }
+    result => {
+      device_array.complete_accesses();
+      result
+    }
+  }
+}
+
+//#[tracing::instrument(skip(environment, device_array))]
+fn execute(
+  environment: &mut Environment,
+  device_array: &mut crate::devices::DeviceArray,
 ) -> Result<StepReport, StepFatal> {
   let mut report = StepReport::default();
 
@@ -70,10 +811,36 @@ pub fn step(
     return Err(StepFatal::AlreadyPoisoned);
   }
 
+  device_array.tick(environment.cycles);
+
+  // a pending interrupt takes the place of the next instruction, unless a
+  // handler is already running
+  let pending = match environment.handling {
+    None => device_array.take_interrupt(),
+    Some(_) => None,
+  };
+  if let Some(irq) = pending {
+    let vector = INTERRUPT_VECTOR_BASE + 2 * irq as usize;
+    // jumping through an empty vector would restart the program
+    if environment.memory[vector] == 0 {
+      environment.poison = true;
+      return Err(StepFatal::UnhandledInterrupt { irq });
+    }
+    report.previous = Some(environment.memory[vector + 1]);
+    environment.memory[vector + 1] = environment.iar as i32;
+    environment.initialized[vector + 1] = true;
+    environment.iar = environment.memory[vector] as u32;
+    report.changed = Some(vector as u32 + 1);
+    report.interrupt = Some(irq);
+    environment.handling = Some(irq);
+    environment.cycles += 1;
+    return Ok(report);
+  }
+
   // if we make it to the end without returning an error, we'll turn this off
   environment.poison = true;
 
-  if environment.iar >= MEMORY_SIZE as u32 - 4 {
+  if environment.iar > MEMORY_SIZE as u32 - 4 {
     return Err(StepFatal::InvalidIAR {
       iar: environment.iar,
     });
@@ -96,44 +863,76 @@ pub fn step(
   // );
 
   match instruction {
+    0 if environment.strict_halt
+      && !environment.initialized[environment.iar as usize] =>
+    {
+      return Err(StepFatal::UninitializedHalt {
+        iar: environment.iar,
+      })
+    }
     0 => return Err(StepFatal::Halted),
-    1 | 2 | 3 | 4 | 6 | 7 | 9 | 10 => {
+    1 | 2 | 3 | 4 | 6 | 7 | 9 | 10 | 18 | 19 | 28 | 29 => {
       report.changed = Some(arg1 as u32);
-      let arg2v = get_mem(arg2 as u32, environment, device_array)
-        .ok_or(StepFatal::InvalidIndex { index: arg2 as u32 })?
-        .map_err(|error| StepFatal::DeviceFailure { error })?;
-      let arg3v = get_mem(arg3 as u32, environment, device_array)
-        .ok_or(StepFatal::InvalidIndex { index: arg3 as u32 })?
-        .map_err(|error| StepFatal::DeviceFailure { error })?;
+      let arg2v =
+        read_mem(arg2 as u32, environment, device_array, &mut report)?;
+      let arg3v =
+        read_mem(arg3 as u32, environment, device_array, &mut report)?;
 
       let val = match instruction {
-        1 => arg2v.wrapping_add(arg3v),
-        2 => arg2v.wrapping_sub(arg3v),
-        3 => arg2v.saturating_mul(arg3v),
-        4 => {
+        1 | 18 => {
+          let carry_in = instruction == 18 && environment.carry;
+          let sum = arg2v as u32 as u64 + arg3v as u32 as u64 + carry_in as u64;
+          environment.carry = sum > u32::MAX as u64;
+          let exact = arg2v as i64 + arg3v as i64 + carry_in as i64;
+          environment.overflow |= exact != exact as i32 as i64;
+          if instruction == 1 {
+            environment.arithmetic.apply(1, arg2v, arg3v)?
+          } else {
+            sum as u32 as i32
+          }
+        }
+        2 | 19 => {
+          let borrow_in = instruction == 19 && environment.carry;
+          let minuend = arg2v as u32 as u64;
+          let subtrahend = arg3v as u32 as u64 + borrow_in as u64;
+          environment.carry = subtrahend > minuend;
+          let exact = arg2v as i64 - arg3v as i64 - borrow_in as i64;
+          environment.overflow |= exact != exact as i32 as i64;
+          if instruction == 2 {
+            environment.arithmetic.apply(2, arg2v, arg3v)?
+          } else {
+            minuend.wrapping_sub(subtrahend) as u32 as i32
+          }
+        }
+        3 => environment.arithmetic.apply(3, arg2v, arg3v)?,
+        4 | 28 => {
           if arg3v == 0 {
             return Err(StepFatal::DivisionByZero);
+          } else if instruction == 4 {
+            environment.arithmetic.apply(4, arg2v, arg3v)?
           } else {
-            arg2v.wrapping_div(arg3v)
+            // takes the sign of the dividend, like div truncating
+            arg2v.wrapping_rem(arg3v)
           }
         }
         6 => arg2v & arg3v,
         7 => arg2v | arg3v,
-        9 => arg2v << arg3v,
-        10 => arg2v >> arg3v,
+        // shift counts are taken mod 32; sr (10) sign-extends and srl (29)
+        // shifts in zeroes
+        9 => arg2v.wrapping_shl(arg3v as u32),
+        10 => arg2v.wrapping_shr(arg3v as u32),
+        29 => (arg2v as u32).wrapping_shr(arg3v as u32) as i32,
         _ => unreachable!(),
       };
 
-      report.redraw = set_mem(arg1 as u32, val, environment, device_array)
-        .ok_or(StepFatal::InvalidIndex { index: arg1 as u32 })?
-        .map_err(|error| StepFatal::DeviceFailure { error })?;
+      report.redraw =
+        write_mem(arg1 as u32, val, environment, device_array, &mut report)?;
     }
     // unaries
     5 | 8 => {
       report.changed = Some(arg1 as u32);
-      let arg2v = get_mem(arg2 as u32, environment, device_array)
-        .ok_or(StepFatal::InvalidIndex { index: arg2 as u32 })?
-        .map_err(|error| StepFatal::DeviceFailure { error })?;
+      let arg2v =
+        read_mem(arg2 as u32, environment, device_array, &mut report)?;
 
       let val = match instruction {
         5 => arg2v,
@@ -141,59 +940,57 @@ pub fn step(
         _ => unreachable!(),
       };
 
-      report.redraw = set_mem(arg1 as u32, val, environment, device_array)
-        .ok_or(StepFatal::InvalidIndex { index: arg1 as u32 })?
-        .map_err(|error| StepFatal::DeviceFailure { error })?;
+      report.redraw =
+        write_mem(arg1 as u32, val, environment, device_array, &mut report)?;
     }
     // array
     11 | 12 => {
-      let arg3v = get_mem(arg3 as u32, environment, device_array)
-        .ok_or(StepFatal::InvalidIndex { index: arg3 as u32 })?
-        .map_err(|error| StepFatal::DeviceFailure { error })?;
+      let arg3v =
+        read_mem(arg3 as u32, environment, device_array, &mut report)?;
 
-      let index: u32 = (arg2 + arg3v) as u32;
+      // the sum is an address like any operand, so a negative one is
+      // reported by `read_mem`/`write_mem` unless it hits a device
+      let index = arg2.checked_add(arg3v).ok_or(StepFatal::IndexOverflow {
+        base: arg2,
+        offset: arg3v,
+      })? as u32;
 
       match instruction {
         11 => {
           report.changed = Some(arg1 as u32);
-          let indexv = get_mem(index, environment, device_array)
-            .ok_or(StepFatal::InvalidIndex { index: index })?
-            .map_err(|error| StepFatal::DeviceFailure { error })?;
+          let indexv = read_mem(index, environment, device_array, &mut report)?;
 
-          report.redraw =
-            set_mem(arg1 as u32, indexv, environment, device_array)
-              .ok_or(StepFatal::InvalidIndex { index: arg1 as u32 })?
-              .map_err(|error| StepFatal::DeviceFailure { error })?;
+          report.redraw = write_mem(
+            arg1 as u32,
+            indexv,
+            environment,
+            device_array,
+            &mut report,
+          )?;
         }
         12 => {
           report.changed = Some(index);
-          let arg1v = get_mem(arg1 as u32, environment, device_array)
-            .ok_or(StepFatal::InvalidIndex { index: arg1 as u32 })?
-            .map_err(|error| StepFatal::DeviceFailure { error })?;
+          let arg1v =
+            read_mem(arg1 as u32, environment, device_array, &mut report)?;
 
           report.redraw =
-            set_mem(index as u32, arg1v, environment, device_array)
-              .ok_or(StepFatal::InvalidIndex {
-                index: index as u32,
-              })?
-              .map_err(|error| StepFatal::DeviceFailure { error })?;
+            write_mem(index, arg1v, environment, device_array, &mut report)?;
         }
         _ => unreachable!(),
       }
     }
-    // branches
-    13 | 14 | 15 => {
-      let arg2v = get_mem(arg2 as u32, environment, device_array)
-        .ok_or(StepFatal::InvalidIndex { index: arg2 as u32 })?
-        .map_err(|error| StepFatal::DeviceFailure { error })?;
-      let arg3v = get_mem(arg3 as u32, environment, device_array)
-        .ok_or(StepFatal::InvalidIndex { index: arg3 as u32 })?
-        .map_err(|error| StepFatal::DeviceFailure { error })?;
+    // branches; bltu (27) is blt comparing unsigned
+    13 | 14 | 15 | 27 => {
+      let arg2v =
+        read_mem(arg2 as u32, environment, device_array, &mut report)?;
+      let arg3v =
+        read_mem(arg3 as u32, environment, device_array, &mut report)?;
 
       if match instruction {
         13 => arg2v == arg3v,
         14 => arg2v != arg3v,
         15 => arg2v < arg3v,
+        27 => (arg2v as u32) < (arg3v as u32),
         _ => unreachable!(),
       } {
         environment.iar = arg1 as u32;
@@ -202,14 +999,20 @@ pub fn step(
     }
     // call
     16 => {
-      report.redraw = set_mem(
+      // a device would swallow the link or reject it with its own error;
+      // any other bad slot faults in the write like it would for `cp`
+      if device_array.is_mapped(arg2 as u32) {
+        return Err(StepFatal::DeviceLink {
+          register: arg2 as u32,
+        });
+      }
+      report.redraw = write_mem(
         arg2 as u32,
         (environment.iar + 4) as i32,
         environment,
         device_array,
-      )
-      .ok_or(StepFatal::InvalidIndex { index: arg2 as u32 })?
-      .map_err(|error| StepFatal::DeviceFailure { error })?;
+        &mut report,
+      )?;
 
       report.changed = Some(arg2 as u32);
 
@@ -218,13 +1021,94 @@ pub fn step(
     }
     // ret
     17 => {
-      let arg1v = get_mem(arg1 as u32, environment, device_array)
-        .ok_or(StepFatal::InvalidIndex { index: arg1 as u32 })?
-        .map_err(|error| StepFatal::DeviceFailure { error })?;
+      let arg1v =
+        read_mem(arg1 as u32, environment, device_array, &mut report)?;
 
+      // returning through the handler's own link unmasks interrupts
+      if let Some(irq) = environment.handling {
+        if arg1 as usize == INTERRUPT_VECTOR_BASE + 2 * irq as usize + 1 {
+          environment.handling = None;
+        }
+      }
       environment.iar = arg1v as u32;
       branched = true;
     }
+    // bo: branch on overflow, clearing the flag
+    30 => {
+      if environment.overflow {
+        environment.overflow = false;
+        environment.iar = arg1 as u32;
+        branched = true;
+      }
+    }
+    // cpwait: cp, but a busy device is retried rather than faulting
+    20 => {
+      report.changed = Some(arg1 as u32);
+      let arg2v =
+        read_mem_blocking(arg2 as u32, environment, device_array, &mut report)?;
+
+      report.redraw =
+        write_mem(arg1 as u32, arg2v, environment, device_array, &mut report)?;
+    }
+    // indirect: arg2 holds the address, like the array ops with no offset
+    21 | 22 => {
+      let pointer =
+        read_mem(arg2 as u32, environment, device_array, &mut report)? as u32;
+
+      match instruction {
+        21 => {
+          report.changed = Some(arg1 as u32);
+          let pointerv =
+            read_mem(pointer, environment, device_array, &mut report)?;
+
+          report.redraw = write_mem(
+            arg1 as u32,
+            pointerv,
+            environment,
+            device_array,
+            &mut report,
+          )?;
+        }
+        22 => {
+          report.changed = Some(pointer);
+          let arg1v =
+            read_mem(arg1 as u32, environment, device_array, &mut report)?;
+
+          report.redraw =
+            write_mem(pointer, arg1v, environment, device_array, &mut report)?;
+        }
+        _ => unreachable!(),
+      }
+    }
+    // sub-word: ldb/stb (23/24) and ldh/sth (25/26) address the byte or
+    // halfword at offset `mem[arg3]` within the word at arg2, counted from
+    // the least significant end. Loads sign-extend.
+    23..=26 => {
+      let bits = if instruction <= 24 { 8 } else { 16 };
+      let offset =
+        read_mem(arg3 as u32, environment, device_array, &mut report)?;
+      if !(0..32 / bits).contains(&offset) {
+        return Err(StepFatal::InvalidSubword { offset });
+      }
+      let shift = offset * bits;
+      let word = read_mem(arg2 as u32, environment, device_array, &mut report)?;
+
+      if instruction % 2 == 1 {
+        report.changed = Some(arg1 as u32);
+        // shifting the sub-word to the top and back copies its sign bit down
+        let val = (word << (32 - bits - shift)) >> (32 - bits);
+        report.redraw =
+          write_mem(arg1 as u32, val, environment, device_array, &mut report)?;
+      } else {
+        report.changed = Some(arg2 as u32);
+        let arg1v =
+          read_mem(arg1 as u32, environment, device_array, &mut report)?;
+        let mask = (1 << bits) - 1;
+        let val = (word & !(mask << shift)) | ((arg1v & mask) << shift);
+        report.redraw =
+          write_mem(arg2 as u32, val, environment, device_array, &mut report)?;
+      }
+    }
     _ => return Err(StepFatal::InvalidInstruction { instr: instruction }),
   }
 
@@ -232,20 +1116,133 @@ pub fn step(
     environment.iar += 4;
   }
 
+  // every opcode that gets this far is below OPCODE_COUNT
+  environment.cycles += environment.cycle_costs[instruction as usize];
+  environment.steps += 1;
   environment.poison = false;
 
   Ok(report)
 }
 
+thread_local! {
+  /// Set while `step_catching` runs, so the panic hook leaves the report to
+  /// it rather than printing over the UI.
+  static CATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Wraps the panic hook, once, so panics caught by `step_catching` only go
+/// to the log. Every other panic still reaches the hook it replaced.
+fn quiet_caught_panics() {
+  static INSTALL: std::sync::Once = std::sync::Once::new();
+  INSTALL.call_once(|| {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+      if CATCHING.get() {
+        log::error!("step panicked: {info}");
+      } else {
+        hook(info);
+      }
+    }));
+  });
+}
+
+/// `step`, but a panic inside it comes back as `StepFatal::InternalError`
+/// instead of unwinding through the caller.
+pub fn step_catching(
+  environment: &mut Environment,
+  device_array: &mut crate::devices::DeviceArray,
+) -> Result<StepReport, StepFatal> {
+  quiet_caught_panics();
+  CATCHING.set(true);
+  let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+    step(environment, device_array)
+  }));
+  CATCHING.set(false);
+  result.unwrap_or_else(|payload| {
+    let message = payload
+      .downcast_ref::<&str>()
+      .map(|message| message.to_string())
+      .or_else(|| payload.downcast_ref::<String>().cloned())
+      .unwrap_or_else(|| "step panicked".to_owned());
+    Err(StepFatal::InternalError { message })
+  })
+}
+
+/// `get_mem` with both failure modes mapped onto the offending address.
+fn read_mem(
+  addr: u32,
+  environment: &Environment,
+  device_array: &mut crate::devices::DeviceArray,
+  report: &mut StepReport,
+) -> Result<i32, StepFatal> {
+  report.note_read(addr);
+  get_mem(addr, environment, device_array)
+    .ok_or_else(|| unmapped(addr))?
+    .map_err(|error| StepFatal::DeviceFailure {
+      error,
+      register: addr,
+    })
+}
+
+/// `read_mem`, but a busy device stalls the instruction, to be retried next
+/// cycle like a slow one, until `DEVICE_READ_RETRIES` cycles have passed
+/// since its first attempt.
+fn read_mem_blocking(
+  addr: u32,
+  environment: &mut Environment,
+  device_array: &mut crate::devices::DeviceArray,
+  report: &mut StepReport,
+) -> Result<i32, StepFatal> {
+  match read_mem(addr, environment, device_array, report) {
+    Err(StepFatal::DeviceFailure {
+      error: crate::devices::DeviceError::Busy,
+      register,
+    }) if device_array.waited(register) < DEVICE_READ_RETRIES => {
+      Err(StepFatal::DeviceFailure {
+        error: crate::devices::DeviceError::Stalled,
+        register,
+      })
+    }
+    result => result,
+  }
+}
+
+/// `set_mem` with both failure modes mapped onto the offending address,
+/// noting what a memory cell held before it was overwritten.
+fn write_mem(
+  addr: u32,
+  value: i32,
+  environment: &mut Environment,
+  device_array: &mut crate::devices::DeviceArray,
+  report: &mut StepReport,
+) -> Result<Option<crate::devices::DeviceKind>, StepFatal> {
+  report.previous = environment.memory.get(addr as usize).copied();
+  set_mem(addr, value, environment, device_array)
+    .ok_or_else(|| unmapped(addr))?
+    .map_err(|error| StepFatal::DeviceFailure {
+      error,
+      register: addr,
+    })
+}
+
+/// Device registers live in the top half of the address space, so only
+/// negative operands that miss every register are reported as negative.
+fn unmapped(addr: u32) -> StepFatal {
+  match addr as i32 {
+    operand if operand < 0 => StepFatal::NegativeAddress { operand },
+    _ => StepFatal::InvalidIndex { index: addr },
+  }
+}
+
 fn get_mem(
   addr: u32,
   environment: &Environment,
   device_array: &mut crate::devices::DeviceArray,
 ) -> Option<Result<i32, crate::devices::DeviceError>> {
-  if addr >= MEMORY_SIZE as u32 {
+  if addr >= DEVICE_BASE {
     device_array.get(addr)
   } else {
-    Some(Ok(environment.memory[addr as usize]))
+    environment.memory.get(addr as usize).copied().map(Ok)
   }
 }
 
@@ -254,11 +1251,761 @@ fn set_mem(
   value: i32,
   environment: &mut Environment,
   device_array: &mut crate::devices::DeviceArray,
-) -> Option<Result<bool, crate::devices::DeviceError>> {
-  if addr >= MEMORY_SIZE as u32 {
+) -> Option<
+  Result<Option<crate::devices::DeviceKind>, crate::devices::DeviceError>,
+> {
+  if addr >= DEVICE_BASE {
     device_array.set(addr, value)
-  } else {
+  } else if (addr as usize) < MEMORY_SIZE {
     environment.memory[addr as usize] = value;
-    Some(Ok(false))
+    environment.initialized[addr as usize] = true;
+    Some(Ok(None))
+  } else {
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::headless::step_at;
+
+  /// Runs `instruction` at 100 with `cells` seeded first.
+  fn run(
+    instruction: [i32; 4],
+    cells: &[(usize, i32)],
+  ) -> (Environment, Result<StepReport, StepFatal>) {
+    step_at(100, instruction, |env| {
+      for &(addr, value) in cells {
+        env.memory[addr] = value;
+      }
+    })
+    .unwrap()
+  }
+
+  /// What `opcode` leaves in m[10] given m[11] and m[12].
+  fn binary(opcode: i32, a: i32, b: i32) -> i32 {
+    let (env, result) = run([opcode, 10, 11, 12], &[(11, a), (12, b)]);
+    result.unwrap();
+    assert_eq!(env.iar, 104);
+    env.memory[10]
+  }
+
+  /// Where `opcode` goes next given m[11] and m[12]: 40 if it branched.
+  fn branch(opcode: i32, a: i32, b: i32) -> u32 {
+    let (env, result) = run([opcode, 40, 11, 12], &[(11, a), (12, b)]);
+    result.unwrap();
+    env.iar
+  }
+
+  #[test]
+  fn halt_stops() {
+    let (env, result) = run([0, 0, 0, 0], &[]);
+    assert!(matches!(result, Err(StepFatal::Halted)));
+    assert_eq!(env.iar, 100);
+  }
+
+  #[test]
+  fn the_last_whole_instruction_runs() {
+    let last = MEMORY_SIZE as u32 - 4;
+    let (mut env, result) =
+      step_at(last, [5, 10, 11, 0], |env| env.memory[11] = 7).unwrap();
+    result.unwrap();
+    assert_eq!((env.memory[10], env.iar), (7, MEMORY_SIZE as u32));
+
+    let mut device_array = crate::devices::DeviceArray::default();
+    assert!(matches!(
+      step(&mut env, &mut device_array),
+      Err(StepFatal::InvalidIAR { .. })
+    ));
+  }
+
+  /// How a program parsed from `mif` stops after its first instruction.
+  fn second_step(mif: &str, strict_halt: bool) -> StepFatal {
+    let mut env = Environment::parse(&mut mif.as_bytes()).unwrap();
+    env.strict_halt = strict_halt;
+    let mut device_array = crate::devices::DeviceArray::default();
+    step(&mut env, &mut device_array).unwrap();
+    match step(&mut env, &mut device_array) {
+      Err(fatal) => fatal,
+      Ok(_) => panic!("expected the program to stop"),
+    }
+  }
+
+  #[test]
+  fn strict_halt_faults_on_running_off_the_program() {
+    let unfinished = "0 : 5; 1 : 20; 2 : 21;";
+    assert!(matches!(
+      second_step(unfinished, true),
+      StepFatal::UninitializedHalt { iar: 4 }
+    ));
+    assert!(matches!(second_step(unfinished, false), StepFatal::Halted));
+    // a HALT that was written stops cleanly either way
+    let finished = "0 : 5; 1 : 20; 2 : 21; 4 : 0;";
+    assert!(matches!(second_step(finished, true), StepFatal::Halted));
+  }
+
+  #[test]
+  fn arithmetic_opcodes() {
+    assert_eq!(binary(1, 5, 7), 12);
+    assert_eq!(binary(2, 5, 7), -2);
+    assert_eq!(binary(3, 6, -7), -42);
+    // division truncates towards zero
+    assert_eq!(binary(4, -7, 2), -3);
+  }
+
+  /// m[10] from `opcode` on m[11] and m[12] under `mode`.
+  fn moded(
+    mode: ArithmeticMode,
+    opcode: i32,
+    a: i32,
+    b: i32,
+  ) -> Result<i32, StepFatal> {
+    let (env, result) = step_at(100, [opcode, 10, 11, 12], |env| {
+      env.memory[11] = a;
+      env.memory[12] = b;
+      env.arithmetic = mode;
+    })
+    .unwrap();
+    result.map(|_| env.memory[10])
+  }
+
+  #[test]
+  fn arithmetic_modes_at_the_limits() {
+    use ArithmeticMode::*;
+
+    assert_eq!(moded(Wrapping, 1, i32::MAX, 1).unwrap(), i32::MIN);
+    assert_eq!(moded(Wrapping, 2, i32::MIN, 1).unwrap(), i32::MAX);
+    assert_eq!(moded(Wrapping, 3, i32::MAX, 2).unwrap(), -2);
+    assert_eq!(moded(Saturating, 1, i32::MAX, 1).unwrap(), i32::MAX);
+    assert_eq!(moded(Saturating, 2, i32::MIN, 1).unwrap(), i32::MIN);
+    assert_eq!(moded(Saturating, 4, i32::MIN, -1).unwrap(), i32::MAX);
+    for (opcode, a, b) in
+      [(1, i32::MAX, 1), (2, i32::MIN, 1), (4, i32::MIN, -1)]
+    {
+      assert!(matches!(
+        moded(Checked, opcode, a, b),
+        Err(StepFatal::Overflow { instr }) if instr == opcode
+      ));
+    }
+    assert_eq!(moded(Checked, 1, i32::MAX, 0).unwrap(), i32::MAX);
+    // legacy wraps everything but mul, which saturates
+    assert_eq!(moded(Legacy, 1, i32::MAX, 1).unwrap(), i32::MIN);
+    assert_eq!(moded(Legacy, 2, i32::MIN, 1).unwrap(), i32::MAX);
+    assert_eq!(moded(Legacy, 3, i32::MAX, 2).unwrap(), i32::MAX);
+  }
+
+  #[test]
+  fn div_by_zero_faults() {
+    let (_, result) = run([4, 10, 11, 12], &[(11, 1)]);
+    assert!(matches!(result, Err(StepFatal::DivisionByZero)));
+  }
+
+  #[test]
+  fn bitwise_opcodes() {
+    assert_eq!(binary(6, 0b1100, 0b1010), 0b1000);
+    assert_eq!(binary(7, 0b1100, 0b1010), 0b1110);
+    assert_eq!(binary(9, 3, 4), 48);
+    assert_eq!(binary(10, -8, 1), -4);
+  }
+
+  #[test]
+  fn unary_opcodes() {
+    let (env, _) = run([5, 10, 11, 0], &[(11, 9)]);
+    assert_eq!(env.memory[10], 9);
+    let (env, _) = run([8, 10, 11, 0], &[(11, 0)]);
+    assert_eq!(env.memory[10], -1);
+  }
+
+  #[test]
+  fn array_opcodes() {
+    // cpfa: m[10] = m[20 + m[12]]
+    let (env, _) = run([11, 10, 20, 12], &[(12, 3), (23, 99)]);
+    assert_eq!(env.memory[10], 99);
+    // cpta: m[20 + m[12]] = m[11]
+    let (env, report) = run([12, 11, 20, 12], &[(11, 5), (12, 3)]);
+    assert_eq!(env.memory[23], 5);
+    assert_eq!(report.unwrap().changed, Some(23));
+  }
+
+  #[test]
+  fn branch_opcodes() {
+    assert_eq!(branch(13, 3, 3), 40);
+    assert_eq!(branch(13, 3, 4), 104);
+    assert_eq!(branch(14, 3, 4), 40);
+    assert_eq!(branch(14, 3, 3), 104);
+    assert_eq!(branch(15, -1, 0), 40);
+    assert_eq!(branch(15, 0, -1), 104);
+  }
+
+  #[test]
+  fn call_and_ret() {
+    let (env, _) = run([16, 40, 10, 0], &[]);
+    assert_eq!(env.iar, 40);
+    assert_eq!(env.memory[10], 104);
+
+    let (env, _) = run([17, 10, 0, 0], &[(10, 60)]);
+    assert_eq!(env.iar, 60);
+  }
+
+  #[test]
+  fn call_links_only_into_memory() {
+    use crate::devices::scratch::SCRATCH_BASE;
+
+    let (env, result) = run([16, 40, SCRATCH_BASE as i32, 0], &[]);
+    assert!(matches!(
+      result,
+      Err(StepFatal::DeviceLink { register }) if register == SCRATCH_BASE
+    ));
+    assert_eq!(env.iar, 100);
+
+    let (_, result) = run([16, 40, -1, 0], &[]);
+    assert!(matches!(
+      result,
+      Err(StepFatal::NegativeAddress { operand: -1 })
+    ));
+  }
+
+  #[test]
+  fn unknown_opcode_faults() {
+    let (env, result) = run([99, 0, 0, 0], &[]);
+    assert!(matches!(
+      result,
+      Err(StepFatal::InvalidInstruction { instr: 99 })
+    ));
+    assert_eq!(env.iar, 100);
+  }
+
+  /// m[10] from `opcode` on m[11] and m[12] with the carry flag starting
+  /// at `carry`, and the carry and overflow flags it leaves.
+  fn with_carry(opcode: i32, a: i32, b: i32, carry: bool) -> (i32, bool, bool) {
+    let (env, result) = step_at(100, [opcode, 10, 11, 12], |env| {
+      env.memory[11] = a;
+      env.memory[12] = b;
+      env.carry = carry;
+    })
+    .unwrap();
+    result.unwrap();
+    (env.memory[10], env.carry, env.overflow)
+  }
+
+  #[test]
+  fn add_and_adc_carry_at_the_edges() {
+    // signed overflow without an unsigned carry, and the other way round
+    assert_eq!(with_carry(1, i32::MAX, 1, false), (i32::MIN, false, true));
+    assert_eq!(with_carry(1, -1, 1, false), (0, true, false));
+    // add ignores a carry left over; adc adds it in
+    assert_eq!(with_carry(1, i32::MAX, 0, true), (i32::MAX, false, false));
+    assert_eq!(with_carry(18, i32::MAX, 0, true), (i32::MIN, false, true));
+    assert_eq!(with_carry(18, -1, 0, true), (0, true, false));
+    assert_eq!(with_carry(18, i32::MIN, i32::MIN, true), (1, true, true));
+  }
+
+  /// Runs `low` then `high` on the 64-bit values split across m[20..22]
+  /// and m[22..24], low word first, and joins the result from m[24..26].
+  fn chained(low: i32, high: i32, a: u64, b: u64) -> u64 {
+    let mut env = Environment::default();
+    env.memory[..8].copy_from_slice(&[low, 24, 20, 22, high, 25, 21, 23]);
+    for (addr, value) in [(20, a), (22, b)] {
+      env.memory[addr] = value as u32 as i32;
+      env.memory[addr + 1] = (value >> 32) as u32 as i32;
+    }
+    let mut device_array = crate::devices::DeviceArray::default();
+    step(&mut env, &mut device_array).unwrap();
+    step(&mut env, &mut device_array).unwrap();
+    env.memory[24] as u32 as u64 | (env.memory[25] as u32 as u64) << 32
+  }
+
+  #[test]
+  fn add_and_adc_chain_into_a_64_bit_sum() {
+    let (a, b) = (0x0000_0001_ffff_ffff, 0x0000_0002_0000_0001);
+    assert_eq!(chained(1, 18, a, b), a + b);
+    assert_eq!(chained(1, 18, u32::MAX as u64, 1), 1 << 32);
+    // and sub/sbb borrow across the words the same way
+    assert_eq!(chained(2, 19, b, a), b - a);
+    assert_eq!(chained(2, 19, 1 << 32, 1), u32::MAX as u64);
+  }
+
+  #[test]
+  fn sub_and_sbb_borrow_at_the_edges() {
+    assert_eq!(with_carry(2, i32::MIN, 1, false), (i32::MAX, false, true));
+    assert_eq!(with_carry(2, 0, 1, false), (-1, true, false));
+    assert_eq!(with_carry(19, i32::MIN, 0, true), (i32::MAX, false, true));
+    assert_eq!(with_carry(19, 0, 0, true), (-1, true, false));
+    assert_eq!(with_carry(19, i32::MAX, -1, true), (i32::MAX, true, false));
+    assert_eq!(with_carry(19, 5, 5, false), (0, false, false));
+  }
+
+  #[test]
+  fn reset_clears_the_carry() {
+    let (mut env, _) = step_at(0, [1, 10, 11, 12], |env| {
+      env.memory[11] = -1;
+      env.memory[12] = 1;
+    })
+    .unwrap();
+    assert!(env.carry);
+
+    env.reset();
+    assert!(!env.carry);
+    env.memory[..4].copy_from_slice(&[18, 10, 12, 12]);
+    step(&mut env, &mut crate::devices::DeviceArray::default()).unwrap();
+    assert_eq!(env.memory[10], 2);
+  }
+
+  #[test]
+  fn bltu_compares_unsigned() {
+    // -1 is the largest unsigned value, where blt takes it as the smallest
+    assert_eq!(branch(27, 1, -1), 40);
+    assert_eq!(branch(15, 1, -1), 104);
+    assert_eq!(branch(27, -1, 1), 104);
+    assert_eq!(branch(27, -2, -1), 40);
+    assert_eq!(branch(27, i32::MAX, i32::MIN), 40);
+    assert_eq!(branch(27, -1, -1), 104);
+  }
+
+  #[test]
+  fn rem_takes_the_dividends_sign() {
+    assert_eq!(binary(28, 7, 3), 1);
+    assert_eq!(binary(28, -7, 3), -1);
+    assert_eq!(binary(28, 7, -3), 1);
+    assert_eq!(binary(28, -7, -3), -1);
+    assert_eq!(binary(28, i32::MIN, -1), 0);
+
+    let (_, result) = run([28, 10, 11, 12], &[(11, 7)]);
+    assert!(matches!(result, Err(StepFatal::DivisionByZero)));
+  }
+
+  #[test]
+  fn srl_shifts_the_sign_bit_out() {
+    assert_eq!(binary(29, -8, 1), 0x7fff_fffc);
+    assert_eq!(binary(10, -8, 1), -4);
+    assert_eq!(binary(29, -1, 28), 0xf);
+    assert_eq!(binary(29, i32::MIN, 31), 1);
+    // counts wrap mod 32
+    assert_eq!(binary(29, -1, 32), -1);
+  }
+
+  #[test]
+  fn slow_device_stalls_then_reads() {
+    use crate::devices::{scratch, DeviceKind};
+
+    let mut env = Environment::default();
+    env.memory[..4].copy_from_slice(&[5, 10, scratch::SCRATCH_BASE as i32, 0]);
+    let mut device_array = crate::devices::DeviceArray::default();
+    device_array
+      .register_device(Box::new(scratch::ScratchDevice::default()))
+      .unwrap();
+    device_array
+      .set(scratch::SCRATCH_BASE, 42)
+      .unwrap()
+      .unwrap();
+    device_array.set_latency(DeviceKind::Scratch, 3);
+
+    for cycle in 1..=3 {
+      let report = step(&mut env, &mut device_array).unwrap();
+      assert_eq!(report.stalled, Some(scratch::SCRATCH_BASE));
+      assert_eq!((env.iar, env.steps, env.cycles), (0, 0, cycle));
+    }
+    let report = step(&mut env, &mut device_array).unwrap();
+    assert_eq!(report.stalled, None);
+    assert_eq!(env.memory[10], 42);
+    assert_eq!((env.iar, env.steps, env.cycles), (4, 1, 4));
+  }
+
+  /// Answers 42 from its one register, but only on the second try.
+  #[derive(Default)]
+  struct BusyOnce {
+    tried: bool,
+  }
+
+  impl crate::devices::DeviceFrame for BusyOnce {
+    fn kind(&self) -> crate::devices::DeviceKind {
+      crate::devices::DeviceKind::Scratch
+    }
+
+    fn registers(&self) -> &'static [u32] {
+      &[0x80000080]
+    }
+
+    fn set(
+      &mut self,
+      _register: u32,
+      _value: i32,
+    ) -> Result<bool, crate::devices::DeviceError> {
+      Ok(false)
+    }
+
+    fn get(
+      &mut self,
+      _register: u32,
+    ) -> Result<i32, crate::devices::DeviceError> {
+      match std::mem::replace(&mut self.tried, true) {
+        false => Err(crate::devices::DeviceError::Busy),
+        true => Ok(42),
+      }
+    }
+  }
+
+  #[test]
+  fn cpwait_retries_a_busy_device() {
+    use crate::devices::{DeviceArray, DeviceError};
+
+    let mut env = Environment::default();
+    env.memory[..4].copy_from_slice(&[20, 10, 0x80000080u32 as i32, 0]);
+    let mut device_array = DeviceArray::default();
+    device_array
+      .register_device(Box::new(BusyOnce::default()))
+      .unwrap();
+
+    let report = step(&mut env, &mut device_array).unwrap();
+    assert_eq!(report.stalled, Some(0x80000080));
+    assert_eq!(env.iar, 0);
+    step(&mut env, &mut device_array).unwrap();
+    assert_eq!((env.memory[10], env.iar), (42, 4));
+
+    // plain cp doesn't wait
+    env.memory[..4].copy_from_slice(&[5, 10, 0x80000080u32 as i32, 0]);
+    env.iar = 0;
+    let mut device_array = DeviceArray::default();
+    device_array
+      .register_device(Box::new(BusyOnce::default()))
+      .unwrap();
+    assert!(matches!(
+      step(&mut env, &mut device_array),
+      Err(StepFatal::DeviceFailure {
+        error: DeviceError::Busy,
+        ..
+      })
+    ));
+  }
+
+  #[test]
+  fn cpwait_gives_up_on_a_device_that_stays_busy() {
+    use crate::devices::DeviceError;
+
+    // the fault device's data register, set to stay busy
+    let mut env = Environment::default();
+    env.memory[..4].copy_from_slice(&[20, 10, 0x800000a2u32 as i32, 0]);
+    let mut device_array = crate::headless::headless_devices();
+    device_array.set(0x800000a0, 1).unwrap().unwrap();
+
+    for _ in 0..DEVICE_READ_RETRIES {
+      let report = step(&mut env, &mut device_array).unwrap();
+      assert!(report.stalled.is_some());
+    }
+    assert!(matches!(
+      step(&mut env, &mut device_array),
+      Err(StepFatal::DeviceFailure {
+        error: DeviceError::Busy,
+        register: 0x800000a2,
+      })
+    ));
+  }
+
+  /// Panics on any read, standing in for a bug inside `step`.
+  struct Panicking;
+
+  impl crate::devices::DeviceFrame for Panicking {
+    fn kind(&self) -> crate::devices::DeviceKind {
+      crate::devices::DeviceKind::Scratch
+    }
+
+    fn registers(&self) -> &'static [u32] {
+      &[0x80000080]
+    }
+
+    fn set(
+      &mut self,
+      _register: u32,
+      _value: i32,
+    ) -> Result<bool, crate::devices::DeviceError> {
+      Ok(false)
+    }
+
+    fn get(
+      &mut self,
+      _register: u32,
+    ) -> Result<i32, crate::devices::DeviceError> {
+      panic!("read of a broken device");
+    }
+  }
+
+  #[test]
+  fn a_panicking_step_becomes_a_fault() {
+    let mut env = Environment::default();
+    env.memory[..4].copy_from_slice(&[5, 10, 0x80000080u32 as i32, 0]);
+    let mut device_array = crate::devices::DeviceArray::default();
+    device_array.register_device(Box::new(Panicking)).unwrap();
+
+    match step_catching(&mut env, &mut device_array) {
+      Err(StepFatal::InternalError { message }) => {
+        assert_eq!(message, "read of a broken device");
+      }
+      _ => panic!("expected an internal error"),
+    }
+    assert!(!CATCHING.get());
+    // the half-run step leaves the machine poisoned
+    assert!(matches!(
+      step_catching(&mut env, &mut device_array),
+      Err(StepFatal::AlreadyPoisoned)
+    ));
+  }
+
+  #[test]
+  fn find_lists_every_match_up_to_the_limit() {
+    let mut env = Environment::default();
+    env.fill(FillPattern::Incrementing);
+    env.memory[3] = 42;
+    env.memory[9000] = 42;
+    env.memory[MEMORY_SIZE - 1] = 42;
+
+    assert_eq!(env.find(42, -1, MEMORY_SIZE), [3, 42, 9000, 16383]);
+    assert_eq!(env.find(42, -1, 2), [3, 42]);
+    // the low byte only, so every 256th cell from 42 matches as well
+    assert_eq!(env.find(42, 0xff, MEMORY_SIZE).len(), 64 + 3);
+  }
+
+  #[test]
+  fn written_mif_parses_back_to_the_same_memory() {
+    let source = "\
+start\tcpdata 100 -7
+\tadd 101 100 one
+\tbne start 101 100
+\thalt
+one\t1
+table\t5 5 5 5 -1
+";
+    let assembly = crate::asm::assemble(source).unwrap();
+    let mut mif = Vec::new();
+    assembly.write_mif(&mut mif).unwrap();
+    let parsed = Environment::parse(&mut mif.as_slice()).unwrap();
+
+    assert_eq!(parsed.memory, assembly.environment.memory);
+    assert_eq!(parsed.initialized, assembly.environment.initialized);
+  }
+
+  #[test]
+  fn hex_writes_report_the_hex_display() {
+    let (_, result) = run([5, 0x80000003u32 as i32, 11, 0], &[(11, 0x1234)]);
+    let report = result.unwrap();
+    assert_eq!(report.redraw, Some(crate::devices::DeviceKind::Hex));
+    assert_eq!(report.changed, Some(0x80000003));
+
+    // memory writes don't ask for a redraw
+    let (_, result) = run([5, 10, 11, 0], &[(11, 1)]);
+    assert_eq!(result.unwrap().redraw, None);
+  }
+
+  #[test]
+  fn big_endian_dumps_round_trip() {
+    let mut env = Environment::default();
+    env.memory[..3].copy_from_slice(&[0x01020304, -2, 0]);
+    env.memory[9] = i32::MIN;
+    let format = DumpFormat {
+      endianness: Endianness::Big,
+      trim: true,
+    };
+
+    let mut data = Vec::new();
+    env.dump(&mut data, format).unwrap();
+    assert_eq!(data.len(), 10 * 4);
+    assert_eq!(data[..4], [1, 2, 3, 4]);
+    let restored = Environment::undump(&mut data.as_slice(), format).unwrap();
+    assert_eq!(restored.memory, env.memory);
+
+    // read back little-endian, every word comes out byte-swapped
+    let swapped =
+      Environment::undump(&mut data.as_slice(), DumpFormat::default()).unwrap();
+    assert_eq!(swapped.memory[0], 0x04030201);
+  }
+
+  #[test]
+  fn addresses_either_side_of_each_boundary() {
+    let read = |addr: u32| run([5, 10, addr as i32, 0], &[]).1;
+
+    // the last memory cell, then the unmapped gap up to the devices
+    assert!(read(MEMORY_SIZE as u32 - 1).is_ok());
+    assert!(matches!(
+      read(MEMORY_SIZE as u32),
+      Err(StepFatal::InvalidIndex { index }) if index == MEMORY_SIZE as u32
+    ));
+    assert!(matches!(
+      read(DEVICE_BASE - 1),
+      Err(StepFatal::InvalidIndex { index }) if index == DEVICE_BASE - 1
+    ));
+    // device space starts at DEVICE_BASE, but only registers answer there
+    assert!(matches!(
+      read(DEVICE_BASE),
+      Err(StepFatal::NegativeAddress { operand: i32::MIN })
+    ));
+    assert!(read(0x80000006).is_ok());
+  }
+
+  #[test]
+  fn array_index_below_zero_faults() {
+    for opcode in [11, 12] {
+      let (env, result) = run([opcode, 10, 5, 20], &[(10, 7), (20, -8)]);
+      assert!(matches!(
+        result,
+        Err(StepFatal::NegativeAddress { operand: -3 })
+      ));
+      assert_eq!(env.memory[10], 7);
+
+      let (_, result) = run([opcode, 10, i32::MAX, 20], &[(20, 1)]);
+      assert!(matches!(
+        result,
+        Err(StepFatal::IndexOverflow {
+          base: i32::MAX,
+          offset: 1
+        })
+      ));
+    }
+  }
+
+  #[test]
+  fn fill_patterns_cover_all_of_memory() {
+    let mut env = Environment::default();
+    env.fill(FillPattern::Incrementing);
+    assert_eq!(env.memory[..3], [0, 1, 2]);
+    assert_eq!(env.memory[MEMORY_SIZE - 1], MEMORY_SIZE as i32 - 1);
+    assert!(env.initialized.iter().all(|&initialized| initialized));
+
+    env.fill(FillPattern::Constant(-5));
+    assert!(env.memory.iter().all(|&value| value == -5));
+  }
+
+  #[test]
+  fn device_faults_name_the_register_and_error() {
+    use crate::devices::DeviceError;
+
+    // the fault device only has modes 0 to 4
+    let (env, result) = run([5, 0x800000a0u32 as i32, 11, 0], &[(11, 5)]);
+    let Err(fatal) = result else {
+      panic!("writing mode 5 should fault");
+    };
+    assert!(matches!(
+      fatal,
+      StepFatal::DeviceFailure {
+        error: DeviceError::Unwritable,
+        register: 0x800000a0,
+      }
+    ));
+    assert!(fatal.to_string().starts_with("register 0x800000a0 "));
+    assert_eq!(env.iar, 100);
+  }
+
+  #[test]
+  fn negative_operands_fault_as_written() {
+    let (_, result) = run([5, 10, -5, 0], &[]);
+    assert!(matches!(
+      result,
+      Err(StepFatal::NegativeAddress { operand: -5 })
+    ));
+
+    let (_, result) = run([1, -7, 11, 12], &[]);
+    assert!(matches!(
+      result,
+      Err(StepFatal::NegativeAddress { operand: -7 })
+    ));
+    assert_eq!(
+      StepFatal::NegativeAddress { operand: -7 }.to_string(),
+      "negative address -7"
+    );
+  }
+
+  #[test]
+  fn segments_load_at_their_base_and_report_overlaps() {
+    let mut segment = Environment::default();
+    segment.memory[..2].copy_from_slice(&[5, 6]);
+    segment.initialized[..2].fill(true);
+    let mut env = Environment::default();
+    env.memory[101] = 9;
+    env.initialized[101] = true;
+
+    assert_eq!(env.load_segment(&segment, 100).unwrap(), [101]);
+    assert_eq!(env.memory[99..103], [0, 5, 6, 0]);
+    assert!(!env.initialized[102]);
+    assert!(env.load_segment(&segment, MEMORY_SIZE as u32 - 1).is_err());
+  }
+
+  #[test]
+  fn extract_and_dump_range_take_just_the_range() {
+    let mut env = Environment::default();
+    env.fill(FillPattern::Incrementing);
+
+    let table = env.extract(100, 103).unwrap();
+    assert_eq!(table.memory[..4], [100, 101, 102, 0]);
+    assert_eq!(table.initialized[..4], [true, true, true, false]);
+
+    let mut data = Vec::new();
+    env
+      .dump_range(&mut data, DumpFormat::default(), 100, 102)
+      .unwrap();
+    assert_eq!(data, [100, 0, 0, 0, 101, 0, 0, 0]);
+
+    assert!(env.extract(MEMORY_SIZE - 1, MEMORY_SIZE + 1).is_err());
+    assert!(env
+      .dump_range(&mut data, DumpFormat::default(), 5, 4)
+      .is_err());
+  }
+
+  #[test]
+  fn args_fill_their_region_after_the_count() {
+    let mut env = Environment::default();
+    env.set_args(&[7, -8]).unwrap();
+    assert_eq!(env.memory[ARGS_BASE..ARGS_BASE + 4], [2, 7, -8, 0]);
+    assert!(!env.initialized[ARGS_BASE + 3]);
+
+    env.set_args(&[1; ARGS_MAX]).unwrap();
+    // a full region ends just short of the interrupt vectors
+    assert_eq!(env.memory[INTERRUPT_VECTOR_BASE - 1], 1);
+    assert!(env.set_args(&[1; ARGS_MAX + 1]).is_err());
+    assert_eq!(env.memory[ARGS_BASE], ARGS_MAX as i32);
+  }
+
+  #[test]
+  fn cpfi_and_cpti_go_through_the_pointer() {
+    // m[11] points at m[30]
+    let (env, result) = run([21, 10, 11, 0], &[(11, 30), (30, 42)]);
+    assert_eq!(result.unwrap().changed, Some(10));
+    assert_eq!(env.memory[10], 42);
+
+    let (env, result) = run([22, 10, 11, 0], &[(10, 42), (11, 30)]);
+    assert_eq!(result.unwrap().changed, Some(30));
+    assert_eq!(env.memory[30], 42);
+    assert_eq!(env.memory[11], 30);
+
+    let (_, result) = run([21, 10, 11, 0], &[(11, -2)]);
+    assert!(matches!(
+      result,
+      Err(StepFatal::NegativeAddress { operand: -2 })
+    ));
+  }
+
+  #[test]
+  fn subword_loads_sign_extend_and_stores_merge() {
+    const WORD: i32 = 0x1280ff34;
+    let subword = |opcode, offset| {
+      let (env, result) = run(
+        [opcode, 10, 11, 12],
+        &[(10, 0xab), (11, WORD), (12, offset)],
+      );
+      result.map(|_| (env.memory[10], env.memory[11]))
+    };
+
+    assert_eq!(subword(23, 0).unwrap().0, 0x34);
+    assert_eq!(subword(23, 1).unwrap().0, -1);
+    assert_eq!(subword(25, 1).unwrap().0, 0x1280);
+    assert_eq!(subword(24, 3).unwrap().1, 0xab80ff34u32 as i32);
+    assert_eq!(subword(26, 0).unwrap().1, 0x128000ab);
+
+    assert!(matches!(
+      subword(23, 4),
+      Err(StepFatal::InvalidSubword { offset: 4 })
+    ));
+    assert!(matches!(
+      subword(26, -1),
+      Err(StepFatal::InvalidSubword { offset: -1 })
+    ));
   }
 }
@@ -1,58 +1,488 @@
-use std::collections::HashMap;
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, RwLock,
+  },
+};
 
+use color_eyre::eyre;
+
+pub mod fault;
+pub mod info;
 pub mod kbd;
+pub mod mouse;
 pub mod onboard;
+pub mod rng;
+pub mod scratch;
+pub mod sink;
+pub mod timer;
+pub mod tscr;
 pub mod vga;
 
 #[derive(Default)]
 pub struct DeviceArray {
   devices: Vec<Box<dyn DeviceFrame>>,
-  registers: HashMap<u32, usize>,
+  /// Bus address to the index of the device behind it and the register the
+  /// device itself knows that address as.
+  registers: HashMap<u32, (usize, u32)>,
+  /// While set, writes to output devices are accepted and dropped, so a
+  /// program can be fast-forwarded without drawing anything.
+  frozen: Arc<AtomicBool>,
+  /// The kind of every registered device, in registration order.
+  inventory: Arc<RwLock<Vec<DeviceKind>>>,
+  /// Cycles an access to each kind of device stalls for before it goes
+  /// through. Kinds not listed answer at once.
+  latencies: HashMap<DeviceKind, u64>,
+  /// The cycle each register's outstanding access began at, kept until the
+  /// instruction making it completes so a retried instruction doesn't wait
+  /// over again for the registers it already waited on.
+  started: HashMap<u32, u64>,
+  /// The current cycle, as last passed to `tick`.
+  now: u64,
 }
 
 impl DeviceArray {
-  pub fn register_device(&mut self, device: Box<dyn DeviceFrame>) {
+  /// Registers `device` at its own register numbers. Fails without
+  /// registering anything if one is already taken.
+  pub fn register_device(
+    &mut self,
+    device: Box<dyn DeviceFrame>,
+  ) -> eyre::Result<()> {
+    let base = device.registers().iter().copied().min().unwrap_or(0);
+    self.register_device_at(device, base)
+  }
+
+  /// Registers `device` with its registers moved so the lowest sits at
+  /// `base`, keeping their spacing. The device still sees its own register
+  /// numbers, so any device can be mapped more than once this way. Fails
+  /// without registering anything if a moved register is already taken.
+  pub fn register_device_at(
+    &mut self,
+    device: Box<dyn DeviceFrame>,
+    base: u32,
+  ) -> eyre::Result<()> {
     let idx = self.devices.len();
-    self
-      .registers
-      .extend(device.registers().into_iter().map(|reg| (*reg, idx)));
+    let lowest = device.registers().iter().copied().min().unwrap_or(base);
+    let mapped = device
+      .registers()
+      .iter()
+      .map(|reg| (base.wrapping_add(reg - lowest), (idx, *reg)))
+      .collect::<Vec<_>>();
+    if let Some((addr, _)) = mapped
+      .iter()
+      .find(|(addr, _)| self.registers.contains_key(addr))
+    {
+      eyre::bail!("register 0x{addr:08x} is already mapped");
+    }
+
+    self.registers.extend(mapped);
+    self.inventory.write().unwrap().push(device.kind());
     self.devices.push(device);
     log::info!("Device array contents: {:?}", self.registers);
+    Ok(())
   }
 
+  /// Writes a register, returning the kind of the written device when it
+  /// wants the UI redrawn.
   pub fn set(
     &mut self,
     register: u32,
     value: i32,
-  ) -> Option<Result<bool, DeviceError>> {
+  ) -> Option<Result<Option<DeviceKind>, DeviceError>> {
     let register = register & 0xffffffff;
     // log::info!("Seeking to set 0x{:08x} ({register}) to {value}", register);
     // log::info!("Devices {:?}", self.registers);
     // log::info!("Device index {:?}", self.registers.get(&register));
-    self
-      .registers
-      .get(&register)
-      .map(|idx| self.devices[*idx].set(register, value))
+    let (idx, native) = *self.registers.get(&register)?;
+    let kind = self.devices[idx].kind();
+    if kind.is_output() && self.frozen.load(Ordering::Relaxed) {
+      return Some(Ok(None));
+    }
+    if let Err(error) = self.wait(register, kind) {
+      return Some(Err(error));
+    }
+    Some(
+      self.devices[idx]
+        .set(native, value)
+        .map(|redraw| redraw.then_some(kind)),
+    )
+  }
+
+  /// Makes every access to devices of `kind` take `cycles` cycles.
+  pub fn set_latency(&mut self, kind: DeviceKind, cycles: u64) {
+    self.latencies.insert(kind, cycles);
+  }
+
+  /// Advances the clock that latencies are measured against. Going back in
+  /// time (a reset) abandons every access in progress.
+  pub fn tick(&mut self, cycle: u64) {
+    if cycle < self.now {
+      self.started.clear();
+    }
+    self.now = cycle;
+  }
+
+  /// Holds an access to `register` off with `Stalled` until its device's
+  /// latency has passed since the first attempt.
+  fn wait(
+    &mut self,
+    register: u32,
+    kind: DeviceKind,
+  ) -> Result<(), DeviceError> {
+    let Some(&latency) = self.latencies.get(&kind) else {
+      return Ok(());
+    };
+    let started = *self.started.entry(register).or_insert(self.now);
+    if self.now - started < latency {
+      return Err(DeviceError::Stalled);
+    }
+    Ok(())
+  }
+
+  /// Cycles since the current instruction first tried `register`, counting
+  /// from now if this is its first try.
+  pub fn waited(&mut self, register: u32) -> u64 {
+    self.now - *self.started.entry(register).or_insert(self.now)
+  }
+
+  /// Ends the accesses of an instruction that has finished, one way or the
+  /// other, so the next access to the same registers waits afresh.
+  pub fn complete_accesses(&mut self) {
+    self.started.clear();
+  }
+
+  /// The kinds of the registered devices, kept up to date as more are
+  /// registered.
+  pub fn inventory(&self) -> Arc<RwLock<Vec<DeviceKind>>> {
+    self.inventory.clone()
+  }
+
+  /// The flag that freezes output devices, for toggling from elsewhere.
+  pub fn frozen(&self) -> Arc<AtomicBool> {
+    self.frozen.clone()
+  }
+
+  /// Whether a device answers at `register`.
+  pub fn is_mapped(&self, register: u32) -> bool {
+    self.registers.contains_key(&register)
   }
 
   pub fn get(&mut self, register: u32) -> Option<Result<i32, DeviceError>> {
+    let (idx, native) = *self.registers.get(&register)?;
+    if let Err(error) = self.wait(register, self.devices[idx].kind()) {
+      return Some(Err(error));
+    }
+    Some(self.devices[idx].get(native))
+  }
+
+  /// Concatenates every device's snapshot, each prefixed by its length, in
+  /// registration order.
+  pub fn snapshot(&self) -> Vec<u8> {
+    let mut data = (self.devices.len() as u32).to_le_bytes().to_vec();
+    for device in self.devices.iter() {
+      let device_data = device.snapshot();
+      data.extend_from_slice(&(device_data.len() as u32).to_le_bytes());
+      data.extend_from_slice(&device_data);
+    }
+    data
+  }
+
+  /// Restores a snapshot taken from an array with the same devices
+  /// registered in the same order. The whole snapshot is checked first, so
+  /// a bad one leaves every device as it was.
+  pub fn restore(&mut self, mut data: &[u8]) -> eyre::Result<()> {
+    let take_u32 = |data: &mut &[u8]| -> eyre::Result<u32> {
+      let Some((word, rest)) = data.split_first_chunk::<4>() else {
+        eyre::bail!("device snapshot truncated");
+      };
+      *data = rest;
+      Ok(u32::from_le_bytes(*word))
+    };
+
+    let count = take_u32(&mut data)? as usize;
+    if count != self.devices.len() {
+      eyre::bail!(
+        "snapshot holds {count} devices but {} are registered",
+        self.devices.len()
+      );
+    }
+
+    let mut parts = Vec::with_capacity(count);
+    for _ in 0..count {
+      let len = take_u32(&mut data)? as usize;
+      if data.len() < len {
+        eyre::bail!("device snapshot truncated");
+      }
+      let (device_data, rest) = data.split_at(len);
+      parts.push(device_data);
+      data = rest;
+    }
+
+    for (device, device_data) in self.devices.iter_mut().zip(parts) {
+      device.restore(device_data);
+    }
+    Ok(())
+  }
+
+  /// Takes the first pending interrupt from any registered device.
+  pub fn take_interrupt(&mut self) -> Option<u32> {
+    self
+      .devices
+      .iter_mut()
+      .find_map(|device| device.take_interrupt())
+  }
+
+  /// Shuts every device down, returning the tasks that must finish before
+  /// their side effects are visible.
+  pub fn shutdown(&mut self) -> Vec<tokio::task::JoinHandle<()>> {
     self
-      .registers
-      .get(&register)
-      .map(|idx| self.devices[*idx].get(register))
+      .devices
+      .iter_mut()
+      .flat_map(|device| device.shutdown())
+      .collect()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceKind {
+  Lcd,
+  Hex,
+  Led,
+  Vga,
+  Kbd,
+  Mouse,
+  Tscr,
+  TermKbd,
+  Scratch,
+  FileSink,
+  Fault,
+  Info,
+  Timer,
+  Rng,
+}
+
+impl std::str::FromStr for DeviceKind {
+  type Err = eyre::Report;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "lcd" => Ok(DeviceKind::Lcd),
+      "hex" => Ok(DeviceKind::Hex),
+      "led" => Ok(DeviceKind::Led),
+      "vga" => Ok(DeviceKind::Vga),
+      "kbd" => Ok(DeviceKind::Kbd),
+      "mouse" => Ok(DeviceKind::Mouse),
+      "tscr" => Ok(DeviceKind::Tscr),
+      "termkbd" => Ok(DeviceKind::TermKbd),
+      "scratch" => Ok(DeviceKind::Scratch),
+      "filesink" => Ok(DeviceKind::FileSink),
+      "fault" => Ok(DeviceKind::Fault),
+      "info" => Ok(DeviceKind::Info),
+      "timer" => Ok(DeviceKind::Timer),
+      "rng" => Ok(DeviceKind::Rng),
+      _ => eyre::bail!("unknown device kind {s:?}"),
+    }
+  }
+}
+
+impl DeviceKind {
+  /// A stable number for the kind, as programs see it through `InfoDevice`.
+  pub fn id(self) -> i32 {
+    match self {
+      DeviceKind::Lcd => 1,
+      DeviceKind::Hex => 2,
+      DeviceKind::Vga => 3,
+      DeviceKind::Kbd => 4,
+      DeviceKind::TermKbd => 5,
+      DeviceKind::Scratch => 6,
+      DeviceKind::FileSink => 7,
+      DeviceKind::Fault => 8,
+      DeviceKind::Info => 9,
+      DeviceKind::Timer => 10,
+      DeviceKind::Rng => 11,
+      DeviceKind::Led => 12,
+      DeviceKind::Mouse => 13,
+      DeviceKind::Tscr => 14,
+    }
+  }
+
+  /// Devices whose writes only change what's displayed.
+  pub fn is_output(self) -> bool {
+    matches!(
+      self,
+      DeviceKind::Lcd | DeviceKind::Hex | DeviceKind::Led | DeviceKind::Vga
+    )
   }
 }
 
 #[derive(Debug)]
 pub enum DeviceError {
   Busy,
+  /// Not ready yet, but will be if asked again; `step` retries the
+  /// instruction rather than faulting.
+  Stalled,
   Dead,
   Unreadable,
   Unwritable,
 }
 
+impl std::fmt::Display for DeviceError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      DeviceError::Busy => write!(f, "is busy"),
+      DeviceError::Stalled => write!(f, "is not ready yet"),
+      DeviceError::Dead => write!(f, "is dead"),
+      DeviceError::Unreadable => write!(f, "is not readable"),
+      DeviceError::Unwritable => write!(f, "is not writable"),
+    }
+  }
+}
+
 pub trait DeviceFrame: Send {
+  fn kind(&self) -> DeviceKind;
   fn registers(&self) -> &'static [u32];
   fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError>;
   fn get(&mut self, register: u32) -> Result<i32, DeviceError>;
+
+  /// Returns the interrupt number (below `core::INTERRUPT_COUNT`) this
+  /// device wants serviced, clearing it.
+  fn take_interrupt(&mut self) -> Option<u32> {
+    None
+  }
+
+  /// Serialises whatever state the device needs to resume later. Devices
+  /// with nothing worth keeping can leave this empty.
+  fn snapshot(&self) -> Vec<u8> {
+    Vec::new()
+  }
+
+  /// Restores state produced by `snapshot`; malformed data is ignored.
+  fn restore(&mut self, _data: &[u8]) {}
+
+  /// Called once when the machine stops for good. Background listeners should
+  /// be cancelled here; tasks still applying writes are handed back so the
+  /// caller can wait for them.
+  fn shutdown(&mut self) -> Vec<tokio::task::JoinHandle<()>> {
+    Vec::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use scratch::{ScratchDevice, SCRATCH_BASE, SCRATCH_COUNT};
+
+  /// Second scratch bank, well clear of the first.
+  const SECOND: u32 = SCRATCH_BASE + 0x100;
+
+  /// Two scratch banks, at `SCRATCH_BASE` and `SECOND`.
+  fn two_banks() -> DeviceArray {
+    let mut device_array = DeviceArray::default();
+    device_array
+      .register_device(Box::new(ScratchDevice::default()))
+      .unwrap();
+    device_array
+      .register_device_at(Box::new(ScratchDevice::default()), SECOND)
+      .unwrap();
+    device_array
+  }
+
+  #[test]
+  fn bad_snapshot_restores_nothing() {
+    let mut device_array = two_banks();
+    device_array.set(SCRATCH_BASE, 7).unwrap().unwrap();
+    device_array.set(SECOND, 70).unwrap().unwrap();
+    let data = device_array.snapshot();
+
+    device_array.set(SCRATCH_BASE, 8).unwrap().unwrap();
+    device_array.set(SECOND, 80).unwrap().unwrap();
+    // the first device's part is whole, the second's cut short
+    assert!(device_array.restore(&data[..data.len() - 1]).is_err());
+    assert_eq!(device_array.get(SCRATCH_BASE).unwrap().unwrap(), 8);
+    assert_eq!(device_array.get(SECOND).unwrap().unwrap(), 80);
+
+    device_array.restore(&data).unwrap();
+    assert_eq!(device_array.get(SCRATCH_BASE).unwrap().unwrap(), 7);
+    assert_eq!(device_array.get(SECOND).unwrap().unwrap(), 70);
+  }
+
+  #[test]
+  fn overlapping_registers_are_rejected() {
+    let mut device_array = two_banks();
+    assert!(device_array
+      .register_device(Box::new(ScratchDevice::default()))
+      .is_err());
+    assert!(device_array
+      .register_device_at(Box::new(ScratchDevice::default()), SECOND + 15)
+      .is_err());
+    // nothing of either rejected device was mapped
+    assert_eq!(device_array.inventory().read().unwrap().len(), 2);
+    assert!(!device_array.is_mapped(SECOND + 16));
+  }
+
+  #[test]
+  fn latency_stalls_for_its_cycles_then_answers() {
+    let mut device_array = DeviceArray::default();
+    device_array
+      .register_device(Box::new(ScratchDevice::default()))
+      .unwrap();
+    device_array.set(SCRATCH_BASE, 7).unwrap().unwrap();
+    device_array.set_latency(DeviceKind::Scratch, 3);
+
+    for cycle in 0..3 {
+      device_array.tick(cycle);
+      assert!(matches!(
+        device_array.get(SCRATCH_BASE),
+        Some(Err(DeviceError::Stalled))
+      ));
+    }
+    device_array.tick(3);
+    assert_eq!(device_array.get(SCRATCH_BASE).unwrap().unwrap(), 7);
+
+    // the next instruction's access waits all over again
+    device_array.complete_accesses();
+    assert!(matches!(
+      device_array.get(SCRATCH_BASE),
+      Some(Err(DeviceError::Stalled))
+    ));
+  }
+
+  #[test]
+  fn banks_at_different_bases_hold_their_own_values() {
+    let mut device_array = two_banks();
+    let last = SCRATCH_COUNT as u32 - 1;
+    device_array.set(SCRATCH_BASE, 1).unwrap().unwrap();
+    device_array.set(SCRATCH_BASE + last, 2).unwrap().unwrap();
+    device_array.set(SECOND, 3).unwrap().unwrap();
+    device_array.set(SECOND + last, 4).unwrap().unwrap();
+
+    assert_eq!(device_array.get(SCRATCH_BASE).unwrap().unwrap(), 1);
+    assert_eq!(device_array.get(SCRATCH_BASE + last).unwrap().unwrap(), 2);
+    assert_eq!(device_array.get(SECOND).unwrap().unwrap(), 3);
+    assert_eq!(device_array.get(SECOND + last).unwrap().unwrap(), 4);
+    // the gap between the banks stays unmapped
+    assert!(device_array.get(SECOND - 1).is_none());
+  }
+
+  #[test]
+  fn freezing_drops_only_output_writes() {
+    let mut device_array = two_banks();
+    let hex_device = onboard::HexDisplayDevice::default();
+    let hex0 = hex_device.hex0.clone();
+    device_array.register_device(Box::new(hex_device)).unwrap();
+
+    device_array.frozen().store(true, Ordering::Relaxed);
+    assert!(matches!(device_array.set(0x80000003, 0x12), Some(Ok(None))));
+    assert_eq!(hex0.load(Ordering::Relaxed), 0);
+    device_array.set(SCRATCH_BASE, 5).unwrap().unwrap();
+    assert_eq!(device_array.get(SCRATCH_BASE).unwrap().unwrap(), 5);
+
+    device_array.frozen().store(false, Ordering::Relaxed);
+    assert!(matches!(
+      device_array.set(0x80000003, 0x12),
+      Some(Ok(Some(DeviceKind::Hex)))
+    ));
+    assert_eq!(hex0.load(Ordering::Relaxed), 0x12);
+  }
 }
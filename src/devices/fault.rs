@@ -0,0 +1,100 @@
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// A device that fails on request, for exercising how programs and the
+/// executor cope with `DeviceError`s.
+///
+/// 0x800000a0 selects the failure (0 none, 1 `Busy`, 2 `Dead`,
+/// 3 `Unreadable`, 4 `Unwritable`) and 0x800000a1 how many accesses to the
+/// data register 0x800000a2 succeed before it starts. Once started it keeps
+/// failing until the mode is written again. The control registers always
+/// work; the data register otherwise behaves like a plain cell.
+#[derive(Default)]
+pub struct FaultDevice {
+  mode: i32,
+  countdown: i32,
+  value: i32,
+}
+
+impl FaultDevice {
+  /// Counts one access to the data register, failing if it's time to.
+  fn access(&mut self) -> Result<(), DeviceError> {
+    if self.countdown > 0 {
+      self.countdown -= 1;
+      return Ok(());
+    }
+
+    match self.mode {
+      1 => Err(DeviceError::Busy),
+      2 => Err(DeviceError::Dead),
+      3 => Err(DeviceError::Unreadable),
+      4 => Err(DeviceError::Unwritable),
+      _ => Ok(()),
+    }
+  }
+}
+
+impl DeviceFrame for FaultDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Fault
+  }
+
+  fn registers(&self) -> &'static [u32] {
+    &[0x800000a0, 0x800000a1, 0x800000a2]
+  }
+
+  fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError> {
+    match register {
+      0x800000a0 if !(0..=4).contains(&value) => {
+        return Err(DeviceError::Unwritable)
+      }
+      0x800000a0 => self.mode = value,
+      0x800000a1 => self.countdown = value.max(0),
+      0x800000a2 => {
+        self.access()?;
+        self.value = value;
+      }
+      _ => unreachable!(),
+    }
+
+    Ok(false)
+  }
+
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    match register {
+      0x800000a0 => Ok(self.mode),
+      0x800000a1 => Ok(self.countdown),
+      0x800000a2 => {
+        self.access()?;
+        Ok(self.value)
+      }
+      _ => unreachable!(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn data_register_fails_after_its_countdown() {
+    let mut fault = FaultDevice::default();
+    fault.set(0x800000a2, 6).unwrap();
+    fault.set(0x800000a0, 2).unwrap();
+    fault.set(0x800000a1, 2).unwrap();
+
+    assert_eq!(fault.get(0x800000a2).unwrap(), 6);
+    fault.set(0x800000a2, 7).unwrap();
+    assert_eq!(fault.get(0x800000a1).unwrap(), 0);
+    // it keeps failing, reads and writes alike, until the mode changes
+    assert!(matches!(fault.get(0x800000a2), Err(DeviceError::Dead)));
+    assert!(matches!(fault.set(0x800000a2, 8), Err(DeviceError::Dead)));
+    assert!(matches!(
+      fault.set(0x800000a0, 5),
+      Err(DeviceError::Unwritable)
+    ));
+
+    fault.set(0x800000a0, 0).unwrap();
+    assert_eq!(fault.get(0x800000a2).unwrap(), 7);
+  }
+}
@@ -0,0 +1,102 @@
+use std::sync::{Arc, RwLock};
+
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// Lists the machine's hardware for programs that configure themselves.
+///
+/// 0x800000b0 reads the number of registered devices. Writing an index to
+/// 0x800000b1 selects a device, in registration order, and 0x800000b2 then
+/// reads its `DeviceKind::id` (`Unreadable` past the last device).
+pub struct InfoDevice {
+  inventory: Arc<RwLock<Vec<DeviceKind>>>,
+  selected: i32,
+}
+
+impl InfoDevice {
+  /// Takes `DeviceArray::inventory`, so devices registered later are listed
+  /// too.
+  pub fn new(inventory: Arc<RwLock<Vec<DeviceKind>>>) -> Self {
+    InfoDevice {
+      inventory,
+      selected: 0,
+    }
+  }
+}
+
+impl DeviceFrame for InfoDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Info
+  }
+
+  fn registers(&self) -> &'static [u32] {
+    &[0x800000b0, 0x800000b1, 0x800000b2]
+  }
+
+  fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError> {
+    match register {
+      0x800000b1 => self.selected = value,
+      _ => return Err(DeviceError::Unwritable),
+    }
+
+    Ok(false)
+  }
+
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    let inventory = self.inventory.read().map_err(|_| DeviceError::Dead)?;
+    match register {
+      0x800000b0 => Ok(inventory.len() as i32),
+      0x800000b1 => Ok(self.selected),
+      0x800000b2 => usize::try_from(self.selected)
+        .ok()
+        .and_then(|index| inventory.get(index))
+        .map(|kind| kind.id())
+        .ok_or(DeviceError::Unreadable),
+      _ => unreachable!(),
+    }
+  }
+
+  fn snapshot(&self) -> Vec<u8> {
+    self.selected.to_le_bytes().to_vec()
+  }
+
+  fn restore(&mut self, data: &[u8]) {
+    if let Ok(selected) = data.try_into() {
+      self.selected = i32::from_le_bytes(selected);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::devices::sink::FileSinkDevice;
+
+  #[test]
+  fn lists_devices_registered_before_and_after_it() {
+    let mut device_array = crate::headless::headless_devices();
+    device_array
+      .register_device(Box::new(FileSinkDevice::default()))
+      .unwrap();
+
+    // the five headless devices, the info device itself, then the sink
+    assert_eq!(device_array.get(0x800000b0).unwrap().unwrap(), 7);
+    let mut kinds = Vec::new();
+    for index in 0..7 {
+      device_array.set(0x800000b1, index).unwrap().unwrap();
+      kinds.push(device_array.get(0x800000b2).unwrap().unwrap());
+    }
+    assert_eq!(kinds[0], DeviceKind::Lcd.id());
+    assert_eq!(kinds[5], DeviceKind::Info.id());
+    assert_eq!(kinds[6], DeviceKind::FileSink.id());
+
+    device_array.set(0x800000b1, 7).unwrap().unwrap();
+    assert!(matches!(
+      device_array.get(0x800000b2).unwrap(),
+      Err(DeviceError::Unreadable)
+    ));
+    assert!(matches!(
+      device_array.set(0x800000b0, 1).unwrap(),
+      Err(DeviceError::Unwritable)
+    ));
+  }
+}
@@ -1,17 +1,30 @@
+use std::collections::VecDeque;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use color_eyre::eyre;
-use tokio::sync::{watch, Mutex, Notify};
+use tokio::{
+  sync::{mpsc, watch, Mutex, Notify},
+  task::AbortHandle,
+};
 
 use crate::sdlcore::SdlKbdEvent;
 
-use super::{DeviceError, DeviceFrame};
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// Raised on every key event while 0x80000023 is non-zero. Taking the
+/// interrupt clears the enable register; the handler re-enables it when it is
+/// ready for the next key.
+pub const KBD_INTERRUPT: u32 = 0;
 
 pub struct KbdDevice {
   turn: Arc<AtomicBool>,
   notify: Arc<Notify>,
   event: Arc<Mutex<SdlKbdEvent>>,
+  irq_enabled: Arc<AtomicBool>,
+  irq_pending: Arc<AtomicBool>,
+  /// The handshake and interrupt listeners, which never finish on their own.
+  listeners: Vec<AbortHandle>,
 }
 
 impl KbdDevice {
@@ -19,11 +32,15 @@ impl KbdDevice {
     let event = Arc::new(Mutex::new(*kbd_ev_rx.borrow()));
     let notify = Arc::new(Notify::new());
     let turn = Arc::new(AtomicBool::new(false));
+    let irq_enabled = Arc::new(AtomicBool::new(false));
+    let irq_pending = Arc::new(AtomicBool::new(false));
+
+    let mut irq_kbd_ev_rx = kbd_ev_rx.clone();
 
     let remote_event_handle = event.clone();
     let remote_notify = notify.clone();
     let remote_turn = turn.clone();
-    tokio::spawn(async move {
+    let handshake = tokio::spawn(async move {
       loop {
         tracing::info!("kbd awaiting notification");
         remote_notify.notified().await;
@@ -39,17 +56,48 @@ impl KbdDevice {
       eyre::Result::<()>::Ok(())
     });
 
+    let remote_event_handle = event.clone();
+    let remote_turn = turn.clone();
+    let remote_irq_enabled = irq_enabled.clone();
+    let remote_irq_pending = irq_pending.clone();
+    let interrupts = tokio::spawn(async move {
+      loop {
+        irq_kbd_ev_rx.changed().await?;
+        // a polled handshake in flight owns the latch
+        if !remote_irq_enabled.load(Ordering::Acquire)
+          || remote_turn.load(Ordering::Acquire)
+        {
+          continue;
+        }
+
+        tracing::info!("kbd raising interrupt");
+        let event = *irq_kbd_ev_rx.borrow();
+        *remote_event_handle.lock().await = event;
+        remote_irq_pending.store(true, Ordering::Release);
+      }
+
+      #[allow(unreachable_code)]
+      eyre::Result::<()>::Ok(())
+    });
+
     KbdDevice {
       notify,
       event,
       turn,
+      irq_enabled,
+      irq_pending,
+      listeners: vec![handshake.abort_handle(), interrupts.abort_handle()],
     }
   }
 }
 
 impl DeviceFrame for KbdDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Kbd
+  }
+
   fn registers(&self) -> &'static [u32] {
-    &[0x80000020, 0x80000021, 0x80000022]
+    &[0x80000020, 0x80000021, 0x80000022, 0x80000023]
   }
 
   fn set(
@@ -57,7 +105,10 @@ impl DeviceFrame for KbdDevice {
     register: u32,
     value: i32,
   ) -> Result<bool, super::DeviceError> {
-    if self.turn.load(Ordering::SeqCst) {
+    if register == 0x80000023 {
+      self.irq_enabled.store(value != 0, Ordering::Release);
+      Ok(false)
+    } else if self.turn.load(Ordering::SeqCst) {
       Err(DeviceError::Busy)
     } else if register != 0x80000020 || value != 1 {
       Err(DeviceError::Unwritable)
@@ -71,6 +122,8 @@ impl DeviceFrame for KbdDevice {
   fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
     if register == 0x80000020 {
       return Ok(self.turn.load(Ordering::Relaxed) as i32);
+    } else if register == 0x80000023 {
+      return Ok(self.irq_enabled.load(Ordering::Relaxed) as i32);
     } else if self.turn.load(Ordering::Acquire) {
       return Err(DeviceError::Busy);
     }
@@ -82,4 +135,230 @@ impl DeviceFrame for KbdDevice {
       _ => unreachable!(),
     }
   }
+
+  fn take_interrupt(&mut self) -> Option<u32> {
+    if self.irq_pending.swap(false, Ordering::AcqRel) {
+      self.irq_enabled.store(false, Ordering::Release);
+      Some(KBD_INTERRUPT)
+    } else {
+      None
+    }
+  }
+
+  fn shutdown(&mut self) -> Vec<tokio::task::JoinHandle<()>> {
+    for listener in self.listeners.iter() {
+      listener.abort();
+    }
+    Vec::new()
+  }
+}
+
+/// Key presses typed into the terminal UI while it is capturing input,
+/// delivered as ASCII codes. 0x80000024 reads the number of queued keys and
+/// 0x80000025 pops the oldest one (`Busy` while the queue is empty).
+pub struct TermKbdDevice {
+  key_rx: mpsc::UnboundedReceiver<u8>,
+  queued: VecDeque<u8>,
+}
+
+impl TermKbdDevice {
+  pub fn new() -> (Self, mpsc::UnboundedSender<u8>) {
+    let (key_tx, key_rx) = mpsc::unbounded_channel();
+    (
+      TermKbdDevice {
+        key_rx,
+        queued: VecDeque::new(),
+      },
+      key_tx,
+    )
+  }
+}
+
+impl DeviceFrame for TermKbdDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::TermKbd
+  }
+
+  fn registers(&self) -> &'static [u32] {
+    &[0x80000024, 0x80000025]
+  }
+
+  fn set(&mut self, _register: u32, _value: i32) -> Result<bool, DeviceError> {
+    Err(DeviceError::Unwritable)
+  }
+
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    while let Ok(key) = self.key_rx.try_recv() {
+      self.queued.push_back(key);
+    }
+
+    match register {
+      0x80000024 => Ok(self.queued.len() as i32),
+      0x80000025 => self
+        .queued
+        .pop_front()
+        .map(|key| key as i32)
+        .ok_or(DeviceError::Busy),
+      _ => unreachable!(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::core::{step, Environment, StepFatal, INTERRUPT_VECTOR_BASE};
+  use crate::devices::DeviceArray;
+  use crate::sched::Scheduler;
+  use sdl3::keyboard::Keycode;
+
+  #[test]
+  fn handshake_latches_the_next_key() {
+    let scheduler = Scheduler::new();
+    let (kbd_ev_tx, kbd_ev_rx) = watch::channel(SdlKbdEvent::default());
+    let mut kbd = scheduler.enter(|| KbdDevice::init(kbd_ev_rx));
+    // the listener has to be waiting before it can be notified
+    scheduler.settle();
+
+    kbd.set(0x80000020, 1).unwrap();
+    assert_eq!(kbd.get(0x80000020).unwrap(), 1);
+    assert!(matches!(kbd.get(0x80000022), Err(DeviceError::Busy)));
+    assert!(matches!(kbd.set(0x80000020, 1), Err(DeviceError::Busy)));
+
+    // no key yet, so however long it runs the turn stays raised
+    scheduler.settle();
+    assert_eq!(kbd.get(0x80000020).unwrap(), 1);
+
+    kbd_ev_tx
+      .send(SdlKbdEvent {
+        down: true,
+        keycode: Keycode::B,
+      })
+      .unwrap();
+    scheduler.settle();
+    assert_eq!(kbd.get(0x80000020).unwrap(), 0);
+    assert_eq!(kbd.get(0x80000021).unwrap(), 1);
+    assert_eq!(kbd.get(0x80000022).unwrap(), Keycode::B as i32);
+
+    // a key that arrives between handshakes isn't latched
+    kbd_ev_tx
+      .send(SdlKbdEvent {
+        down: false,
+        keycode: Keycode::B,
+      })
+      .unwrap();
+    scheduler.settle();
+    assert_eq!(kbd.get(0x80000021).unwrap(), 1);
+
+    kbd.shutdown();
+  }
+
+  #[test]
+  fn terminal_keys_queue_until_read() {
+    let (mut term_kbd, term_keys) = TermKbdDevice::new();
+    // the executor forwards keys typed into the TUI through the recorder
+    let mut inputs = crate::replay::InputRecorder::new(term_keys);
+    assert_eq!(term_kbd.get(0x80000024).unwrap(), 0);
+    assert!(matches!(term_kbd.get(0x80000025), Err(DeviceError::Busy)));
+
+    inputs.record(b'h');
+    inputs.record(b'i');
+    assert_eq!(term_kbd.get(0x80000024).unwrap(), 2);
+    assert_eq!(term_kbd.get(0x80000025).unwrap(), b'h' as i32);
+    assert_eq!(term_kbd.get(0x80000025).unwrap(), b'i' as i32);
+    assert_eq!(term_kbd.get(0x80000024).unwrap(), 0);
+    assert!(matches!(
+      term_kbd.set(0x80000025, 1),
+      Err(DeviceError::Unwritable)
+    ));
+  }
+
+  const ENABLE: i32 = 0x80000023u32 as i32;
+  const KEYCODE: i32 = 0x80000022u32 as i32;
+
+  /// A machine that enables the keyboard interrupt at 0 and spins at 4,
+  /// with the kbd device registered and the interrupt vector set to
+  /// `handler`.
+  fn interrupt_machine(
+    scheduler: &Scheduler,
+    handler: i32,
+  ) -> (Environment, DeviceArray, watch::Sender<SdlKbdEvent>) {
+    let (kbd_ev_tx, kbd_ev_rx) = watch::channel(SdlKbdEvent::default());
+    let mut device_array = DeviceArray::default();
+    device_array
+      .register_device(Box::new(scheduler.enter(|| KbdDevice::init(kbd_ev_rx))))
+      .unwrap();
+    let mut env = Environment::default();
+    env.memory[..8].copy_from_slice(&[5, ENABLE, 100, 0, 13, 4, 101, 101]);
+    env.memory[100] = 1;
+    env.memory[INTERRUPT_VECTOR_BASE] = handler;
+    scheduler.settle();
+    (env, device_array, kbd_ev_tx)
+  }
+
+  fn press(
+    scheduler: &Scheduler,
+    kbd_ev_tx: &watch::Sender<SdlKbdEvent>,
+    keycode: Keycode,
+  ) {
+    kbd_ev_tx
+      .send(SdlKbdEvent {
+        down: true,
+        keycode,
+      })
+      .unwrap();
+    scheduler.settle();
+  }
+
+  #[test]
+  fn keypress_runs_the_handler_with_interrupts_masked() {
+    let scheduler = Scheduler::new();
+    let (mut env, mut device_array, kbd_ev_tx) =
+      interrupt_machine(&scheduler, 40);
+    // re-enable, read the key into m[50] and return
+    let link = (INTERRUPT_VECTOR_BASE + 1) as i32;
+    env.memory[40..44].copy_from_slice(&[5, ENABLE, 100, 0]);
+    env.memory[44..48].copy_from_slice(&[5, 50, KEYCODE, 0]);
+    env.memory[48..52].copy_from_slice(&[17, link, 0, 0]);
+
+    step(&mut env, &mut device_array).unwrap();
+    step(&mut env, &mut device_array).unwrap();
+    assert_eq!(env.iar, 4);
+
+    press(&scheduler, &kbd_ev_tx, Keycode::B);
+    let report = step(&mut env, &mut device_array).unwrap();
+    assert_eq!(report.interrupt, Some(KBD_INTERRUPT));
+    assert_eq!((env.iar, env.memory[link as usize]), (40, 4));
+
+    // a key while the handler runs waits for it to return
+    step(&mut env, &mut device_array).unwrap();
+    press(&scheduler, &kbd_ev_tx, Keycode::C);
+    let report = step(&mut env, &mut device_array).unwrap();
+    assert_eq!(report.interrupt, None);
+    assert_eq!(env.memory[50], Keycode::C as i32);
+    step(&mut env, &mut device_array).unwrap();
+    assert_eq!((env.iar, env.handling), (4, None));
+
+    let report = step(&mut env, &mut device_array).unwrap();
+    assert_eq!(report.interrupt, Some(KBD_INTERRUPT));
+    assert_eq!(env.iar, 40);
+
+    device_array.shutdown();
+  }
+
+  #[test]
+  fn interrupt_without_a_handler_faults() {
+    let scheduler = Scheduler::new();
+    let (mut env, mut device_array, kbd_ev_tx) =
+      interrupt_machine(&scheduler, 0);
+
+    step(&mut env, &mut device_array).unwrap();
+    press(&scheduler, &kbd_ev_tx, Keycode::B);
+    assert!(matches!(
+      step(&mut env, &mut device_array),
+      Err(StepFatal::UnhandledInterrupt { irq: KBD_INTERRUPT })
+    ));
+
+    device_array.shutdown();
+  }
 }
@@ -0,0 +1,173 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use color_eyre::eyre;
+use sdl3::mouse::MouseButton;
+use tokio::{
+  sync::{watch, Mutex, Notify},
+  task::AbortHandle,
+};
+
+use crate::sdlcore::SdlMouseEvent;
+
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// Where the mouse is, which buttons are held (bit 0 left, 1 middle, 2 right,
+/// 3 and 4 the side buttons) and how far it has moved since the last latch.
+#[derive(Debug, Clone, Copy, Default)]
+struct MouseState {
+  x: i32,
+  y: i32,
+  buttons: i32,
+  dx: i32,
+  dy: i32,
+}
+
+impl MouseState {
+  fn apply(&mut self, event: SdlMouseEvent) {
+    match event {
+      SdlMouseEvent::Motion { x, y, dx, dy } => {
+        self.x = x;
+        self.y = y;
+        self.dx += dx;
+        self.dy += dy;
+      }
+      SdlMouseEvent::Button {
+        x,
+        y,
+        down,
+        mouse_btn,
+      } => {
+        self.x = x;
+        self.y = y;
+        let bit = match mouse_btn {
+          MouseButton::Left => 1,
+          MouseButton::Middle => 2,
+          MouseButton::Right => 4,
+          MouseButton::X1 => 8,
+          MouseButton::X2 => 16,
+          _ => 0,
+        };
+        if down {
+          self.buttons |= bit;
+        } else {
+          self.buttons &= !bit;
+        }
+      }
+    }
+  }
+}
+
+/// The mouse over the VGA window, read with the same handshake as
+/// `KbdDevice`: writing 1 to 0x80000050 asks for the state after the next
+/// mouse event and reads back 1 until it has been latched. The latched X
+/// and Y (0x80000051, 0x80000052), buttons (0x80000053) and motion since the
+/// previous latch (0x80000054, 0x80000055) are `Busy` in the meantime.
+pub struct MouseDevice {
+  turn: Arc<AtomicBool>,
+  notify: Arc<Notify>,
+  latched: Arc<Mutex<MouseState>>,
+  /// The tracking and handshake listeners, which never finish on their own.
+  listeners: Vec<AbortHandle>,
+}
+
+impl MouseDevice {
+  pub fn init(mut mouse_ev_rx: watch::Receiver<SdlMouseEvent>) -> Self {
+    let live = Arc::new(Mutex::new(MouseState::default()));
+    let latched = Arc::new(Mutex::new(MouseState::default()));
+    let notify = Arc::new(Notify::new());
+    let turn = Arc::new(AtomicBool::new(false));
+
+    // every event counts towards the state, latched or not, so no button
+    // release or motion is lost between polls
+    let (changed_tx, mut changed_rx) = watch::channel(());
+    let remote_live = live.clone();
+    let tracking = tokio::spawn(async move {
+      loop {
+        mouse_ev_rx.changed().await?;
+        let event = *mouse_ev_rx.borrow();
+        remote_live.lock().await.apply(event);
+        changed_tx.send(())?;
+      }
+
+      #[allow(unreachable_code)]
+      eyre::Result::<()>::Ok(())
+    });
+
+    let remote_latched = latched.clone();
+    let remote_notify = notify.clone();
+    let remote_turn = turn.clone();
+    let handshake = tokio::spawn(async move {
+      loop {
+        remote_notify.notified().await;
+        changed_rx.borrow_and_update();
+        changed_rx.changed().await?;
+        let mut live = live.lock().await;
+        *remote_latched.lock().await = *live;
+        live.dx = 0;
+        live.dy = 0;
+        std::mem::drop(live);
+        remote_turn.store(false, Ordering::SeqCst);
+      }
+
+      #[allow(unreachable_code)]
+      eyre::Result::<()>::Ok(())
+    });
+
+    MouseDevice {
+      turn,
+      notify,
+      latched,
+      listeners: vec![tracking.abort_handle(), handshake.abort_handle()],
+    }
+  }
+}
+
+impl DeviceFrame for MouseDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Mouse
+  }
+
+  fn registers(&self) -> &'static [u32] {
+    &[
+      0x80000050, 0x80000051, 0x80000052, 0x80000053, 0x80000054, 0x80000055,
+    ]
+  }
+
+  fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError> {
+    if self.turn.load(Ordering::SeqCst) {
+      Err(DeviceError::Busy)
+    } else if register != 0x80000050 || value != 1 {
+      Err(DeviceError::Unwritable)
+    } else {
+      self.turn.store(true, Ordering::SeqCst);
+      self.notify.notify_waiters();
+      Ok(false)
+    }
+  }
+
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    if register == 0x80000050 {
+      return Ok(self.turn.load(Ordering::Relaxed) as i32);
+    } else if self.turn.load(Ordering::Acquire) {
+      return Err(DeviceError::Busy);
+    }
+
+    let guard = self.latched.try_lock().map_err(|_| DeviceError::Busy)?;
+    match register {
+      0x80000051 => Ok(guard.x),
+      0x80000052 => Ok(guard.y),
+      0x80000053 => Ok(guard.buttons),
+      0x80000054 => Ok(guard.dx),
+      0x80000055 => Ok(guard.dy),
+      _ => unreachable!(),
+    }
+  }
+
+  fn shutdown(&mut self) -> Vec<tokio::task::JoinHandle<()>> {
+    for listener in self.listeners.iter() {
+      listener.abort();
+    }
+    Vec::new()
+  }
+}
@@ -1,23 +1,78 @@
 use std::sync::{
-  atomic::{AtomicBool, AtomicU16, Ordering},
-  Arc,
+  atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering},
+  Arc, Mutex,
 };
 
-use tokio::sync::Mutex;
+use color_eyre::eyre;
 
-use super::{DeviceError, DeviceFrame};
+use super::{DeviceError, DeviceFrame, DeviceKind};
 
-pub struct LEDDisplayDevice {}
+/// Number of LEDs in the bank, lit by the low bits of 0x80000005.
+pub const LED_COUNT: u32 = 18;
 
+#[derive(Default)]
+pub struct LEDDisplayDevice {
+  /// Bit `n` lights LED `n`; bits from `LED_COUNT` up are dropped.
+  pub leds: Arc<AtomicU32>,
+}
+
+impl DeviceFrame for LEDDisplayDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Led
+  }
+
+  fn registers(&self) -> &'static [u32] {
+    &[0x80000005]
+  }
+
+  fn set(&mut self, _register: u32, value: i32) -> Result<bool, DeviceError> {
+    let mask = (1 << LED_COUNT) - 1;
+    self.leds.store(value as u32 & mask, Ordering::Relaxed);
+    Ok(true)
+  }
+
+  fn get(&mut self, _register: u32) -> Result<i32, DeviceError> {
+    Err(DeviceError::Unreadable)
+  }
+
+  fn snapshot(&self) -> Vec<u8> {
+    self.leds.load(Ordering::Relaxed).to_le_bytes().to_vec()
+  }
+
+  fn restore(&mut self, data: &[u8]) {
+    if let Ok(leds) = data.try_into() {
+      self.leds.store(u32::from_le_bytes(leds), Ordering::Relaxed);
+    }
+  }
+}
+
+/// Two four-digit displays at 0x80000003 and 0x80000004. Writing non-zero to
+/// 0x80000006 or 0x80000007 shows the matching display in decimal instead of
+/// hex, and zero switches it back.
 #[derive(Default)]
 pub struct HexDisplayDevice {
   pub hex0: Arc<AtomicU16>,
   pub hex1: Arc<AtomicU16>,
+  pub decimal0: Arc<AtomicBool>,
+  pub decimal1: Arc<AtomicBool>,
+}
+
+/// How a hex display shows `value` in the given mode.
+pub fn format_hex(value: u16, decimal: bool) -> String {
+  if decimal {
+    format!("{value}")
+  } else {
+    format!("{value:04x}")
+  }
 }
 
 impl DeviceFrame for HexDisplayDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Hex
+  }
+
   fn registers(&self) -> &'static [u32] {
-    &[0x80000003, 0x80000004]
+    &[0x80000003, 0x80000004, 0x80000006, 0x80000007]
   }
 
   fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError> {
@@ -28,29 +83,123 @@ impl DeviceFrame for HexDisplayDevice {
       0x80000004 => {
         self.hex1.store(value as u16, Ordering::Relaxed);
       }
+      0x80000006 => self.decimal0.store(value != 0, Ordering::Relaxed),
+      0x80000007 => self.decimal1.store(value != 0, Ordering::Relaxed),
       _ => unreachable!(),
     }
 
     Ok(true)
   }
 
-  fn get(&mut self, _register: u32) -> Result<i32, DeviceError> {
-    Err(DeviceError::Unreadable)
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    match register {
+      0x80000006 => Ok(self.decimal0.load(Ordering::Relaxed) as i32),
+      0x80000007 => Ok(self.decimal1.load(Ordering::Relaxed) as i32),
+      _ => Err(DeviceError::Unreadable),
+    }
+  }
+
+  /// Both values, then both modes.
+  fn snapshot(&self) -> Vec<u8> {
+    let mut data = [&self.hex0, &self.hex1]
+      .iter()
+      .flat_map(|hex| hex.load(Ordering::Relaxed).to_le_bytes())
+      .collect::<Vec<_>>();
+    data.push(self.decimal0.load(Ordering::Relaxed) as u8);
+    data.push(self.decimal1.load(Ordering::Relaxed) as u8);
+    data
+  }
+
+  fn restore(&mut self, data: &[u8]) {
+    // snapshots from before the decimal mode stop after the values
+    let (h0, h1, h2, h3, d0, d1) = match *data {
+      [h0, h1, h2, h3] => (h0, h1, h2, h3, 0, 0),
+      [h0, h1, h2, h3, d0, d1] => (h0, h1, h2, h3, d0, d1),
+      _ => return,
+    };
+    self.decimal0.store(d0 != 0, Ordering::Relaxed);
+    self.decimal1.store(d1 != 0, Ordering::Relaxed);
+    self
+      .hex0
+      .store(u16::from_le_bytes([h0, h1]), Ordering::Relaxed);
+    self
+      .hex1
+      .store(u16::from_le_bytes([h2, h3]), Ordering::Relaxed);
   }
 }
 
+/// Characters per line of the LCD.
+const LCD_COLUMNS: usize = 14;
+
+/// A 14x2 character display. Writing 1 to 0x80000010 commits the character
+/// in 0x80000013 at the cursor (0x80000011, 0x80000012), and 2 blanks the
+/// display and homes the cursor. While 0x80000014 is non-zero, writing
+/// 0x80000013 puts the character straight at the cursor and moves it on,
+/// wrapping to the other line, so text goes out one write per character.
+///
+/// Every write lands on the display before `set` returns, so characters show
+/// up in the order the program wrote them.
 #[derive(Default)]
 pub struct LcdDisplayDevice {
-  turn: bool,
   x: usize,
   y: usize,
   chr: char,
+  advance: bool,
   pub lcd: Arc<Mutex<[[char; 14]; 2]>>,
+  /// What each character code shows as, for programs written against a
+  /// display ROM. Codes map straight to Latin-1 without one.
+  charmap: Option<Box<[char; 256]>>,
+}
+
+impl LcdDisplayDevice {
+  /// Runs `f` on the display, waiting out the UI if it's reading it.
+  fn update(&self, f: impl FnOnce(&mut [[char; LCD_COLUMNS]; 2])) {
+    f(&mut self.lcd.lock().unwrap());
+  }
+
+  pub fn with_charmap(charmap: [char; 256]) -> Self {
+    LcdDisplayDevice {
+      charmap: Some(Box::new(charmap)),
+      ..Default::default()
+    }
+  }
+}
+
+/// Reads a character table of one `code glyph` pair per line, with the code
+/// in decimal or `0x` hex. Codes that aren't listed keep their Latin-1
+/// character.
+pub fn parse_charmap(text: &str) -> eyre::Result<[char; 256]> {
+  let mut charmap = std::array::from_fn(|code| code as u8 as char);
+  for (index, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+
+    let (code, glyph) =
+      line.split_once(char::is_whitespace).ok_or_else(|| {
+        eyre::eyre!("line {}: expected `code glyph`", index + 1)
+      })?;
+    let code = match code.strip_prefix("0x") {
+      Some(hex) => u8::from_str_radix(hex, 16)?,
+      None => code.parse::<u8>()?,
+    };
+    let mut glyph = glyph.trim().chars();
+    let (Some(chr), None) = (glyph.next(), glyph.next()) else {
+      eyre::bail!("line {}: glyph must be a single character", index + 1);
+    };
+    charmap[code as usize] = chr;
+  }
+  Ok(charmap)
 }
 
 impl DeviceFrame for LcdDisplayDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Lcd
+  }
+
   fn registers(&self) -> &'static [u32] {
-    &[0x80000010, 0x80000011, 0x80000012, 0x80000013]
+    &[0x80000010, 0x80000011, 0x80000012, 0x80000013, 0x80000014]
   }
 
   fn set(
@@ -58,26 +207,45 @@ impl DeviceFrame for LcdDisplayDevice {
     register: u32,
     value: i32,
   ) -> Result<bool, super::DeviceError> {
-    if self.turn {
-      return Err(DeviceError::Busy);
-    }
-
     match register {
       0x80000010 => {
         if value == 0 {
           return Err(DeviceError::Unwritable);
+        } else if value == 2 {
+          self.update(|lcd| *lcd = [[' '; LCD_COLUMNS]; 2]);
+          (self.x, self.y) = (0, 0);
         } else {
-          let lcd_handle = self.lcd.clone();
+          // columns past the end are off the display
           let (x, y, chr) = (self.x, self.y, self.chr);
-          tokio::spawn(async move {
-            let mut guard = lcd_handle.lock().await;
-            guard[y][x] = chr;
+          self.update(|lcd| {
+            if let Some(cell) = lcd[y].get_mut(x) {
+              *cell = chr;
+            }
           });
         }
       }
       0x80000011 => self.x = value as usize & 0xf,
       0x80000012 => self.y = value as usize & 0x1,
-      0x80000013 => self.chr = (value & 0xff) as u8 as char,
+      0x80000013 => {
+        let code = (value & 0xff) as u8;
+        self.chr = match &self.charmap {
+          Some(charmap) => charmap[code as usize],
+          None => code as char,
+        };
+        if self.advance {
+          // a cursor set past the end starts the next line
+          if self.x >= LCD_COLUMNS {
+            (self.x, self.y) = (0, self.y ^ 1);
+          }
+          let (x, y, chr) = (self.x, self.y, self.chr);
+          self.update(|lcd| lcd[y][x] = chr);
+          self.x += 1;
+          if self.x == LCD_COLUMNS {
+            (self.x, self.y) = (0, self.y ^ 1);
+          }
+        }
+      }
+      0x80000014 => self.advance = value != 0,
       _ => unreachable!(),
     }
 
@@ -86,8 +254,141 @@ impl DeviceFrame for LcdDisplayDevice {
 
   fn get(&mut self, register: u32) -> Result<i32, super::DeviceError> {
     match register {
-      0x80000010 => Ok(self.turn as i32),
+      // writes finish inside `set`, so there's never one outstanding
+      0x80000010 => Ok(0),
+      0x80000014 => Ok(self.advance as i32),
       _ => Err(DeviceError::Unreadable),
     }
   }
+
+  /// Cursor, pending character and advance mode, followed by the display
+  /// contents.
+  fn snapshot(&self) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(self.x as u32).to_le_bytes());
+    data.extend_from_slice(&(self.y as u32).to_le_bytes());
+    data.extend_from_slice(&(self.chr as u32).to_le_bytes());
+    data.extend_from_slice(&(self.advance as u32).to_le_bytes());
+    for chr in self.lcd.lock().unwrap().iter().flatten() {
+      data.extend_from_slice(&(*chr as u32).to_le_bytes());
+    }
+    data
+  }
+
+  fn restore(&mut self, data: &[u8]) {
+    let words = data
+      .chunks_exact(4)
+      .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+      .collect::<Vec<_>>();
+    let [x, y, chr, rest @ ..] = words.as_slice() else {
+      return;
+    };
+    // snapshots from before the advance mode hold 0 or 28 more words
+    let (advance, contents) = match rest {
+      [advance, contents @ ..] if rest.len() % 2 == 1 => (*advance, contents),
+      contents => (0, contents),
+    };
+
+    self.x = *x as usize & 0xf;
+    self.y = *y as usize & 0x1;
+    self.chr = char::from_u32(*chr).unwrap_or_default();
+    self.advance = advance != 0;
+    let mut lcd = self.lcd.lock().unwrap();
+    for (cell, chr) in lcd.iter_mut().flatten().zip(contents.iter()) {
+      *cell = char::from_u32(*chr).unwrap_or_default();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn row(lcd: &LcdDisplayDevice, y: usize) -> String {
+    lcd.lcd.lock().unwrap()[y].iter().collect()
+  }
+
+  #[test]
+  fn writes_land_in_order_while_the_ui_reads() {
+    let mut lcd = LcdDisplayDevice::default();
+    let display = lcd.lcd.clone();
+    let reader = std::thread::spawn(move || {
+      for _ in 0..1000 {
+        let _ = *display.lock().unwrap();
+      }
+    });
+
+    for (x, chr) in "HELLO".bytes().enumerate() {
+      lcd.set(0x80000011, x as i32).unwrap();
+      lcd.set(0x80000013, chr as i32).unwrap();
+      lcd.set(0x80000010, 1).unwrap();
+    }
+    reader.join().unwrap();
+    assert!(row(&lcd, 0).starts_with("HELLO"));
+  }
+
+  #[test]
+  fn clear_blanks_the_display_and_homes_the_cursor() {
+    let mut lcd = LcdDisplayDevice::default();
+    lcd.set(0x80000014, 1).unwrap();
+    lcd.set(0x80000011, 5).unwrap();
+    lcd.set(0x80000012, 1).unwrap();
+    lcd.set(0x80000013, 'A' as i32).unwrap();
+
+    lcd.set(0x80000010, 2).unwrap();
+    assert_eq!(row(&lcd, 0), " ".repeat(LCD_COLUMNS));
+    assert_eq!(row(&lcd, 1), " ".repeat(LCD_COLUMNS));
+    lcd.set(0x80000013, 'B' as i32).unwrap();
+    assert!(row(&lcd, 0).starts_with('B'));
+  }
+
+  #[test]
+  fn advance_wraps_onto_the_other_line() {
+    let mut lcd = LcdDisplayDevice::default();
+    lcd.set(0x80000010, 2).unwrap();
+    lcd.set(0x80000014, 1).unwrap();
+
+    // the last column wraps after its write
+    lcd.set(0x80000011, 13).unwrap();
+    lcd.set(0x80000013, 'A' as i32).unwrap();
+    lcd.set(0x80000013, 'B' as i32).unwrap();
+    assert!(row(&lcd, 0).ends_with('A'));
+    assert!(row(&lcd, 1).starts_with('B'));
+
+    // a cursor set past the end wraps before it, back to the top line
+    lcd.set(0x80000011, 15).unwrap();
+    lcd.set(0x80000013, 'C' as i32).unwrap();
+    assert!(row(&lcd, 0).starts_with('C'));
+  }
+
+  #[test]
+  fn turn_reads_low_once_the_commit_has_landed() {
+    let mut lcd = LcdDisplayDevice::default();
+    lcd.set(0x80000013, 'Z' as i32).unwrap();
+    lcd.set(0x80000010, 1).unwrap();
+    // a program polling for completion sees the character already there
+    assert_eq!(lcd.get(0x80000010).unwrap(), 0);
+    assert!(row(&lcd, 0).starts_with('Z'));
+
+    lcd.set(0x80000011, 1).unwrap();
+    lcd.set(0x80000010, 1).unwrap();
+    assert!(row(&lcd, 0).starts_with("ZZ"));
+  }
+
+  #[test]
+  fn charmap_translates_codes_as_they_are_written() {
+    let charmap = parse_charmap("0x01 ♥\n\n65 a\n").unwrap();
+    assert_eq!(charmap[1], '♥');
+    assert_eq!(charmap[b'A' as usize], 'a');
+    assert_eq!(charmap[b'B' as usize], 'B');
+    assert!(parse_charmap("300 x").is_err());
+    assert!(parse_charmap("65 ab").is_err());
+
+    let mut lcd = LcdDisplayDevice::with_charmap(charmap);
+    lcd.set(0x80000014, 1).unwrap();
+    for code in [1, 'A' as i32, 'B' as i32] {
+      lcd.set(0x80000013, code).unwrap();
+    }
+    assert!(row(&lcd, 0).starts_with("♥aB"));
+  }
 }
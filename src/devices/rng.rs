@@ -0,0 +1,73 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// xorshift gets stuck at zero, so a zero seed is swapped for this.
+const ZERO_SEED: u32 = 0x9e3779b9;
+
+/// Pseudo-random numbers from a 32-bit xorshift generator. The same seed
+/// always gives the same sequence, so a run can be made reproducible.
+pub struct RngDevice {
+  state: u32,
+}
+
+impl RngDevice {
+  /// Seeded from the clock; programs that want a fixed sequence write their
+  /// own seed.
+  pub fn new() -> Self {
+    let nanos = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |time| time.subsec_nanos() ^ time.as_secs() as u32);
+    RngDevice::seeded(nanos)
+  }
+
+  pub fn seeded(seed: u32) -> Self {
+    RngDevice {
+      state: if seed == 0 { ZERO_SEED } else { seed },
+    }
+  }
+}
+
+impl DeviceFrame for RngDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Rng
+  }
+
+  /// 0x80000040 reads the next number; writing a seed to 0x80000041 restarts
+  /// the sequence from it.
+  fn registers(&self) -> &'static [u32] {
+    &[0x80000040, 0x80000041]
+  }
+
+  fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError> {
+    match register {
+      0x80000041 => *self = RngDevice::seeded(value as u32),
+      _ => return Err(DeviceError::Unwritable),
+    }
+
+    Ok(false)
+  }
+
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    match register {
+      0x80000040 => {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        Ok(self.state as i32)
+      }
+      0x80000041 => Err(DeviceError::Unreadable),
+      _ => unreachable!(),
+    }
+  }
+
+  fn snapshot(&self) -> Vec<u8> {
+    self.state.to_le_bytes().to_vec()
+  }
+
+  fn restore(&mut self, data: &[u8]) {
+    if let Ok(state) = data.try_into() {
+      *self = RngDevice::seeded(u32::from_le_bytes(state));
+    }
+  }
+}
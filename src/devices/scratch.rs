@@ -0,0 +1,77 @@
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// First of the scratch registers.
+pub const SCRATCH_BASE: u32 = 0x80000080;
+pub const SCRATCH_COUNT: usize = 16;
+
+static REGISTERS: [u32; SCRATCH_COUNT] = {
+  let mut registers = [0; SCRATCH_COUNT];
+  let mut index = 0;
+  while index < SCRATCH_COUNT {
+    registers[index] = SCRATCH_BASE + index as u32;
+    index += 1;
+  }
+  registers
+};
+
+/// A bank of general-purpose read/write registers outside main memory, for
+/// temporaries that shouldn't live in the program's address space.
+#[derive(Default)]
+pub struct ScratchDevice {
+  values: [i32; SCRATCH_COUNT],
+}
+
+impl DeviceFrame for ScratchDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Scratch
+  }
+
+  fn registers(&self) -> &'static [u32] {
+    &REGISTERS
+  }
+
+  fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError> {
+    self.values[(register - SCRATCH_BASE) as usize] = value;
+    Ok(false)
+  }
+
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    Ok(self.values[(register - SCRATCH_BASE) as usize])
+  }
+
+  fn snapshot(&self) -> Vec<u8> {
+    self
+      .values
+      .iter()
+      .flat_map(|value| value.to_le_bytes())
+      .collect()
+  }
+
+  fn restore(&mut self, data: &[u8]) {
+    for (value, word) in self.values.iter_mut().zip(data.chunks_exact(4)) {
+      *value = i32::from_le_bytes(word.try_into().unwrap());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn registers_hold_values_across_a_snapshot() {
+    let mut scratch = ScratchDevice::default();
+    let last = SCRATCH_BASE + SCRATCH_COUNT as u32 - 1;
+    assert_eq!(scratch.registers().first(), Some(&SCRATCH_BASE));
+    assert_eq!(scratch.registers().last(), Some(&last));
+
+    scratch.set(SCRATCH_BASE, -4).unwrap();
+    scratch.set(last, 9).unwrap();
+    assert_eq!(scratch.get(SCRATCH_BASE).unwrap(), -4);
+    assert_eq!(scratch.get(last).unwrap(), 9);
+
+    let mut restored = ScratchDevice::default();
+    restored.restore(&scratch.snapshot());
+    assert_eq!(restored.values, scratch.values);
+  }
+}
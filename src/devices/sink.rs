@@ -0,0 +1,117 @@
+use std::{
+  fs::{File, OpenOptions},
+  io::{BufWriter, Write},
+};
+
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// Streams bytes from the program into a file on the host.
+///
+/// Writing a character to 0x80000090 appends it to the path (writing 0
+/// clears it), and any non-zero write to 0x80000091 opens that path for
+/// appending. 0x80000092 writes its low byte to the open file and
+/// 0x80000093 flushes and closes it. Host I/O errors are logged and reported
+/// as `Dead`, as is writing with no file open.
+#[derive(Default)]
+pub struct FileSinkDevice {
+  path: String,
+  file: Option<BufWriter<File>>,
+}
+
+impl FileSinkDevice {
+  fn close(&mut self) -> Result<(), DeviceError> {
+    let Some(mut file) = self.file.take() else {
+      return Ok(());
+    };
+    file.flush().map_err(|error| {
+      log::warn!("Failed to flush {:?}: {error}", self.path);
+      DeviceError::Dead
+    })
+  }
+}
+
+impl DeviceFrame for FileSinkDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::FileSink
+  }
+
+  fn registers(&self) -> &'static [u32] {
+    &[0x80000090, 0x80000091, 0x80000092, 0x80000093]
+  }
+
+  fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError> {
+    match register {
+      0x80000090 if value == 0 => self.path.clear(),
+      0x80000090 => {
+        let chr =
+          char::from_u32(value as u32).ok_or(DeviceError::Unwritable)?;
+        self.path.push(chr);
+      }
+      0x80000091 if value == 0 => return Err(DeviceError::Unwritable),
+      0x80000091 => {
+        self.close()?;
+        let file = OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(&self.path)
+          .map_err(|error| {
+            log::warn!("Failed to open {:?}: {error}", self.path);
+            DeviceError::Dead
+          })?;
+        self.file = Some(BufWriter::new(file));
+      }
+      0x80000092 => {
+        let file = self.file.as_mut().ok_or(DeviceError::Dead)?;
+        file.write_all(&[value as u8]).map_err(|error| {
+          log::warn!("Failed to write to {:?}: {error}", self.path);
+          DeviceError::Dead
+        })?;
+      }
+      0x80000093 => self.close()?,
+      _ => unreachable!(),
+    }
+
+    Ok(false)
+  }
+
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    match register {
+      // whether a file is open
+      0x80000091 => Ok(self.file.is_some() as i32),
+      _ => Err(DeviceError::Unreadable),
+    }
+  }
+
+  fn shutdown(&mut self) -> Vec<tokio::task::JoinHandle<()>> {
+    let _ = self.close();
+    Vec::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bytes_stream_into_the_named_file() {
+    let path = std::env::temp_dir()
+      .join(format!("case100-{}-sink.txt", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut sink = FileSinkDevice::default();
+    assert!(matches!(sink.set(0x80000092, 1), Err(DeviceError::Dead)));
+
+    for chr in path.to_str().unwrap().chars() {
+      sink.set(0x80000090, chr as i32).unwrap();
+    }
+    sink.set(0x80000091, 1).unwrap();
+    assert_eq!(sink.get(0x80000091).unwrap(), 1);
+    for byte in b"hi\n" {
+      sink.set(0x80000092, *byte as i32).unwrap();
+    }
+    sink.set(0x80000093, 1).unwrap();
+    assert_eq!(sink.get(0x80000091).unwrap(), 0);
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"hi\n");
+    std::fs::remove_file(path).unwrap();
+  }
+}
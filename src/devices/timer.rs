@@ -0,0 +1,100 @@
+use std::{
+  sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use tokio::task::AbortHandle;
+
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// A millisecond counter, so programs can tell how much time has passed.
+///
+/// 0x80000030 reads the count, which runs from power-on. A non-zero reload
+/// value in 0x80000031 makes it wrap back to 0 on reaching that value rather
+/// than at `u32::MAX`. Writing 0x80000032 resets the count and starts it
+/// (non-zero) or stops it (zero); it reads back whether it's running.
+pub struct TimerDevice {
+  count: Arc<AtomicU32>,
+  reload: Arc<AtomicU32>,
+  running: Arc<AtomicBool>,
+  /// The task counting milliseconds, which never finishes on its own.
+  ticker: AbortHandle,
+}
+
+impl TimerDevice {
+  pub fn init() -> Self {
+    let count = Arc::new(AtomicU32::new(0));
+    let reload = Arc::new(AtomicU32::new(0));
+    let running = Arc::new(AtomicBool::new(true));
+
+    let remote_count = count.clone();
+    let remote_reload = reload.clone();
+    let remote_running = running.clone();
+    let ticker = tokio::spawn(async move {
+      // missed ticks are caught up on, so the count keeps to the clock
+      let mut interval = tokio::time::interval(Duration::from_millis(1));
+      loop {
+        interval.tick().await;
+        if !remote_running.load(Ordering::Acquire) {
+          continue;
+        }
+        let reload = remote_reload.load(Ordering::Relaxed);
+        let _ = remote_count.fetch_update(
+          Ordering::AcqRel,
+          Ordering::Acquire,
+          |count| match count.wrapping_add(1) {
+            next if reload != 0 && next >= reload => Some(0),
+            next => Some(next),
+          },
+        );
+      }
+    });
+
+    TimerDevice {
+      count,
+      reload,
+      running,
+      ticker: ticker.abort_handle(),
+    }
+  }
+}
+
+impl DeviceFrame for TimerDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Timer
+  }
+
+  fn registers(&self) -> &'static [u32] {
+    &[0x80000030, 0x80000031, 0x80000032]
+  }
+
+  fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError> {
+    match register {
+      0x80000031 => self.reload.store(value as u32, Ordering::Relaxed),
+      0x80000032 => {
+        self.running.store(value != 0, Ordering::Release);
+        self.count.store(0, Ordering::Release);
+      }
+      _ => return Err(DeviceError::Unwritable),
+    }
+
+    Ok(false)
+  }
+
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    match register {
+      0x80000030 => Ok(self.count.load(Ordering::Acquire) as i32),
+      0x80000031 => Ok(self.reload.load(Ordering::Relaxed) as i32),
+      0x80000032 => Ok(self.running.load(Ordering::Acquire) as i32),
+      _ => unreachable!(),
+    }
+  }
+
+  fn shutdown(&mut self) -> Vec<tokio::task::JoinHandle<()>> {
+    self.ticker.abort();
+    Vec::new()
+  }
+}
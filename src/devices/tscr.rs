@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use color_eyre::eyre;
+use tokio::{
+  sync::{watch, Mutex, Notify},
+  task::AbortHandle,
+};
+
+use crate::sdlcore::SdlTscrEvent;
+
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// A touchscreen over the VGA window, touched by holding the left mouse
+/// button. Read with the same handshake as `KbdDevice`: writing 1 to
+/// 0x80000070 asks for the next touch event and reads back 1 until it has
+/// arrived, and until then X (0x80000071), Y (0x80000072) and whether the
+/// screen is pressed (0x80000073) are `Busy`.
+pub struct TscrDevice {
+  turn: Arc<AtomicBool>,
+  notify: Arc<Notify>,
+  event: Arc<Mutex<SdlTscrEvent>>,
+  /// The handshake listener, which never finishes on its own.
+  listener: AbortHandle,
+}
+
+impl TscrDevice {
+  pub fn init(mut tscr_ev_rx: watch::Receiver<SdlTscrEvent>) -> Self {
+    let event = Arc::new(Mutex::new(*tscr_ev_rx.borrow()));
+    let notify = Arc::new(Notify::new());
+    let turn = Arc::new(AtomicBool::new(false));
+
+    let remote_event_handle = event.clone();
+    let remote_notify = notify.clone();
+    let remote_turn = turn.clone();
+    let handshake = tokio::spawn(async move {
+      loop {
+        remote_notify.notified().await;
+        let mut guard = remote_event_handle.lock().await;
+        tscr_ev_rx.changed().await?;
+        *guard = *tscr_ev_rx.borrow();
+        std::mem::drop(guard);
+        remote_turn.store(false, Ordering::SeqCst);
+      }
+
+      #[allow(unreachable_code)]
+      eyre::Result::<()>::Ok(())
+    });
+
+    TscrDevice {
+      turn,
+      notify,
+      event,
+      listener: handshake.abort_handle(),
+    }
+  }
+}
+
+impl DeviceFrame for TscrDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Tscr
+  }
+
+  fn registers(&self) -> &'static [u32] {
+    &[0x80000070, 0x80000071, 0x80000072, 0x80000073]
+  }
+
+  fn set(&mut self, register: u32, value: i32) -> Result<bool, DeviceError> {
+    if self.turn.load(Ordering::SeqCst) {
+      Err(DeviceError::Busy)
+    } else if register != 0x80000070 || value != 1 {
+      Err(DeviceError::Unwritable)
+    } else {
+      self.turn.store(true, Ordering::SeqCst);
+      self.notify.notify_waiters();
+      Ok(false)
+    }
+  }
+
+  fn get(&mut self, register: u32) -> Result<i32, DeviceError> {
+    if register == 0x80000070 {
+      return Ok(self.turn.load(Ordering::Relaxed) as i32);
+    } else if self.turn.load(Ordering::Acquire) {
+      return Err(DeviceError::Busy);
+    }
+
+    let guard = self.event.try_lock().map_err(|_| DeviceError::Busy)?;
+    match register {
+      0x80000071 => Ok(guard.x),
+      0x80000072 => Ok(guard.y),
+      0x80000073 => Ok(guard.pressed as i32),
+      _ => unreachable!(),
+    }
+  }
+
+  fn shutdown(&mut self) -> Vec<tokio::task::JoinHandle<()>> {
+    self.listener.abort();
+    Vec::new()
+  }
+}
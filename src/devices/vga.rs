@@ -1,6 +1,34 @@
-use tokio::sync::mpsc;
+use std::{
+  sync::{Arc, RwLock},
+  time::Duration,
+};
 
-use super::{DeviceError, DeviceFrame};
+use tokio::sync::mpsc::{self, error::SendTimeoutError};
+
+use super::{DeviceError, DeviceFrame, DeviceKind};
+
+/// Canvas sizes selectable through the mode register 0x80000067; the current
+/// size reads back from 0x80000068 (width) and 0x80000069 (height).
+/// 0x8000006c reads the colour at (x1, y1), offset by the viewport like a
+/// draw. It stalls the instruction with `Stalled` until every draw sent has
+/// reached the window, and is `Unreadable` off the canvas.
+///
+/// Every draw shows at once unless 0x8000006d is non-zero, which holds
+/// draws back until a write to 0x8000006e presents them all together, for
+/// drawing whole frames without flicker.
+pub const VGA_MODES: [(u32, u32); 2] = [(640, 480), (320, 240)];
+
+/// What a draw does when the SDL side hasn't caught up with the queue.
+///
+/// `Drop` never stalls the program, but a burst larger than the queue fails
+/// with `Dead`. `Block` stalls the executor for up to the timeout waiting for
+/// room, so bursts go through at the cost of latency; a command still
+/// waiting at the deadline fails with `Busy` and can be retried.
+#[derive(Debug, Clone, Copy)]
+pub enum DrawSendMode {
+  Drop,
+  Block(Duration),
+}
 
 pub struct VgaDevice {
   turn: bool,
@@ -10,12 +38,19 @@ pub struct VgaDevice {
   y1: i32,
   y2: i32,
   colour: i32,
+  mode: usize,
+  viewport: (i32, i32),
+  buffered: bool,
   draw_cmd_tx: mpsc::Sender<crate::sdlcore::SdlDrawCommand>,
+  framebuffer: Arc<RwLock<crate::sdlcore::Framebuffer>>,
+  send_mode: DrawSendMode,
 }
 
 impl VgaDevice {
   pub fn new(
     draw_cmd_tx: mpsc::Sender<crate::sdlcore::SdlDrawCommand>,
+    framebuffer: Arc<RwLock<crate::sdlcore::Framebuffer>>,
+    send_mode: DrawSendMode,
   ) -> Self {
     Self {
       turn: false,
@@ -25,16 +60,57 @@ impl VgaDevice {
       y1: 0,
       y2: 0,
       colour: 0,
+      mode: 0,
+      viewport: (0, 0),
+      buffered: false,
       draw_cmd_tx,
+      framebuffer,
+      send_mode,
+    }
+  }
+
+  /// Counts a command as pending before it goes, so the window can't draw
+  /// it and uncount it first, and takes it back off if it never went.
+  fn track<E>(&self, send: impl FnOnce() -> Result<(), E>) -> Result<(), E> {
+    self.framebuffer.write().unwrap().pending += 1;
+    let result = send();
+    if result.is_err() {
+      self.framebuffer.write().unwrap().pending -= 1;
     }
+    result
+  }
+
+  fn send(
+    &self,
+    command: crate::sdlcore::SdlDrawCommand,
+  ) -> Result<(), DeviceError> {
+    self.track(|| match self.send_mode {
+      DrawSendMode::Drop => self
+        .draw_cmd_tx
+        .try_send(command)
+        .map_err(|_| DeviceError::Dead),
+      DrawSendMode::Block(timeout) => tokio::task::block_in_place(|| {
+        tokio::runtime::Handle::current()
+          .block_on(self.draw_cmd_tx.send_timeout(command, timeout))
+      })
+      .map_err(|error| match error {
+        SendTimeoutError::Timeout(_) => DeviceError::Busy,
+        SendTimeoutError::Closed(_) => DeviceError::Dead,
+      }),
+    })
   }
 }
 
 impl DeviceFrame for VgaDevice {
+  fn kind(&self) -> DeviceKind {
+    DeviceKind::Vga
+  }
+
   fn registers(&self) -> &'static [u32] {
     &[
       0x80000060, 0x80000061, 0x80000062, 0x80000063, 0x80000064, 0x80000065,
-      0x80000066,
+      0x80000066, 0x80000067, 0x80000068, 0x80000069, 0x8000006a, 0x8000006b,
+      0x8000006c, 0x8000006d, 0x8000006e,
     ]
   }
 
@@ -48,22 +124,34 @@ impl DeviceFrame for VgaDevice {
     }
 
     match register {
+      // 2 draws a line between the corners, anything else non-zero fills
+      // the rectangle between them
       0x80000060 => {
         if value == 0 {
           return Err(DeviceError::Unwritable);
         } else {
           if self.write_mode {
             log::info!("Sending draw command");
-            self
-              .draw_cmd_tx
-              .try_send(crate::sdlcore::SdlDrawCommand {
+            self.send(if value == 2 {
+              // the registers only mask to a power of two, so the ends are
+              // kept on the canvas here
+              let (width, height) = VGA_MODES[self.mode];
+              crate::sdlcore::SdlDrawCommand::Line {
+                x1: self.x1.min(width as i32 - 1),
+                y1: self.y1.min(height as i32 - 1),
+                x2: self.x2.min(width as i32 - 1),
+                y2: self.y2.min(height as i32 - 1),
+                colour: self.colour,
+              }
+            } else {
+              crate::sdlcore::SdlDrawCommand::Rect {
                 x1: self.x1,
                 x2: self.x2,
                 y1: self.y1,
                 y2: self.y2,
                 colour: self.colour,
-              })
-              .map_err(|_| DeviceError::Dead)?;
+              }
+            })?;
           } else {
             return Err(DeviceError::Dead);
           }
@@ -87,6 +175,40 @@ impl DeviceFrame for VgaDevice {
       0x80000066 => {
         self.colour = value & 0xffffff;
       }
+      0x80000067 => {
+        let mode = usize::try_from(value)
+          .ok()
+          .filter(|mode| *mode < VGA_MODES.len())
+          .ok_or(DeviceError::Unwritable)?;
+        let (width, height) = VGA_MODES[mode];
+        self.send(crate::sdlcore::SdlDrawCommand::Resize { width, height })?;
+        self.mode = mode;
+      }
+      0x80000068 | 0x80000069 | 0x8000006c => {
+        return Err(DeviceError::Unwritable)
+      }
+      0x8000006d => {
+        let buffered = value != 0;
+        self.send(crate::sdlcore::SdlDrawCommand::Buffered(buffered))?;
+        self.buffered = buffered;
+      }
+      0x8000006e => self.send(crate::sdlcore::SdlDrawCommand::Present)?,
+      // an (x, y) offset for every later rectangle, clamped to the canvas
+      // size either way since anything further is entirely off screen
+      0x8000006a | 0x8000006b => {
+        let (width, height) = VGA_MODES[self.mode];
+        let mut viewport = self.viewport;
+        if register == 0x8000006a {
+          viewport.0 = value.clamp(-(width as i32), width as i32);
+        } else {
+          viewport.1 = value.clamp(-(height as i32), height as i32);
+        }
+        self.send(crate::sdlcore::SdlDrawCommand::Viewport {
+          x: viewport.0,
+          y: viewport.1,
+        })?;
+        self.viewport = viewport;
+      }
       _ => unreachable!(),
     }
 
@@ -96,7 +218,190 @@ impl DeviceFrame for VgaDevice {
   fn get(&mut self, register: u32) -> Result<i32, super::DeviceError> {
     match register {
       0x80000060 => Ok(self.turn as i32),
+      0x80000067 => Ok(self.mode as i32),
+      0x80000068 => Ok(VGA_MODES[self.mode].0 as i32),
+      0x80000069 => Ok(VGA_MODES[self.mode].1 as i32),
+      0x8000006a => Ok(self.viewport.0),
+      0x8000006b => Ok(self.viewport.1),
+      0x8000006d => Ok(self.buffered as i32),
+      0x8000006c => {
+        let framebuffer = self.framebuffer.read().unwrap();
+        if framebuffer.pending > 0 {
+          return Err(DeviceError::Stalled);
+        }
+        framebuffer
+          .get(self.x1 + self.viewport.0, self.y1 + self.viewport.1)
+          .ok_or(DeviceError::Unreadable)
+      }
       _ => Err(DeviceError::Unreadable),
     }
   }
+
+  fn snapshot(&self) -> Vec<u8> {
+    [
+      self.write_mode as i32,
+      self.x1,
+      self.y1,
+      self.x2,
+      self.y2,
+      self.colour,
+      self.mode as i32,
+      self.viewport.0,
+      self.viewport.1,
+      self.buffered as i32,
+    ]
+    .iter()
+    .flat_map(|value| value.to_le_bytes())
+    .collect()
+  }
+
+  fn restore(&mut self, data: &[u8]) {
+    let words = data
+      .chunks_exact(4)
+      .map(|word| i32::from_le_bytes(word.try_into().unwrap()))
+      .collect::<Vec<_>>();
+    let [write_mode, x1, y1, x2, y2, colour, mode, ref rest @ ..] =
+      *words.as_slice()
+    else {
+      return;
+    };
+
+    self.write_mode = write_mode > 0;
+    self.x1 = x1;
+    self.y1 = y1;
+    self.x2 = x2;
+    self.y2 = y2;
+    self.colour = colour;
+    self.mode = (mode as usize).min(VGA_MODES.len() - 1);
+
+    let (width, height) = VGA_MODES[self.mode];
+    let _ = self.track(|| {
+      self
+        .draw_cmd_tx
+        .try_send(crate::sdlcore::SdlDrawCommand::Resize { width, height })
+    });
+
+    // snapshots from before the viewport existed leave it at the origin,
+    // and from before buffering, drawing straight to the window
+    self.viewport = match *rest {
+      [x, y, ..] => (x, y),
+      _ => (0, 0),
+    };
+    self.buffered = rest.get(2).is_some_and(|buffered| *buffered != 0);
+    let (x, y) = self.viewport;
+    let _ = self.track(|| {
+      self
+        .draw_cmd_tx
+        .try_send(crate::sdlcore::SdlDrawCommand::Viewport { x, y })
+    });
+    let buffered = self.buffered;
+    let _ = self.track(|| {
+      self
+        .draw_cmd_tx
+        .try_send(crate::sdlcore::SdlDrawCommand::Buffered(buffered))
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn a_longer_queue_takes_a_burst_whole() {
+    let (draw_cmd_tx, mut draw_cmd_rx) = mpsc::channel(32);
+    let (_, pipes_front) = crate::sdlcore::create_pipes(1);
+    let mut vga =
+      VgaDevice::new(draw_cmd_tx, pipes_front.framebuffer, DrawSendMode::Drop);
+
+    for _ in 0..20 {
+      vga.set(0x80000060, 1).unwrap();
+    }
+    let mut drawn = 0;
+    while draw_cmd_rx.try_recv().is_ok() {
+      drawn += 1;
+    }
+    assert_eq!(drawn, 20);
+  }
+
+  #[test]
+  fn pixel_reads_stall_behind_pending_draws() {
+    let (draw_cmd_tx, _draw_cmd_rx) = mpsc::channel(1);
+    let (_, pipes_front) = crate::sdlcore::create_pipes(1);
+    let framebuffer = pipes_front.framebuffer.clone();
+    let mut vga =
+      VgaDevice::new(draw_cmd_tx, pipes_front.framebuffer, DrawSendMode::Drop);
+
+    framebuffer.write().unwrap().pending += 1;
+    assert!(matches!(vga.get(0x8000006c), Err(DeviceError::Stalled)));
+    framebuffer.write().unwrap().pending -= 1;
+    assert!(vga.get(0x8000006c).is_ok());
+  }
+
+  #[test]
+  fn mode_register_resizes_and_the_size_reads_back() {
+    use crate::sdlcore::SdlDrawCommand;
+
+    let (draw_cmd_tx, mut draw_cmd_rx) = mpsc::channel(8);
+    let (_, pipes_front) = crate::sdlcore::create_pipes(1);
+    let mut vga =
+      VgaDevice::new(draw_cmd_tx, pipes_front.framebuffer, DrawSendMode::Drop);
+
+    assert_eq!(vga.get(0x80000068).unwrap(), 640);
+    assert_eq!(vga.get(0x80000069).unwrap(), 480);
+    vga.set(0x80000067, 1).unwrap();
+    assert!(matches!(
+      draw_cmd_rx.try_recv(),
+      Ok(SdlDrawCommand::Resize {
+        width: 320,
+        height: 240
+      })
+    ));
+    assert_eq!(vga.get(0x80000067).unwrap(), 1);
+    assert_eq!(vga.get(0x80000068).unwrap(), 320);
+    assert_eq!(vga.get(0x80000069).unwrap(), 240);
+
+    // the sizes are read-only and an unknown mode changes nothing
+    assert!(matches!(
+      vga.set(0x80000068, 100),
+      Err(DeviceError::Unwritable)
+    ));
+    assert!(matches!(
+      vga.set(0x80000067, 2),
+      Err(DeviceError::Unwritable)
+    ));
+    assert_eq!(vga.get(0x80000068).unwrap(), 320);
+    assert!(draw_cmd_rx.try_recv().is_err());
+  }
+
+  #[test]
+  fn viewport_is_clamped_to_the_canvas_and_sent() {
+    use crate::sdlcore::SdlDrawCommand;
+
+    let (draw_cmd_tx, mut draw_cmd_rx) = mpsc::channel(8);
+    let (_, pipes_front) = crate::sdlcore::create_pipes(1);
+    let mut vga =
+      VgaDevice::new(draw_cmd_tx, pipes_front.framebuffer, DrawSendMode::Drop);
+
+    vga.set(0x8000006a, -10).unwrap();
+    vga.set(0x8000006b, 5000).unwrap();
+    assert!(matches!(
+      draw_cmd_rx.try_recv(),
+      Ok(SdlDrawCommand::Viewport { x: -10, y: 0 })
+    ));
+    assert!(matches!(
+      draw_cmd_rx.try_recv(),
+      Ok(SdlDrawCommand::Viewport { x: -10, y: 480 })
+    ));
+    assert_eq!(vga.get(0x8000006a).unwrap(), -10);
+    assert_eq!(vga.get(0x8000006b).unwrap(), 480);
+
+    // the offset survives a snapshot
+    let (draw_cmd_tx, _draw_cmd_rx) = mpsc::channel(8);
+    let (_, pipes_front) = crate::sdlcore::create_pipes(1);
+    let mut restored =
+      VgaDevice::new(draw_cmd_tx, pipes_front.framebuffer, DrawSendMode::Drop);
+    restored.restore(&vga.snapshot());
+    assert_eq!(restored.viewport, (-10, 480));
+  }
 }
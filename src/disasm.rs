@@ -0,0 +1,86 @@
+use crate::asm::INSTRUCTIONS;
+
+/// Renders an instruction as its mnemonic followed by what it does, with
+/// `m[n]` standing for memory cell `n`, e.g. `add m[5] = m[12] + m[3]`.
+/// Unknown opcodes come out as `.word` and the raw words.
+pub fn disassemble(instr: i32, a1: i32, a2: i32, a3: i32) -> String {
+  let Some((mnemonic, ..)) =
+    INSTRUCTIONS.iter().find(|(_, op, _)| *op == instr)
+  else {
+    return format!(".word {instr} {a1} {a2} {a3}");
+  };
+
+  let effect = match instr {
+    0 => return mnemonic.to_string(),
+    1..=4 | 6 | 7 | 9 | 10 | 28 | 29 => {
+      let op = match instr {
+        1 => "+",
+        2 => "-",
+        3 => "*",
+        4 => "/",
+        6 => "&",
+        7 => "|",
+        9 => "<<",
+        28 => "%",
+        29 => ">>>",
+        _ => ">>",
+      };
+      format!("m[{a1}] = m[{a2}] {op} m[{a3}]")
+    }
+    5 => format!("m[{a1}] = m[{a2}]"),
+    8 => format!("m[{a1}] = !m[{a2}]"),
+    11 => format!("m[{a1}] = m[{a2} + m[{a3}]]"),
+    12 => format!("m[{a2} + m[{a3}]] = m[{a1}]"),
+    13..=15 => {
+      let op = match instr {
+        13 => "==",
+        14 => "!=",
+        _ => "<",
+      };
+      format!("if m[{a2}] {op} m[{a3}] goto {a1}")
+    }
+    27 => format!("if m[{a2}] < m[{a3}] unsigned goto {a1}"),
+    30 => format!("if overflow goto {a1}, clearing it"),
+    16 => format!("m[{a2}] = return; goto {a1}"),
+    17 => format!("goto m[{a1}]"),
+    18 => format!("m[{a1}] = m[{a2}] + m[{a3}] + carry"),
+    19 => format!("m[{a1}] = m[{a2}] - m[{a3}] - borrow"),
+    20 => format!("m[{a1}] = m[{a2}], waiting while busy"),
+    21 => format!("m[{a1}] = m[m[{a2}]]"),
+    22 => format!("m[m[{a2}]] = m[{a1}]"),
+    23 | 25 => {
+      let part = if instr == 23 { "byte" } else { "half" };
+      format!("m[{a1}] = {part} m[{a3}] of m[{a2}]")
+    }
+    24 | 26 => {
+      let part = if instr == 24 { "byte" } else { "half" };
+      format!("{part} m[{a3}] of m[{a2}] = m[{a1}]")
+    }
+    // listed in `INSTRUCTIONS` but not described here yet
+    _ => return format!("{mnemonic} {a1} {a2} {a3}"),
+  };
+  format!("{mnemonic} {effect}")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn renders_each_form_with_its_operands() {
+    assert_eq!(disassemble(0, 1, 2, 3), "halt");
+    assert_eq!(disassemble(1, 5, 12, 3), "add m[5] = m[12] + m[3]");
+    assert_eq!(disassemble(8, 5, 12, 3), "not m[5] = !m[12]");
+    assert_eq!(disassemble(5, 5, 12, 3), "cp m[5] = m[12]");
+    assert_eq!(disassemble(11, 5, 12, 3), "cpfa m[5] = m[12 + m[3]]");
+    assert_eq!(disassemble(12, 5, 12, 3), "cpta m[12 + m[3]] = m[5]");
+    assert_eq!(disassemble(14, 40, 12, 3), "bne if m[12] != m[3] goto 40");
+    assert_eq!(disassemble(17, 99, 0, 0), "ret goto m[99]");
+  }
+
+  #[test]
+  fn unknown_opcodes_are_words() {
+    assert_eq!(disassemble(99, 1, -2, 3), ".word 99 1 -2 3");
+    assert_eq!(disassemble(-1, 0, 0, 0), ".word -1 0 0 0");
+  }
+}
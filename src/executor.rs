@@ -1,92 +1,924 @@
-use std::sync::{
-  atomic::{AtomicBool, Ordering},
-  Arc,
+use std::{
+  collections::{HashSet, VecDeque},
+  sync::{
+    atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    Arc,
+  },
+  time::{Duration, Instant},
 };
 
-use crate::core::{Environment, StepReport};
+use crate::{
+  backtrace::{Backtrace, Tracer},
+  core::{Checkpoint, Environment},
+  profile::Profile,
+  replay::{InputEvent, InputRecorder},
+};
 use color_eyre::eyre;
-use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::{
+  sync::{mpsc, oneshot, Mutex, Notify},
+  time::MissedTickBehavior,
+};
+
+/// Steps run between yields to the runtime while running. Larger batches
+/// trade UI responsiveness for throughput.
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+/// How often a running executor sends `ExecutorReport::Stats`.
+pub const STATS_INTERVAL: Duration = Duration::from_millis(500);
+/// Steps `StepBack` can undo unless set otherwise.
+pub const DEFAULT_UNDO_DEPTH: usize = 1024;
 
 #[derive(Debug)]
 pub enum ExecutorReport {
-  Redraw,
-  Failure { error: crate::core::StepFatal },
+  /// The devices that asked for a redraw since the last report, and the
+  /// address most recently written, if anything was.
+  Redraw {
+    devices: Vec<crate::devices::DeviceKind>,
+    changed: Option<u32>,
+  },
+  Failure {
+    error: crate::core::StepFatal,
+    backtrace: Backtrace,
+  },
+  /// Instructions executed over the `elapsed` time since the last stats
+  /// report, sent roughly every `STATS_INTERVAL` while running.
+  Stats { steps: u64, elapsed: Duration },
+  /// Execution stopped just before running the instruction at a
+  /// breakpoint.
+  BreakpointHit { iar: u32 },
+  /// Execution stopped just after a write to the watched address. The
+  /// values are `None` for device registers, which memory holds nothing
+  /// for.
+  WatchHit {
+    addr: u32,
+    old: Option<i32>,
+    new: Option<i32>,
+  },
+  /// A `RunFor` ran all `stepped` of its steps.
+  StepsDone { stepped: usize },
+}
+
+/// How often a running executor saves the machine to an `autosave-*.state`
+/// file in the working directory, in the same format as `SaveMachine`.
+#[derive(Debug, Clone, Copy)]
+pub enum Autosave {
+  Every(Duration),
+  Steps(u64),
+}
+
+pub enum ExecutorCommand {
+  /// Serialises the environment followed by every device's state.
+  SaveMachine {
+    reply: oneshot::Sender<eyre::Result<Vec<u8>>>,
+  },
+  /// Restores a machine saved with `SaveMachine`.
+  RestoreMachine {
+    data: Vec<u8>,
+    reply: oneshot::Sender<eyre::Result<()>>,
+  },
+  /// Delivers a key to `TermKbdDevice`, recording it in the input log.
+  Input {
+    key: u8,
+  },
+  /// The environment was replaced or reset from outside, so the input log
+  /// restarts from cycle zero (to replay against the new program) and the
+  /// call stack is forgotten.
+  Restart,
+  InputLog {
+    reply: oneshot::Sender<Vec<InputEvent>>,
+  },
+  ResetProfile,
+  /// The hottest `limit` instruction addresses recorded while profiling.
+  Hotspots {
+    limit: usize,
+    reply: oneshot::Sender<Vec<(u32, u64)>>,
+  },
+  /// The memory cells written most recently, newest first.
+  RecentWrites {
+    reply: oneshot::Sender<Vec<u32>>,
+  },
+  /// A copy of the per-cell access counts recorded while profiling.
+  Accesses {
+    reply: oneshot::Sender<Box<[u64; crate::core::MEMORY_SIZE]>>,
+  },
+  /// Runs up to `count` steps, stopping early at a fault, and replies with
+  /// how many went through. Redraws and the fault are reported as they
+  /// would be while running.
+  StepN {
+    count: usize,
+    reply: oneshot::Sender<usize>,
+  },
+  /// Runs like setting `running`, in batches, but stops by itself with
+  /// `StepsDone` after `count` steps. Clearing `running` stops it early,
+  /// like any run, and so do faults, breakpoints and watches.
+  RunFor {
+    count: usize,
+  },
+  /// Replaces the breakpoints; an empty set stops breaking altogether.
+  SetBreakpoints {
+    addrs: HashSet<u32>,
+  },
+  /// Stops after any write to `addr`, even of the value already there, or
+  /// never with `None`.
+  SetWatch {
+    addr: Option<u32>,
+  },
+  /// Undoes the last step run. Fails when there's nothing left to undo or
+  /// the step wrote a device register.
+  StepBack {
+    reply: oneshot::Sender<eyre::Result<()>>,
+  },
+  /// Runs at most `rate` instructions a second, or flat out with `None`.
+  SetRate {
+    rate: Option<u32>,
+  },
+}
+
+/// What a run of steps did: how many went through, the devices that asked
+/// for a redraw, the last address written, and the fault, or the breakpoint
+/// or watchpoint report, that ended it early, if any.
+struct Batch {
+  stepped: usize,
+  redraw: Vec<crate::devices::DeviceKind>,
+  changed: Option<u32>,
+  fatal: Option<(crate::core::StepFatal, Backtrace)>,
+  stop: Option<ExecutorReport>,
 }
 
 pub struct Executor {
   environment: Arc<Mutex<Environment>>,
   running: Arc<AtomicBool>,
+  batch_size: Arc<AtomicUsize>,
   tx: mpsc::UnboundedSender<ExecutorReport>,
+  commands: mpsc::UnboundedReceiver<ExecutorCommand>,
   device_array: crate::devices::DeviceArray,
   notify: Arc<Notify>,
+  inputs: InputRecorder,
+  profiling: Arc<AtomicBool>,
+  profile: Profile,
+  tracer: Tracer,
+  /// Steps run since the last stats report, and when that report went out.
+  executed: u64,
+  stats_since: Instant,
+  autosave: Option<AutosaveTimer>,
+  breakpoints: HashSet<u32>,
+  /// The breakpoint execution last stopped at, which the next step is let
+  /// through so resuming doesn't stop at it again straight away.
+  resume_at: Option<u32>,
+  watch: Option<u32>,
+  iar: Arc<AtomicU32>,
+  steps: Arc<AtomicU64>,
+  /// A checkpoint per step run, newest last, with `None` for steps that
+  /// can't be undone; `StepBack` stops at those.
+  history: VecDeque<Option<Checkpoint>>,
+  undo_depth: usize,
+  /// Ticks once per step while a rate is set.
+  throttle: Option<tokio::time::Interval>,
+  /// Steps left and steps asked for of the `RunFor` in progress.
+  budget: Option<(usize, usize)>,
+}
+
+/// Steps run, and time spent running them, since the last autosave. Only
+/// running counts, so a paused machine isn't saved over and over.
+struct AutosaveTimer {
+  autosave: Autosave,
+  steps: u64,
+  time: Duration,
+}
+
+impl AutosaveTimer {
+  fn due(&self) -> bool {
+    match self.autosave {
+      Autosave::Every(interval) => self.time >= interval,
+      Autosave::Steps(steps) => self.steps >= steps,
+    }
+  }
 }
 
 pub struct ExecutorHandler {
   pub environment: Arc<Mutex<Environment>>,
   pub running: Arc<AtomicBool>,
+  pub batch_size: Arc<AtomicUsize>,
   pub rx: mpsc::UnboundedReceiver<ExecutorReport>,
+  pub commands: mpsc::UnboundedSender<ExecutorCommand>,
   pub notify: Arc<Notify>,
+  /// Counts executions per instruction address; off by default since it
+  /// costs a write per step.
+  pub profiling: Arc<AtomicBool>,
+  /// The IAR, kept up to date by the executor after every step and
+  /// command, so it can be shown while running without taking the lock.
+  pub iar: Arc<AtomicU32>,
+  /// The environment's step count, kept up to date the same way.
+  pub steps: Arc<AtomicU64>,
+}
+
+impl ExecutorHandler {
+  pub async fn save_machine(&self) -> eyre::Result<Vec<u8>> {
+    let (reply, response) = oneshot::channel();
+    self.commands.send(ExecutorCommand::SaveMachine { reply })?;
+    response.await?
+  }
+
+  pub async fn restore_machine(&self, data: Vec<u8>) -> eyre::Result<()> {
+    let (reply, response) = oneshot::channel();
+    self
+      .commands
+      .send(ExecutorCommand::RestoreMachine { data, reply })?;
+    response.await?
+  }
+
+  pub async fn input_log(&self) -> eyre::Result<Vec<InputEvent>> {
+    let (reply, response) = oneshot::channel();
+    self.commands.send(ExecutorCommand::InputLog { reply })?;
+    Ok(response.await?)
+  }
+
+  pub async fn hotspots(&self, limit: usize) -> eyre::Result<Vec<(u32, u64)>> {
+    let (reply, response) = oneshot::channel();
+    self
+      .commands
+      .send(ExecutorCommand::Hotspots { limit, reply })?;
+    Ok(response.await?)
+  }
+
+  pub async fn recent_writes(&self) -> eyre::Result<Vec<u32>> {
+    let (reply, response) = oneshot::channel();
+    self
+      .commands
+      .send(ExecutorCommand::RecentWrites { reply })?;
+    Ok(response.await?)
+  }
+
+  pub async fn accesses(
+    &self,
+  ) -> eyre::Result<Box<[u64; crate::core::MEMORY_SIZE]>> {
+    let (reply, response) = oneshot::channel();
+    self.commands.send(ExecutorCommand::Accesses { reply })?;
+    Ok(response.await?)
+  }
+
+  pub async fn step_back(&self) -> eyre::Result<()> {
+    let (reply, response) = oneshot::channel();
+    self.commands.send(ExecutorCommand::StepBack { reply })?;
+    response.await?
+  }
+
+  pub async fn step_n(&self, count: usize) -> eyre::Result<usize> {
+    let (reply, response) = oneshot::channel();
+    self
+      .commands
+      .send(ExecutorCommand::StepN { count, reply })?;
+    Ok(response.await?)
+  }
 }
 
 impl Executor {
   pub fn new(
     environment: Environment,
     device_array: crate::devices::DeviceArray,
+    term_keys: mpsc::UnboundedSender<u8>,
   ) -> (Self, ExecutorHandler) {
+    let iar = Arc::new(AtomicU32::new(environment.iar));
+    let steps = Arc::new(AtomicU64::new(environment.steps));
     let environment = Arc::new(Mutex::new(environment));
     let running = Arc::new(AtomicBool::new(false));
+    let batch_size = Arc::new(AtomicUsize::new(DEFAULT_BATCH_SIZE));
     let (tx, rx) = mpsc::unbounded_channel();
+    let (commands_tx, commands) = mpsc::unbounded_channel();
     let notify = Arc::new(Notify::new());
+    let profiling = Arc::new(AtomicBool::new(false));
     (
       Executor {
         environment: environment.clone(),
         running: running.clone(),
+        batch_size: batch_size.clone(),
         tx,
+        commands,
         device_array,
         notify: notify.clone(),
+        inputs: InputRecorder::new(term_keys),
+        profiling: profiling.clone(),
+        profile: Profile::default(),
+        tracer: Tracer::default(),
+        executed: 0,
+        stats_since: Instant::now(),
+        autosave: None,
+        breakpoints: HashSet::new(),
+        resume_at: None,
+        watch: None,
+        iar: iar.clone(),
+        steps: steps.clone(),
+        history: VecDeque::new(),
+        undo_depth: DEFAULT_UNDO_DEPTH,
+        throttle: None,
+        budget: None,
       },
       ExecutorHandler {
         environment,
         running,
+        batch_size,
         rx,
+        commands: commands_tx,
         notify,
+        profiling,
+        iar,
+        steps,
       },
     )
   }
 
+  /// How many steps `StepBack` can undo; 0 stops recording them at all.
+  pub fn set_undo_depth(&mut self, depth: usize) {
+    self.undo_depth = depth;
+  }
+
+  /// Turns on periodic saving while running; it's off by default.
+  pub fn set_autosave(&mut self, autosave: Autosave) {
+    self.autosave = Some(AutosaveTimer {
+      autosave,
+      steps: 0,
+      time: Duration::ZERO,
+    });
+  }
+
   pub async fn process(mut self) -> eyre::Result<()> {
+    // locked through its own handle so the guard doesn't hold `self`
+    let environment = self.environment.clone();
     let mut guard = None;
     loop {
+      while let Ok(command) = self.commands.try_recv() {
+        if guard.is_none() {
+          guard = Some(environment.lock().await);
+        }
+        let Some(ref mut env) = guard else {
+          unreachable!()
+        };
+
+        self.execute(command, env)?;
+      }
+
       if self.running.load(Ordering::Acquire) {
         if guard.is_none() {
-          guard = Some(self.environment.lock().await);
+          guard = Some(environment.lock().await);
         }
         let Some(ref mut env) = guard else {
           unreachable!()
         };
 
-        match crate::core::step(env, &mut self.device_array) {
-          Ok(StepReport { redraw, .. }) => {
-            if redraw {
-              self.tx.send(ExecutorReport::Redraw)?;
-            }
+        // redraws requested within a batch are coalesced into one report;
+        // a throttled machine runs a step per tick instead
+        let batch_size = match self.throttle {
+          Some(_) => 1,
+          None => self.batch_size.load(Ordering::Relaxed).max(1),
+        };
+        let batch_size = match self.budget {
+          Some((left, _)) => batch_size.min(left),
+          None => batch_size,
+        };
+        let batch_start = Instant::now();
+        let Batch {
+          stepped,
+          redraw,
+          changed,
+          fatal,
+          stop,
+        } = self.run_steps(env, batch_size, true);
+
+        if !redraw.is_empty() || changed.is_some() {
+          self.tx.send(ExecutorReport::Redraw {
+            devices: redraw,
+            changed,
+          })?;
+        }
+
+        let elapsed = self.stats_since.elapsed();
+        if elapsed >= STATS_INTERVAL {
+          self.tx.send(ExecutorReport::Stats {
+            steps: std::mem::take(&mut self.executed),
+            elapsed,
+          })?;
+          self.stats_since = Instant::now();
+        }
+
+        if let Some(timer) = &mut self.autosave {
+          timer.steps += stepped as u64;
+          timer.time += batch_start.elapsed();
+          if timer.due() {
+            autosave(env, &self.device_array, self.inputs.cycles());
+            timer.steps = 0;
+            timer.time = Duration::ZERO;
           }
-          Err(e) => {
+        }
+
+        if let Some((e, backtrace)) = fatal {
+          std::mem::drop(guard.take());
+          self.running.store(false, Ordering::Release);
+          log::warn!("Step fatal/halted {:?}", e);
+          self.tx.send(ExecutorReport::Failure {
+            error: e,
+            backtrace,
+          })?;
+        }
+
+        if let Some(report) = stop {
+          std::mem::drop(guard.take());
+          self.running.store(false, Ordering::Release);
+          self.tx.send(report)?;
+        }
+
+        if let Some((left, count)) = &mut self.budget {
+          *left -= stepped;
+          if *left == 0 && self.running.load(Ordering::Acquire) {
+            let stepped = *count;
             std::mem::drop(guard.take());
             self.running.store(false, Ordering::Release);
-            log::warn!("Step fatal/halted {:?}", e);
-            self.tx.send(ExecutorReport::Failure { error: e })?;
+            self.tx.send(ExecutorReport::StepsDone { stepped })?;
           }
         }
 
-        tokio::task::yield_now().await;
+        match &mut self.throttle {
+          // without the lock, so the UI can read memory in the meantime
+          Some(interval) => {
+            std::mem::drop(guard.take());
+            interval.tick().await;
+          }
+          None => tokio::task::yield_now().await,
+        }
       } else {
         if guard.is_some() {
           std::mem::drop(guard.take());
         }
+        // however the run stopped, a later one isn't bounded by it
+        self.budget = None;
+
+        let command = tokio::select! {
+          _ = self.notify.notified() => None,
+          command = self.commands.recv() => {
+            // the handler going away is the signal to shut down
+            let Some(command) = command else {
+              break;
+            };
+            Some(command)
+          }
+        };
+        if let Some(command) = command {
+          let mut env = environment.lock().await;
+          self.execute(command, &mut env)?;
+        }
+
+        // time spent idle isn't time spent running
+        self.executed = 0;
+        self.stats_since = Instant::now();
+      }
+    }
+
+    // let in-flight device writes land; dropping the devices afterwards
+    // closes the draw queue, which lets the SDL thread finish
+    for task in self.device_array.shutdown() {
+      let _ = task.await;
+    }
+
+    Ok(())
+  }
+
+  /// Runs up to `count` steps, stopping at the first fault, ahead of a
+  /// breakpoint, after a write to the watched address or, when
+  /// `interruptible`, as soon as `running` is cleared.
+  fn run_steps(
+    &mut self,
+    env: &mut Environment,
+    count: usize,
+    interruptible: bool,
+  ) -> Batch {
+    let mut batch = Batch {
+      stepped: 0,
+      redraw: Vec::new(),
+      changed: None,
+      fatal: None,
+      stop: None,
+    };
+    let profiling = self.profiling.load(Ordering::Relaxed);
+    for _ in 0..count {
+      if interruptible && !self.running.load(Ordering::Acquire) {
+        break;
+      }
+
+      let iar = env.iar;
+      if self.breakpoints.contains(&iar) && self.resume_at != Some(iar) {
+        self.resume_at = Some(iar);
+        batch.stop = Some(ExecutorReport::BreakpointHit { iar });
+        break;
+      }
+
+      // the old value has to be taken now, before the step overwrites it
+      let watched = |env: &Environment| {
+        let addr = self.watch?;
+        Some((addr, env.memory.get(addr as usize).copied()))
+      };
+      let before = watched(env);
+
+      let opcode = env.memory.get(iar as usize).copied().unwrap_or(0);
+      let checkpoint = env.checkpoint();
+      // a bug in step shouldn't take the executor (and the UI waiting on
+      // it) down with it
+      match crate::core::step_catching(env, &mut self.device_array) {
+        // nothing ran, so there's nothing to trace, undo or profile, and
+        // a breakpoint on the instruction stays resumed for its retry
+        Ok(report) if report.stalled.is_some() => {
+          self.executed += 1;
+          batch.stepped += 1;
+          self.inputs.tick();
+        }
+        Ok(report) => {
+          self.resume_at = None;
+          self.executed += 1;
+          batch.stepped += 1;
+          self.inputs.tick();
+          self.tracer.record(iar, opcode, &report);
+          if self.undo_depth > 0 {
+            if self.history.len() >= self.undo_depth {
+              self.history.pop_front();
+            }
+            self.history.push_back(checkpoint.complete(&report));
+          }
+          if profiling {
+            self.profile.record(iar, &report);
+          }
+          self.iar.store(env.iar, Ordering::Relaxed);
+          self.steps.store(env.steps, Ordering::Relaxed);
+          batch.changed = report.changed.or(batch.changed);
+          if let Some(device) = report.redraw {
+            if !batch.redraw.contains(&device) {
+              batch.redraw.push(device);
+            }
+          }
+          if let Some((addr, old)) = before {
+            if report.changed == Some(addr) {
+              let new = env.memory.get(addr as usize).copied();
+              batch.stop = Some(ExecutorReport::WatchHit { addr, old, new });
+              break;
+            }
+          }
+        }
+        Err(e) => {
+          self.resume_at = None;
+          batch.fatal = Some((e, self.tracer.backtrace(iar)));
+          break;
+        }
+      }
+    }
+    batch
+  }
 
-        self.notify.notified().await;
+  fn execute(
+    &mut self,
+    command: ExecutorCommand,
+    env: &mut Environment,
+  ) -> eyre::Result<()> {
+    match command {
+      ExecutorCommand::StepN { count, reply } => {
+        let batch = self.run_steps(env, count, false);
+        if !batch.redraw.is_empty() || batch.changed.is_some() {
+          self.tx.send(ExecutorReport::Redraw {
+            devices: batch.redraw,
+            changed: batch.changed,
+          })?;
+        }
+        if let Some((error, backtrace)) = batch.fatal {
+          log::warn!("Step fatal/halted {:?}", error);
+          self.tx.send(ExecutorReport::Failure { error, backtrace })?;
+        }
+        if let Some(report) = batch.stop {
+          self.tx.send(report)?;
+        }
+        let _ = reply.send(batch.stepped);
+      }
+      ExecutorCommand::RunFor { count } => {
+        self.budget = Some((count, count));
+        self.running.store(true, Ordering::Release);
       }
+      ExecutorCommand::SetBreakpoints { addrs } => self.breakpoints = addrs,
+      ExecutorCommand::SetWatch { addr } => self.watch = addr,
+      ExecutorCommand::SetRate { rate } => {
+        self.throttle = rate.filter(|rate| *rate > 0).map(|rate| {
+          let mut interval =
+            tokio::time::interval(Duration::from_secs(1) / rate);
+          // a pause shouldn't be made up for with a burst afterwards
+          interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+          interval
+        });
+      }
+      ExecutorCommand::StepBack { reply } => {
+        let result = match self.history.back() {
+          None => Err(eyre::eyre!("no steps to undo")),
+          Some(None) => Err(eyre::eyre!(
+            "the last step wrote a device, so can't be undone"
+          )),
+          Some(Some(checkpoint)) => {
+            env.undo(checkpoint);
+            let changed = checkpoint.restores();
+            self.history.pop_back();
+            self.tx.send(ExecutorReport::Redraw {
+              devices: Vec::new(),
+              changed,
+            })?;
+            Ok(())
+          }
+        };
+        let _ = reply.send(result);
+      }
+      command => {
+        // a different machine shouldn't skip its first breakpoint
+        if matches!(
+          command,
+          ExecutorCommand::Restart | ExecutorCommand::RestoreMachine { .. }
+        ) {
+          self.resume_at = None;
+          self.history.clear();
+        }
+        execute_command(
+          command,
+          env,
+          &mut self.device_array,
+          &mut self.inputs,
+          &mut self.profile,
+          &mut self.tracer,
+        )
+      }
+    }
+    // commands can replace the environment, and the UI resets it directly
+    // before sending `Restart`
+    self.iar.store(env.iar, Ordering::Relaxed);
+    self.steps.store(env.steps, Ordering::Relaxed);
+    Ok(())
+  }
+}
+
+/// Writes the machine to `autosave-<unix time>-<cycle>.state` in the working
+/// directory, logging rather than failing if it can't.
+fn autosave(
+  environment: &Environment,
+  device_array: &crate::devices::DeviceArray,
+  cycles: u64,
+) {
+  let timestamp = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+  let path = format!("autosave-{timestamp}-{cycles}.state");
+  let result = save_machine(environment, device_array)
+    .and_then(|data| Ok(std::fs::write(&path, data)?));
+  match result {
+    Ok(()) => log::info!("Autosaved to {path}"),
+    Err(error) => log::warn!("Failed to autosave to {path}: {error}"),
+  }
+}
+
+/// The environment followed by every device's state.
+fn save_machine(
+  environment: &Environment,
+  device_array: &crate::devices::DeviceArray,
+) -> eyre::Result<Vec<u8>> {
+  let mut data = Vec::new();
+  environment.save(&mut data)?;
+  data.extend_from_slice(&device_array.snapshot());
+  Ok(data)
+}
+
+fn execute_command(
+  command: ExecutorCommand,
+  environment: &mut Environment,
+  device_array: &mut crate::devices::DeviceArray,
+  inputs: &mut InputRecorder,
+  profile: &mut Profile,
+  tracer: &mut Tracer,
+) {
+  match command {
+    ExecutorCommand::SaveMachine { reply } => {
+      let _ = reply.send(save_machine(environment, device_array));
+    }
+    ExecutorCommand::RestoreMachine { data, reply } => {
+      let mut reader = data.as_slice();
+      // the environment only changes once the devices have taken their part
+      // of the snapshot, and they check all of it before taking any
+      let result = Environment::load(&mut reader).and_then(|mut restored| {
+        device_array.restore(reader)?;
+        restored.keep_settings(environment);
+        *environment = restored;
+        tracer.reset();
+        Ok(())
+      });
+      let _ = reply.send(result);
+    }
+    ExecutorCommand::Input { key } => inputs.record(key),
+    ExecutorCommand::Restart => {
+      inputs.reset();
+      tracer.reset();
+    }
+    ExecutorCommand::InputLog { reply } => {
+      let _ = reply.send(inputs.events().to_vec());
+    }
+    ExecutorCommand::ResetProfile => profile.reset(),
+    ExecutorCommand::Hotspots { limit, reply } => {
+      let _ = reply.send(profile.hottest(limit));
+    }
+    ExecutorCommand::RecentWrites { reply } => {
+      let _ = reply.send(tracer.recent_writes());
+    }
+    ExecutorCommand::Accesses { reply } => {
+      let _ = reply.send(Box::new(*profile.accesses()));
+    }
+    ExecutorCommand::StepN { .. }
+    | ExecutorCommand::RunFor { .. }
+    | ExecutorCommand::SetBreakpoints { .. }
+    | ExecutorCommand::SetWatch { .. }
+    | ExecutorCommand::StepBack { .. }
+    | ExecutorCommand::SetRate { .. } => {
+      unreachable!("handled by the executor")
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Counts m[100] down to zero a step at a time, then halts: `sub` at 0,
+  /// `bne` back to it at 4 and `halt` at 8, for `2 * count` steps.
+  fn countdown(count: i32) -> Environment {
+    let mut env = Environment::default();
+    env.memory[..8].copy_from_slice(&[2, 100, 100, 101, 14, 0, 100, 102]);
+    env.memory[100] = count;
+    env.memory[101] = 1;
+    env
+  }
+
+  fn executor(env: Environment) -> (Executor, ExecutorHandler) {
+    let (term_keys, _) = mpsc::unbounded_channel();
+    Executor::new(env, crate::devices::DeviceArray::default(), term_keys)
+  }
+
+  #[test]
+  fn breakpoint_inside_a_batch_stops_there() {
+    // a straight run of copies, so step k is the instruction at 4k
+    let mut env = Environment::default();
+    for addr in (0..4 * DEFAULT_BATCH_SIZE).step_by(4) {
+      env.memory[addr..addr + 4].copy_from_slice(&[5, 16000, 16001, 0]);
+    }
+    let (mut exec, handler) = executor(Environment::default());
+    handler.running.store(true, Ordering::Release);
+    exec.breakpoints = HashSet::from([4 * 500]);
+
+    let batch = exec.run_steps(&mut env, DEFAULT_BATCH_SIZE, true);
+    assert!(matches!(
+      batch.stop,
+      Some(ExecutorReport::BreakpointHit { iar: 2000 })
+    ));
+    assert_eq!(batch.stepped, 500);
+    assert_eq!(env.iar, 2000);
+
+    // resuming lets the breakpoint through rather than stopping again
+    let batch = exec.run_steps(&mut env, 10, true);
+    assert!(batch.stop.is_none());
+    assert_eq!(env.iar, 2040);
+  }
+
+  #[test]
+  fn one_resume_gets_a_stalled_access_past_its_breakpoint() {
+    use crate::devices::{scratch, DeviceKind};
+
+    let mut env = Environment::default();
+    env.memory[..4].copy_from_slice(&[5, 10, scratch::SCRATCH_BASE as i32, 0]);
+    let mut device_array = crate::devices::DeviceArray::default();
+    device_array
+      .register_device(Box::new(scratch::ScratchDevice::default()))
+      .unwrap();
+    device_array
+      .set(scratch::SCRATCH_BASE, 42)
+      .unwrap()
+      .unwrap();
+    device_array.set_latency(DeviceKind::Scratch, 3);
+    let (term_keys, _) = mpsc::unbounded_channel();
+    let (mut exec, handler) =
+      Executor::new(Environment::default(), device_array, term_keys);
+    handler.running.store(true, Ordering::Release);
+    exec.breakpoints = HashSet::from([0]);
+
+    let batch = exec.run_steps(&mut env, 10, true);
+    assert!(matches!(
+      batch.stop,
+      Some(ExecutorReport::BreakpointHit { iar: 0 })
+    ));
+
+    // three stalls, then the read itself
+    let batch = exec.run_steps(&mut env, 4, true);
+    assert!(batch.stop.is_none());
+    assert_eq!(batch.stepped, 4);
+    assert_eq!(env.memory[10], 42);
+    assert_eq!(env.iar, 4);
+  }
+
+  #[test]
+  fn restoring_keeps_the_run_settings() {
+    let (mut exec, _handler) = executor(Environment::default());
+    let mut saved = countdown(3);
+    saved.iar = 4;
+    let data = save_machine(&saved, &exec.device_array).unwrap();
+
+    let mut env = Environment::default();
+    env.arithmetic = crate::core::ArithmeticMode::Checked;
+    env.cycle_costs[3] = 5;
+    env.strict_halt = true;
+    let (reply, mut response) = oneshot::channel();
+    exec
+      .execute(ExecutorCommand::RestoreMachine { data, reply }, &mut env)
+      .unwrap();
+    response.try_recv().unwrap().unwrap();
+
+    assert_eq!(env.iar, 4);
+    assert_eq!(env.memory[100], 3);
+    assert_eq!(env.arithmetic, crate::core::ArithmeticMode::Checked);
+    assert_eq!(env.cycle_costs[3], 5);
+    assert!(env.strict_halt);
+  }
+
+  #[tokio::test]
+  async fn run_for_stops_after_its_steps_across_batches() {
+    let (exec, mut handler) = executor(countdown(100));
+    handler.batch_size.store(16, Ordering::Relaxed);
+    let runner = tokio::spawn(exec.process());
+
+    handler
+      .commands
+      .send(ExecutorCommand::RunFor { count: 50 })
+      .unwrap();
+    loop {
+      match handler.rx.recv().await.unwrap() {
+        ExecutorReport::StepsDone { stepped } => {
+          assert_eq!(stepped, 50);
+          break;
+        }
+        ExecutorReport::Redraw { .. } | ExecutorReport::Stats { .. } => {}
+        _ => panic!("unexpected report"),
+      }
+    }
+    assert!(!handler.running.load(Ordering::Acquire));
+    assert_eq!(handler.environment.lock().await.steps, 50);
+
+    std::mem::drop(handler);
+    runner.await.unwrap().unwrap();
+  }
+
+  #[tokio::test]
+  async fn running_reports_stats_every_interval() {
+    // `be 0 0 0` branches to itself forever
+    let mut env = Environment::default();
+    env.memory[..4].copy_from_slice(&[13, 0, 0, 0]);
+    let (exec, mut handler) = executor(env);
+    handler.running.store(true, Ordering::Release);
+    let runner = tokio::spawn(exec.process());
+
+    loop {
+      match handler.rx.recv().await.unwrap() {
+        ExecutorReport::Stats { steps, elapsed } => {
+          assert!(steps > 0);
+          assert!(elapsed >= STATS_INTERVAL);
+          break;
+        }
+        ExecutorReport::Redraw { .. } => {}
+        _ => panic!("unexpected report"),
+      }
+    }
+
+    handler.running.store(false, Ordering::Release);
+    std::mem::drop(handler);
+    runner.await.unwrap().unwrap();
+  }
+
+  /// Compares steps per second at a batch size of 1 and the default. Run
+  /// with `cargo test --release batch_throughput -- --ignored --nocapture`.
+  #[tokio::test]
+  #[ignore]
+  async fn batch_throughput() {
+    const COUNT: i32 = 1_000_000;
+    for batch_size in [1, DEFAULT_BATCH_SIZE] {
+      let (exec, mut handler) = executor(countdown(COUNT));
+      handler.batch_size.store(batch_size, Ordering::Relaxed);
+      handler.running.store(true, Ordering::Release);
+
+      let start = Instant::now();
+      let runner = tokio::spawn(exec.process());
+      while let Some(report) = handler.rx.recv().await {
+        if let ExecutorReport::Failure { error, .. } = report {
+          assert!(matches!(error, crate::core::StepFatal::Halted));
+          break;
+        }
+      }
+      let elapsed = start.elapsed();
+      std::mem::drop(handler);
+      runner.await.unwrap().unwrap();
+
+      let rate = 2.0 * COUNT as f64 / elapsed.as_secs_f64();
+      println!("batch size {batch_size}: {rate:.0} steps/s");
     }
   }
 }
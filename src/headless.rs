@@ -0,0 +1,457 @@
+use std::{fs::File, path::Path};
+
+use color_eyre::eyre;
+
+use crate::{
+  core::{self, ArithmeticMode, CellDiff, Environment, StepFatal},
+  devices::DeviceArray,
+  sched::Scheduler,
+};
+
+pub const DEFAULT_STEP_LIMIT: u64 = 1_000_000;
+/// Consecutive steps without a change before memory counts as settled.
+pub const DEFAULT_PATIENCE: u64 = 1000;
+
+#[derive(Debug)]
+pub enum RunOutcome {
+  Halted {
+    steps: u64,
+  },
+  Faulted {
+    steps: u64,
+    error: StepFatal,
+  },
+  StepLimit {
+    steps: u64,
+  },
+  /// Strict mode only: a step branched to itself without writing anything,
+  /// so the program can never make progress.
+  Stuck {
+    steps: u64,
+    iar: u32,
+  },
+  /// `run_until_settled` saw no change for its whole patience window.
+  Settled {
+    steps: u64,
+  },
+}
+
+impl std::fmt::Display for RunOutcome {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RunOutcome::Halted { steps } => write!(f, "halted after {steps} steps"),
+      RunOutcome::Faulted { steps, error } => {
+        write!(f, "faulted after {steps} steps: {error}")
+      }
+      RunOutcome::StepLimit { steps } => {
+        write!(f, "step limit reached after {steps} steps")
+      }
+      RunOutcome::Stuck { steps, iar } => {
+        write!(f, "stuck at {iar} after {steps} steps")
+      }
+      RunOutcome::Settled { steps } => {
+        write!(f, "settled after {steps} steps")
+      }
+    }
+  }
+}
+
+/// Steps `environment` until it halts, faults, or `step_limit` steps have
+/// run without either happening.
+pub fn run_to_halt(
+  environment: &mut Environment,
+  device_array: &mut DeviceArray,
+  step_limit: u64,
+) -> RunOutcome {
+  run_with(environment, device_array, step_limit, |_| {})
+}
+
+/// `run_to_halt`, calling `before_step` with the step count ahead of every
+/// step so inputs can be injected at exact points in the run.
+pub fn run_with(
+  environment: &mut Environment,
+  device_array: &mut DeviceArray,
+  step_limit: u64,
+  mut before_step: impl FnMut(u64),
+) -> RunOutcome {
+  let scheduler = Scheduler::new();
+  scheduler.enter(|| {
+    for steps in 0..step_limit {
+      before_step(steps);
+      let iar = environment.iar;
+      let result = core::step(environment, device_array);
+      scheduler.settle();
+      match result {
+        Ok(report) => {
+          if environment.strict_halt
+            && environment.iar == iar
+            && report.changed.is_none()
+            && report.stalled.is_none()
+          {
+            return RunOutcome::Stuck { steps, iar };
+          }
+        }
+        Err(StepFatal::Halted) => return RunOutcome::Halted { steps },
+        Err(error) => return RunOutcome::Faulted { steps, error },
+      }
+    }
+
+    RunOutcome::StepLimit { steps: step_limit }
+  })
+}
+
+/// Steps `environment` until `patience` consecutive steps leave memory (or
+/// just `watch`, if given) unchanged. Writes of the value a cell already held
+/// don't count as changes. `steps` in the outcome is where the quiet stretch
+/// began.
+pub fn run_until_settled(
+  environment: &mut Environment,
+  device_array: &mut DeviceArray,
+  watch: Option<u32>,
+  patience: u64,
+  step_limit: u64,
+) -> RunOutcome {
+  // tracks the last value seen in each cell so a write can be checked
+  // without snapshotting memory every step
+  let mut previous = environment.memory.clone();
+  let mut quiet_since = 0;
+
+  let scheduler = Scheduler::new();
+  scheduler.enter(|| {
+    for steps in 0..step_limit {
+      if steps - quiet_since >= patience {
+        return RunOutcome::Settled { steps: quiet_since };
+      }
+
+      let result = core::step(environment, device_array);
+      scheduler.settle();
+      match result {
+        Ok(report) => {
+          let Some(addr) = report.changed.map(|addr| addr as usize) else {
+            continue;
+          };
+          if addr >= previous.len() || watch.is_some_and(|w| w as usize != addr)
+          {
+            continue;
+          }
+          if previous[addr] != environment.memory[addr] {
+            previous[addr] = environment.memory[addr];
+            quiet_since = steps + 1;
+          }
+        }
+        Err(StepFatal::Halted) => return RunOutcome::Halted { steps },
+        Err(error) => return RunOutcome::Faulted { steps, error },
+      }
+    }
+
+    RunOutcome::StepLimit { steps: step_limit }
+  })
+}
+
+/// Where two runs stopped agreeing: the step that told them apart, the
+/// IARs and step outcomes at that point, and every cell that differed.
+pub struct Divergence {
+  pub steps: u64,
+  pub iars: (u32, u32),
+  pub outcomes: (String, String),
+  pub cells: Vec<CellDiff>,
+}
+
+impl std::fmt::Display for Divergence {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "diverged at step {}: IAR {} vs {}, {} vs {}",
+      self.steps, self.iars.0, self.iars.1, self.outcomes.0, self.outcomes.1
+    )?;
+    for cell in self.cells.iter() {
+      write!(f, "\n{}: {} vs {}", cell.addr, cell.left, cell.right)?;
+    }
+    Ok(())
+  }
+}
+
+/// Steps `left` and `right` in lockstep, each with its own headless
+/// devices, until they disagree, both stop the same way, or `step_limit`
+/// steps pass. They're compared after every step by IAR, outcome and the
+/// cell written, which is everything a step can change, so memory is only
+/// diffed in full once they've come apart.
+pub fn find_divergence(
+  left: &mut Environment,
+  right: &mut Environment,
+  step_limit: u64,
+) -> Option<Divergence> {
+  let mut devices = (headless_devices(), headless_devices());
+  let divergence =
+    |steps, left: &Environment, right: &Environment, outcomes| {
+      Some(Divergence {
+        steps,
+        iars: (left.iar, right.iar),
+        outcomes,
+        cells: left.diff(right),
+      })
+    };
+
+  if left.iar != right.iar || left.memory != right.memory {
+    let outcomes = ("loaded".to_owned(), "loaded".to_owned());
+    return divergence(0, left, right, outcomes);
+  }
+
+  let describe = |result: &Result<core::StepReport, StepFatal>| match result {
+    Ok(_) => "ok".to_owned(),
+    Err(error) => error.to_string(),
+  };
+  let scheduler = Scheduler::new();
+  scheduler.enter(|| {
+    for steps in 0..step_limit {
+      let results = (
+        core::step(left, &mut devices.0),
+        core::step(right, &mut devices.1),
+      );
+      scheduler.settle();
+
+      let written = |env: &Environment,
+                     result: &Result<core::StepReport, _>| {
+        let addr = result.as_ref().ok()?.changed?;
+        Some((addr, env.memory.get(addr as usize).copied()))
+      };
+      let outcomes = (describe(&results.0), describe(&results.1));
+      if left.iar != right.iar
+        || outcomes.0 != outcomes.1
+        || written(left, &results.0) != written(right, &results.1)
+      {
+        return divergence(steps + 1, left, right, outcomes);
+      }
+      if results.0.is_err() {
+        return None;
+      }
+    }
+
+    None
+  })
+}
+
+/// Runs exactly one step of `instruction` placed at `addr` in a fresh
+/// environment with the headless devices, after `setup` has had a chance to
+/// seed memory. Returns the environment as the step left it.
+#[cfg(test)]
+pub fn step_at(
+  addr: u32,
+  instruction: [i32; 4],
+  setup: impl FnOnce(&mut Environment),
+) -> eyre::Result<(Environment, Result<core::StepReport, StepFatal>)> {
+  let start = addr as usize;
+  if start + 4 > core::MEMORY_SIZE {
+    eyre::bail!("instruction at {addr} does not fit in memory");
+  }
+
+  let mut environment = Environment::default();
+  setup(&mut environment);
+  environment.memory[start..start + 4].copy_from_slice(&instruction);
+  environment.initialized[start..start + 4].fill(true);
+  environment.iar = addr;
+
+  let scheduler = Scheduler::new();
+  let result = scheduler.enter(|| {
+    let result = core::step(&mut environment, &mut headless_devices());
+    scheduler.settle();
+    result
+  });
+  Ok((environment, result))
+}
+
+/// Devices that work without the SDL frontend.
+pub fn headless_devices() -> DeviceArray {
+  let mut device_array = DeviceArray::default();
+  let devices: [Box<dyn crate::devices::DeviceFrame>; 5] = [
+    Box::new(crate::devices::onboard::LcdDisplayDevice::default()),
+    Box::new(crate::devices::onboard::HexDisplayDevice::default()),
+    Box::new(crate::devices::onboard::LEDDisplayDevice::default()),
+    Box::new(crate::devices::scratch::ScratchDevice::default()),
+    Box::new(crate::devices::fault::FaultDevice::default()),
+  ];
+  for device in devices {
+    device_array
+      .register_device(device)
+      .expect("the headless devices have registers of their own");
+  }
+  let inventory = device_array.inventory();
+  device_array
+    .register_device(Box::new(crate::devices::info::InfoDevice::new(inventory)))
+    .expect("the headless devices have registers of their own");
+  device_array
+}
+
+/// Runs `program` to completion, with `args` placed in the argument region,
+/// `arithmetic` governing overflow and `cycle_costs` pricing each opcode,
+/// and compares the final memory against `expected`, printing every
+/// mismatching cell. Returns whether they matched.
+pub fn grade(
+  program: &Path,
+  expected: &Path,
+  step_limit: u64,
+  strict: bool,
+  args: Option<&[i32]>,
+  arithmetic: ArithmeticMode,
+  cycle_costs: [u64; core::OPCODE_COUNT],
+) -> eyre::Result<bool> {
+  let mut environment = Environment::parse(&mut File::open(program)?)?;
+  environment.strict_halt = strict;
+  environment.arithmetic = arithmetic;
+  environment.cycle_costs = cycle_costs;
+  let mut expected = Environment::parse(&mut File::open(expected)?)?;
+  // arguments go into both images so they never show up as mismatches
+  if let Some(args) = args {
+    environment.set_args(args)?;
+    expected.set_args(args)?;
+  }
+  let mut device_array = headless_devices();
+
+  let outcome = run_to_halt(&mut environment, &mut device_array, step_limit);
+  println!("{outcome} ({} cycles)", environment.cycles);
+
+  let diffs = environment.diff(&expected);
+  for diff in diffs.iter() {
+    println!(
+      "mismatch at {}: expected {}, got {}",
+      diff.addr, diff.right, diff.left
+    );
+  }
+
+  let passed = matches!(outcome, RunOutcome::Halted { .. }) && diffs.is_empty();
+  println!(
+    "{} ({} mismatching cells)",
+    if passed { "PASS" } else { "FAIL" },
+    diffs.len()
+  );
+
+  Ok(passed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Writes `contents` to a file of its own in the temp directory.
+  fn mif(name: &str, contents: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir()
+      .join(format!("case100-{}-{name}.mif", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+  }
+
+  /// m[10] = m[8] + m[9], then halt.
+  const ADD_PROGRAM: &str = "0 : 1; 1 : 10; 2 : 8; 3 : 9; 8 : 2; 9 : 3;";
+
+  fn grade_against(name: &str, expected: &str) -> bool {
+    let program = mif(&format!("{name}-program"), ADD_PROGRAM);
+    let expected = mif(&format!("{name}-expected"), expected);
+    let passed = grade(
+      &program,
+      &expected,
+      DEFAULT_STEP_LIMIT,
+      false,
+      None,
+      ArithmeticMode::default(),
+      [1; core::OPCODE_COUNT],
+    )
+    .unwrap();
+    std::fs::remove_file(program).unwrap();
+    std::fs::remove_file(expected).unwrap();
+    passed
+  }
+
+  #[test]
+  fn grade_passes_a_matching_image() {
+    assert!(grade_against("pass", &format!("{ADD_PROGRAM} 10 : 5;")));
+  }
+
+  #[test]
+  fn grade_fails_a_mismatching_image() {
+    assert!(!grade_against("fail", &format!("{ADD_PROGRAM} 10 : 6;")));
+  }
+
+  #[test]
+  fn settling_ignores_writes_of_the_same_value() {
+    // counts m[20] up to m[22] by m[21], then keeps copying it onto itself
+    let program = [
+      [1, 20, 20, 21],
+      [14, 0, 20, 22],
+      [5, 20, 20, 0],
+      [13, 8, 0, 0],
+    ];
+    let settle = |watch, step_limit| {
+      let mut environment = Environment::default();
+      for (addr, words) in program.iter().enumerate() {
+        environment.memory[addr * 4..addr * 4 + 4].copy_from_slice(words);
+      }
+      environment.memory[21] = 1;
+      environment.memory[22] = 3;
+      run_until_settled(
+        &mut environment,
+        &mut headless_devices(),
+        watch,
+        10,
+        step_limit,
+      )
+    };
+
+    // the last real change is the third add, step 4
+    assert!(matches!(
+      settle(None, 100),
+      RunOutcome::Settled { steps: 5 }
+    ));
+    assert!(matches!(
+      settle(Some(20), 100),
+      RunOutcome::Settled { steps: 5 }
+    ));
+    assert!(matches!(
+      settle(Some(30), 100),
+      RunOutcome::Settled { steps: 0 }
+    ));
+    assert!(matches!(
+      settle(None, 12),
+      RunOutcome::StepLimit { steps: 12 }
+    ));
+  }
+
+  #[test]
+  fn divergence_is_found_at_the_first_differing_step() {
+    let overflow = |arithmetic| {
+      let mut environment = Environment::default();
+      environment.memory[..8].copy_from_slice(&[5, 30, 31, 0, 1, 20, 21, 22]);
+      environment.memory[21] = i32::MAX;
+      environment.memory[22] = 1;
+      environment.arithmetic = arithmetic;
+      environment
+    };
+
+    let divergence = find_divergence(
+      &mut overflow(ArithmeticMode::Wrapping),
+      &mut overflow(ArithmeticMode::Saturating),
+      100,
+    )
+    .unwrap();
+    assert_eq!(divergence.steps, 2);
+    assert_eq!(divergence.iars, (8, 8));
+    assert_eq!(divergence.cells.len(), 1);
+    assert_eq!(
+      divergence.to_string(),
+      format!(
+        "diverged at step 2: IAR 8 vs 8, ok vs ok\n20: {} vs {}",
+        i32::MIN,
+        i32::MAX
+      )
+    );
+
+    let (mut left, mut right) = (
+      overflow(ArithmeticMode::Wrapping),
+      overflow(ArithmeticMode::Wrapping),
+    );
+    assert!(find_divergence(&mut left, &mut right, 100).is_none());
+    right.memory[22] = 2;
+    let divergence = find_divergence(&mut left, &mut right, 100).unwrap();
+    assert_eq!(divergence.steps, 0);
+    assert_eq!(divergence.outcomes.0, "loaded");
+  }
+}
@@ -1,9 +1,11 @@
 use core::Environment;
 use std::{
+  collections::{HashSet, VecDeque},
   fs::File,
-  path::PathBuf,
+  io::Write,
+  path::{Path, PathBuf},
   sync::{
-    atomic::{AtomicU16, Ordering},
+    atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering},
     Arc,
   },
   time::{Duration, SystemTime},
@@ -13,25 +15,51 @@ use color_eyre::eyre::{self, OptionExt};
 use devices::DeviceArray;
 use executor::ExecutorReport;
 use itertools::Itertools;
-use rat_ftable::{selection::NoSelection, Table, TableState};
+use rat_ftable::{
+  selection::{cellselection, CellSelection},
+  Table, TableState,
+};
 use ratatui::{
   crossterm::event,
-  layout::{Constraint, Direction, Layout},
-  style::{Color, Style},
-  widgets::{Block, Paragraph, Widget},
+  layout::{Constraint, Direction, Layout, Margin},
+  style::{Color, Modifier, Style},
+  widgets::{
+    Block, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, Widget,
+    Wrap,
+  },
   DefaultTerminal,
 };
 use ratatui_explorer::{FileExplorer, Theme};
-use tokio::sync::Mutex;
+use tokio::sync::mpsc;
 use tokio_stream::StreamExt;
 use tui_input::backend::crossterm::EventHandler;
 
+mod asm;
+mod backtrace;
 mod core;
 mod devices;
+mod disasm;
 mod executor;
+mod headless;
 mod memtable;
+mod profile;
+mod replay;
+mod sched;
 mod sdlcore;
 
+const DRAW_SEND_TIMEOUT: Duration = Duration::from_millis(100);
+/// Shortest gap between redraws asked for by devices. The displays are read
+/// when drawing, so skipping a redraw never loses a value, it only shows the
+/// one after.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+/// Instructions per second [t] cycles through, slow enough at the low end to
+/// watch the displays change. `None` runs flat out.
+const THROTTLE_RATES: [Option<u32>; 5] =
+  [None, Some(1), Some(10), Some(100), Some(1000)];
+/// Most matches a search keeps, so a query like `0` on fresh memory doesn't
+/// list every cell. [n] only cycles through these.
+const SEARCH_LIMIT: usize = 1000;
+
 fn setup_logger() -> eyre::Result<()> {
   let colors = fern::colors::ColoredLevelConfig::default();
   fern::Dispatch::new()
@@ -51,25 +79,82 @@ fn setup_logger() -> eyre::Result<()> {
   Ok(())
 }
 
-#[tokio::main]
-async fn main() -> eyre::Result<()> {
+fn main() -> eyre::Result<()> {
   color_eyre::install()?;
   setup_logger()?;
 
   log::info!("Logging harness setup");
 
-  let terminal = ratatui::init();
-  let lcd_device = devices::onboard::LcdDisplayDevice::default();
+  let args = std::env::args().collect::<Vec<_>>();
+  match args.get(1).map(String::as_str) {
+    Some("grade") => return grade_command(&args[2..]),
+    Some("asm") => return asm_command(&args[2..]),
+    Some("check") => return check_command(&args[2..]),
+    Some("patch") => return patch_command(&args[2..]),
+    Some("replay") => return replay_command(&args[2..]),
+    Some("settle") => return settle_command(&args[2..]),
+    Some("extract") => return extract_command(&args[2..]),
+    Some("diverge") => return diverge_command(&args[2..]),
+    _ => {}
+  }
+
+  // the headless commands above run their devices on a scheduler of their
+  // own, which can't be started from inside another runtime
+  tokio::runtime::Runtime::new()?.block_on(interactive(args))
+}
+
+async fn interactive(args: Vec<String>) -> eyre::Result<()> {
+  // the terminal only goes raw once the devices are set up, so a bad
+  // option reports normally
+  let lcd_device = match args
+    .iter()
+    .position(|arg| arg == "--lcd-charmap")
+    .and_then(|index| args.get(index + 1))
+  {
+    Some(path) => devices::onboard::LcdDisplayDevice::with_charmap(
+      devices::onboard::parse_charmap(&std::fs::read_to_string(path)?)?,
+    ),
+    None => devices::onboard::LcdDisplayDevice::default(),
+  };
   let hex_device = devices::onboard::HexDisplayDevice::default();
+  let led_device = devices::onboard::LEDDisplayDevice::default();
 
-  let (sdl_pipes_back, sdl_pipes_front) = sdlcore::create_pipes();
+  // a longer queue absorbs bigger bursts of draws before they block or drop,
+  // at the cost of the window lagging further behind the program
+  let draw_queue = match args
+    .iter()
+    .position(|arg| arg == "--draw-queue")
+    .and_then(|index| args.get(index + 1))
+  {
+    Some(len) => match len.parse()? {
+      0 => eyre::bail!("--draw-queue must be at least 1"),
+      len => len,
+    },
+    None => sdlcore::DEFAULT_DRAW_QUEUE,
+  };
+  let (sdl_pipes_back, sdl_pipes_front) = sdlcore::create_pipes(draw_queue);
 
-  let vga_device = devices::vga::VgaDevice::new(sdl_pipes_front.draw_cmd_tx);
+  // blocking keeps bursts of draws intact; dropping keeps the executor from
+  // ever stalling on a slow window
+  let draw_send_mode = if args.iter().any(|arg| arg == "--drop-draws") {
+    devices::vga::DrawSendMode::Drop
+  } else {
+    devices::vga::DrawSendMode::Block(DRAW_SEND_TIMEOUT)
+  };
+  let vga_device = devices::vga::VgaDevice::new(
+    sdl_pipes_front.draw_cmd_tx,
+    sdl_pipes_front.framebuffer,
+    draw_send_mode,
+  );
   let kbd_device =
     devices::kbd::KbdDevice::init(sdl_pipes_front.kbd_ev_rx.clone());
+  let mouse_device =
+    devices::mouse::MouseDevice::init(sdl_pipes_front.mouse_ev_rx);
+  let tscr_device = devices::tscr::TscrDevice::init(sdl_pipes_front.tscr_ev_rx);
+  let (term_kbd_device, term_key_tx) = devices::kbd::TermKbdDevice::new();
 
   let mut kbd_ev_rx = sdl_pipes_front.kbd_ev_rx;
-  tokio::spawn(async move {
+  let kbd_logger = tokio::spawn(async move {
     loop {
       kbd_ev_rx.changed().await?;
       tracing::info!("Key event changed");
@@ -79,30 +164,322 @@ async fn main() -> eyre::Result<()> {
     eyre::Result::<()>::Ok(())
   });
 
+  let mut device_array = DeviceArray::default();
   let device_refs = TerminalDeviceRefs {
     hex0: hex_device.hex0.clone(),
     hex1: hex_device.hex1.clone(),
+    decimal0: hex_device.decimal0.clone(),
+    decimal1: hex_device.decimal1.clone(),
+    leds: led_device.leds.clone(),
     lcd_display: lcd_device.lcd.clone(),
+    frozen: device_array.frozen(),
   };
 
-  let mut device_array = DeviceArray::default();
-  device_array.register_device(Box::new(lcd_device));
-  device_array.register_device(Box::new(hex_device));
-  device_array.register_device(Box::new(vga_device));
-  device_array.register_device(Box::new(kbd_device));
-  let (exec, executor_handler) =
-    executor::Executor::new(Environment::default(), device_array);
+  device_array.register_device(Box::new(lcd_device))?;
+  device_array.register_device(Box::new(hex_device))?;
+  device_array.register_device(Box::new(led_device))?;
+  device_array.register_device(Box::new(vga_device))?;
+  device_array.register_device(Box::new(kbd_device))?;
+  device_array.register_device(Box::new(mouse_device))?;
+  device_array.register_device(Box::new(tscr_device))?;
+  device_array.register_device(Box::new(term_kbd_device))?;
+  device_array
+    .register_device(Box::new(devices::timer::TimerDevice::init()))?;
+  device_array.register_device(Box::new(devices::rng::RngDevice::new()))?;
+  device_array
+    .register_device(Box::new(devices::scratch::ScratchDevice::default()))?;
+  device_array
+    .register_device(Box::new(devices::sink::FileSinkDevice::default()))?;
+  let inventory = device_array.inventory();
+  device_array
+    .register_device(Box::new(devices::info::InfoDevice::new(inventory)))?;
+  // each `--scratch-at <addr>` maps one more scratch bank starting there
+  for base in args
+    .iter()
+    .zip(args.iter().skip(1))
+    .filter(|(flag, _)| *flag == "--scratch-at")
+    .map(|(_, base)| base)
+  {
+    let base = match base.strip_prefix("0x") {
+      Some(hex) => u32::from_str_radix(hex, 16)?,
+      None => base.parse()?,
+    };
+    device_array.register_device_at(
+      Box::new(devices::scratch::ScratchDevice::default()),
+      base,
+    )?;
+  }
+  // each `--latency <kind>=<cycles>` slows every device of that kind
+  for latency in args
+    .iter()
+    .zip(args.iter().skip(1))
+    .filter(|(flag, _)| *flag == "--latency")
+    .map(|(_, latency)| latency)
+  {
+    let Some((kind, cycles)) = latency.split_once('=') else {
+      eyre::bail!("expected --latency <kind>=<cycles>, got {latency:?}");
+    };
+    device_array.set_latency(kind.parse()?, cycles.parse()?);
+  }
+  let (mut exec, executor_handler) =
+    executor::Executor::new(Environment::default(), device_array, term_key_tx);
+  // `--undo-depth N`, and `--autosave-secs N` or `--autosave-steps M`, which
+  // are off unless given
+  for (flag, value) in args.iter().zip(args.iter().skip(1)) {
+    match flag.as_str() {
+      "--undo-depth" => exec.set_undo_depth(value.parse()?),
+      "--autosave-secs" => exec.set_autosave(executor::Autosave::Every(
+        Duration::from_secs(value.parse()?),
+      )),
+      "--autosave-steps" => {
+        exec.set_autosave(executor::Autosave::Steps(value.parse()?))
+      }
+      _ => {}
+    }
+  }
+  let terminal = ratatui::init();
 
   // let local_set = tokio::task::LocalSet::new();
-  let _sdl_runner = tokio::spawn(sdlcore::SdlExecutor::run(sdl_pipes_back));
-  let _exec_runner = tokio::spawn(exec.process());
+  let sdl_runner = sdlcore::SdlExecutor::run(sdl_pipes_back);
+  let exec_runner = tokio::spawn(exec.process());
   // let _sdl_runner = tokio::task::spawn_local(sdl_exec.process());
   // local_set.spawn_local(sdl_exec.process());
-  let result = run(terminal, executor_handler, device_refs).await;
+  let dump_format = core::DumpFormat {
+    endianness: if args.iter().any(|arg| arg == "--big-endian") {
+      core::Endianness::Big
+    } else {
+      core::Endianness::Little
+    },
+    trim: args.iter().any(|arg| arg == "--trim-dump"),
+  };
+  let bell = args.iter().any(|arg| arg == "--bell");
+  let result =
+    run(terminal, executor_handler, device_refs, dump_format, bell).await;
+  let shutdown_result = shutdown(exec_runner, sdl_runner, kbd_logger).await;
 
   ratatui::restore();
 
-  result
+  result.and(shutdown_result)
+}
+
+/// Tears the machine down in dependency order once the UI has exited and
+/// dropped its executor handler:
+///
+/// 1. the executor leaves its loop, waits for pending device writes, and
+///    drops the devices, cancelling the keyboard listeners and closing the
+///    VGA draw queue;
+/// 2. the SDL thread draws whatever was still queued and exits, dropping
+///    the input pipes;
+/// 3. the key event logger sees its pipe close and finishes.
+async fn shutdown(
+  exec_runner: tokio::task::JoinHandle<eyre::Result<()>>,
+  sdl_runner: std::thread::JoinHandle<eyre::Result<()>>,
+  kbd_logger: tokio::task::JoinHandle<eyre::Result<()>>,
+) -> eyre::Result<()> {
+  exec_runner.await??;
+  tokio::task::spawn_blocking(move || sdl_runner.join())
+    .await?
+    .map_err(|_| eyre::eyre!("SDL thread panicked"))??;
+  // its only way out is the pipe closing, which isn't worth reporting
+  let _ = kbd_logger.await;
+  Ok(())
+}
+
+fn grade_command(args: &[String]) -> eyre::Result<()> {
+  const USAGE: &str = "usage: case100 grade [--strict] [--args a,b,...] \
+    [--arith legacy|wrapping|saturating|checked] [--costs <costs.txt>] \
+    <program.mif> <expected.mif> [steps]";
+
+  let mut strict = false;
+  let mut program_args = None;
+  let mut arithmetic = core::ArithmeticMode::default();
+  let mut cycle_costs = [1; core::OPCODE_COUNT];
+  let mut positional = Vec::new();
+  let mut args = args.iter();
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--strict" => strict = true,
+      "--args" => {
+        let list = args.next().ok_or_else(|| eyre::eyre!(USAGE))?;
+        program_args = Some(
+          list
+            .split(',')
+            .map(|arg| arg.trim().parse::<i32>())
+            .collect::<Result<Vec<_>, _>>()?,
+        );
+      }
+      "--costs" => {
+        let path = args.next().ok_or_else(|| eyre::eyre!(USAGE))?;
+        cycle_costs = asm::parse_costs(&std::fs::read_to_string(path)?)?;
+      }
+      "--arith" => {
+        arithmetic = args.next().ok_or_else(|| eyre::eyre!(USAGE))?.parse()?;
+      }
+      _ => positional.push(arg),
+    }
+  }
+  let [program, expected, rest @ ..] = positional.as_slice() else {
+    eyre::bail!(USAGE);
+  };
+  let step_limit = match rest.first() {
+    Some(limit) => limit.parse()?,
+    None => headless::DEFAULT_STEP_LIMIT,
+  };
+
+  if !headless::grade(
+    program.as_ref(),
+    expected.as_ref(),
+    step_limit,
+    strict,
+    program_args.as_deref(),
+    arithmetic,
+    cycle_costs,
+  )? {
+    std::process::exit(1);
+  }
+
+  Ok(())
+}
+
+fn asm_command(args: &[String]) -> eyre::Result<()> {
+  let [source, output, listing @ ..] = args else {
+    eyre::bail!("usage: case100 asm <source.e> <output.mif> [listing.lst]");
+  };
+
+  let assembly = asm::assemble(&std::fs::read_to_string(source)?)?;
+  assembly.write_mif(&mut File::create(output)?)?;
+  if let Some(listing) = listing.first() {
+    assembly.write_listing(&mut File::create(listing)?)?;
+  }
+
+  Ok(())
+}
+
+/// Assembles a routine at a base address over an existing image, leaving the
+/// rest of the image alone.
+fn patch_command(args: &[String]) -> eyre::Result<()> {
+  let [program, source, base, output] = args else {
+    eyre::bail!(
+      "usage: case100 patch <program.mif> <routine.e> <base> <output.mif>"
+    );
+  };
+
+  let mut environment = Environment::parse(&mut File::open(program)?)?;
+  let assembly = asm::assemble_into(
+    &std::fs::read_to_string(source)?,
+    &mut environment,
+    base.parse()?,
+  )?;
+  for (label, addr) in assembly.labels.iter() {
+    println!("{label}\t{addr}");
+  }
+  environment.to_mif(&mut File::create(output)?)
+}
+
+fn check_command(args: &[String]) -> eyre::Result<()> {
+  let [source] = args else {
+    eyre::bail!("usage: case100 check <source.e>");
+  };
+
+  let diagnostics = asm::check(&std::fs::read_to_string(source)?);
+  for diagnostic in diagnostics.iter() {
+    println!("{source}:{diagnostic}");
+  }
+  if !diagnostics.is_empty() {
+    std::process::exit(1);
+  }
+
+  Ok(())
+}
+
+fn replay_command(args: &[String]) -> eyre::Result<()> {
+  let [program, inputs, rest @ ..] = args else {
+    eyre::bail!("usage: case100 replay <program.mif> <inputs> [steps]");
+  };
+  let step_limit = match rest.first() {
+    Some(limit) => limit.parse()?,
+    None => headless::DEFAULT_STEP_LIMIT,
+  };
+
+  replay::replay(program.as_ref(), inputs.as_ref(), step_limit)
+}
+
+fn settle_command(args: &[String]) -> eyre::Result<()> {
+  let (watch, args) = match args {
+    [flag, addr, rest @ ..] if flag == "--watch" => (Some(addr.parse()?), rest),
+    _ => (None, args),
+  };
+  let [program, rest @ ..] = args else {
+    eyre::bail!(
+      "usage: case100 settle [--watch <addr>] <program.mif> [patience] [steps]"
+    );
+  };
+  let patience = match rest.first() {
+    Some(patience) => patience.parse()?,
+    None => headless::DEFAULT_PATIENCE,
+  };
+  let step_limit = match rest.get(1) {
+    Some(limit) => limit.parse()?,
+    None => headless::DEFAULT_STEP_LIMIT,
+  };
+
+  let mut environment = Environment::parse(&mut File::open(program)?)?;
+  let mut device_array = headless::headless_devices();
+  let outcome = headless::run_until_settled(
+    &mut environment,
+    &mut device_array,
+    watch,
+    patience,
+    step_limit,
+  );
+  println!("{outcome} (IAR {})", environment.iar);
+  if let Some(addr) = watch {
+    match environment.memory.get(addr as usize) {
+      Some(value) => println!("{addr} = {value}"),
+      None => println!("{addr} is not in memory"),
+    }
+  }
+
+  Ok(())
+}
+
+fn diverge_command(args: &[String]) -> eyre::Result<()> {
+  let [left, right, rest @ ..] = args else {
+    eyre::bail!("usage: case100 diverge <left.mif> <right.mif> [steps]");
+  };
+  let step_limit = match rest.first() {
+    Some(limit) => limit.parse()?,
+    None => headless::DEFAULT_STEP_LIMIT,
+  };
+
+  let mut left = Environment::parse(&mut File::open(left)?)?;
+  let mut right = Environment::parse(&mut File::open(right)?)?;
+  match headless::find_divergence(&mut left, &mut right, step_limit) {
+    Some(divergence) => {
+      println!("{divergence}");
+      std::process::exit(1);
+    }
+    None => println!("no divergence (IAR {})", left.iar),
+  }
+
+  Ok(())
+}
+
+fn extract_command(args: &[String]) -> eyre::Result<()> {
+  let [program, start, end, output] = args else {
+    eyre::bail!(
+      "usage: case100 extract <program.mif> <start> <end> <output.(mif|bin)>"
+    );
+  };
+  let (start, end) = (start.parse()?, end.parse()?);
+
+  let environment = Environment::parse(&mut File::open(program)?)?;
+  let mut file = File::create(output)?;
+  if output.ends_with(".bin") {
+    environment.dump_range(&mut file, core::DumpFormat::default(), start, end)
+  } else {
+    environment.extract(start, end)?.to_mif(&mut file)
+  }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -111,6 +488,15 @@ enum MenuState {
   FileSelection,
 }
 
+/// What the input shown in the memory table's title is for.
+#[derive(Debug, Clone, Copy)]
+enum MemoryPrompt {
+  /// A new value for the selected cell.
+  Poke,
+  /// An address to select.
+  Goto,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum MenuActive {
   Assemble,
@@ -121,46 +507,67 @@ enum MenuActive {
   Steps,
   Break,
   Watch,
+  Search,
 }
 
 impl MenuActive {
-  fn decr(self) -> Self {
-    match self {
-      Self::Assemble => Self::Watch,
-      Self::File => Self::Assemble,
-      Self::Load => Self::File,
-      Self::Run => Self::Load,
-      Self::Reset => Self::Run,
-      Self::Steps => Self::Reset,
-      Self::Break => Self::Steps,
-      Self::Watch => Self::Break,
-    }
+  /// The order Tab moves through the buttons; BackTab walks it backwards.
+  const TAB_ORDER: [MenuActive; 9] = [
+    Self::Assemble,
+    Self::File,
+    Self::Load,
+    Self::Run,
+    Self::Reset,
+    Self::Steps,
+    Self::Break,
+    Self::Watch,
+    Self::Search,
+  ];
+
+  /// The next button in tab order for which `enabled` holds, or `self` if
+  /// there are none.
+  fn cycle(self, forward: bool, enabled: impl Fn(Self) -> bool) -> Self {
+    let order = Self::TAB_ORDER;
+    let start = order.iter().position(|item| *item == self).unwrap_or(0);
+    (1..=order.len())
+      .map(|offset| {
+        if forward {
+          order[(start + offset) % order.len()]
+        } else {
+          order[(start + order.len() - offset) % order.len()]
+        }
+      })
+      .find(|item| enabled(*item))
+      .unwrap_or(self)
   }
+}
 
-  fn incr(self) -> Self {
-    match self {
-      Self::Assemble => Self::File,
-      Self::File => Self::Load,
-      Self::Load => Self::Run,
-      Self::Run => Self::Reset,
-      Self::Reset => Self::Steps,
-      Self::Steps => Self::Break,
-      Self::Break => Self::Watch,
-      Self::Watch => Self::Assemble,
-    }
+/// Whether a button can currently do anything: Assemble needs a file and Run
+/// needs an environment that hasn't faulted.
+fn menu_enabled(target: MenuActive, faulted: bool, has_file: bool) -> bool {
+  match target {
+    MenuActive::Assemble => has_file,
+    MenuActive::Run => !faulted,
+    _ => true,
   }
 }
 
 struct TerminalDeviceRefs {
   hex0: Arc<AtomicU16>,
   hex1: Arc<AtomicU16>,
-  lcd_display: Arc<Mutex<[[char; 14]; 2]>>,
+  decimal0: Arc<AtomicBool>,
+  decimal1: Arc<AtomicBool>,
+  leds: Arc<AtomicU32>,
+  lcd_display: Arc<std::sync::Mutex<[[char; 14]; 2]>>,
+  frozen: Arc<AtomicBool>,
 }
 
 async fn run(
   mut terminal: DefaultTerminal,
   mut executor_handler: executor::ExecutorHandler,
   device_refs: TerminalDeviceRefs,
+  dump_format: core::DumpFormat,
+  bell: bool,
 ) -> eyre::Result<()> {
   let theme = Theme::default();
   let mut file_explorer = FileExplorer::with_theme(theme)?;
@@ -170,10 +577,59 @@ async fn run(
   let mut steps_input = tui_input::Input::default();
   let mut break_input = tui_input::Input::default();
   let mut watch_input = tui_input::Input::default();
+  let mut search_input = tui_input::Input::default();
+  let mut search_results = Vec::<u32>::new();
+  let mut search_index = 0;
+  // the cells last jumped through with [c], and where in them the next
+  // press goes
+  let mut recent_writes = Vec::<u32>::new();
+  let mut recent_index = 0;
   let mut assembled_environment = Environment::default();
   let mut environment = Environment::default();
+  // switched with [o] between runs and carried over to every image loaded
+  let mut arithmetic = core::ArithmeticMode::default();
+  // an index into THROTTLE_RATES
+  let mut throttle = 0;
   let mut memtable_state = TableState::new();
+  let mut memtable_columns = memtable::DEFAULT_COLUMNS;
+  let mut memtable_unsigned = false;
+  // keeps the IAR's row centred in the memory table on every draw; browsing
+  // the table by hand turns it off, and [i] turns it back on
+  let mut follow_iar = true;
+  // a value for the selected cell ([e]) or an address to go to ([g]),
+  // until Enter acts on it or Esc drops it
+  let mut memory_prompt = None::<(MemoryPrompt, tui_input::Input)>;
   let mut request_redraw = true;
+  let mut capture_keys = false;
+  // set by a failure report and cleared by anything that replaces or resets
+  // the environment; Run stays disabled while it's set since stepping a
+  // poisoned environment only faults again
+  let mut fault = None::<(core::StepFatal, backtrace::Backtrace)>;
+  // the cell the executor last reported written, cleared along with the
+  // fault whenever the environment is replaced
+  let mut highlight = None::<u32>;
+  let mut lcd_text = String::new();
+  let mut lcd_dirty = true;
+  let mut hotspots = Vec::<(u32, u64)>::new();
+  // refreshed alongside the hotspots, and on every stats report so it
+  // follows a running program
+  let mut heatmap = None::<memtable::Heatmap>;
+  // instructions per second over the last stats report while running
+  let mut ips = None::<f64>;
+  // halts and faults that arrived while the file picker was open, handled
+  // as soon as the normal view is back, with a notice shown over the picker
+  // in the meantime
+  let mut deferred = VecDeque::<ExecutorReport>::new();
+  let mut notice = None::<String>;
+  // the outcome of the last reload, breakpoint or watchpoint, until the
+  // next one
+  let mut message = None::<String>;
+  // device redraws are coalesced here rather than in the devices, which
+  // flag every write: one arriving too soon after the last draw sets a
+  // deadline instead, and the draw at that deadline shows whatever the
+  // displays hold by then, so a burst of writes still ends on its last value
+  let mut last_draw = tokio::time::Instant::now();
+  let mut redraw_due = None::<tokio::time::Instant>;
   let mut term_event_stream = std::pin::pin! {async_stream::stream! {
     loop {
       if let Ok(true) = event::poll(Duration::ZERO) {
@@ -188,17 +644,25 @@ async fn run(
     match state {
       MenuState::Normal => {
         if request_redraw {
-          let lcd_text = device_refs
-            .lcd_display
-            .lock()
-            .await
-            .iter()
-            .map(|line| line.iter().collect::<String>())
-            .join("\n");
+          // read live rather than from `environment`, which is only
+          // refreshed once the executor stops
+          let iar = executor_handler.iar.load(Ordering::Relaxed);
+          // the LCD is the only display behind a lock, so it's only re-read
+          // once the executor reports it changed
+          if lcd_dirty {
+            lcd_text = device_refs
+              .lcd_display
+              .lock()
+              .unwrap()
+              .iter()
+              .map(|line| line.iter().collect::<String>())
+              .join("\n");
+            lcd_dirty = false;
+          }
           terminal.draw(|f| {
             let major_layout = Layout::default()
               .direction(Direction::Vertical)
-              .constraints(vec![Constraint::Length(12), Constraint::Fill(1)])
+              .constraints(vec![Constraint::Length(15), Constraint::Fill(1)])
               .split(f.area());
 
             let control_block = Block::bordered();
@@ -210,17 +674,31 @@ async fn run(
               .constraints(vec![
                 Constraint::Length(3),
                 Constraint::Length(3),
-                Constraint::Length(4),
+                Constraint::Length(7),
               ])
               .split(control_area);
 
             let top_layout = Layout::default()
               .direction(Direction::Horizontal)
-              .constraints(vec![Constraint::Length(16), Constraint::Fill(1)])
+              .constraints(vec![
+                Constraint::Length(16),
+                Constraint::Fill(1),
+                Constraint::Length(24),
+              ])
               .split(control_layout[0]);
 
             f.render_widget(
-              make_button("Assemble", "[a]", &active, MenuActive::Assemble),
+              make_button_with(
+                "Assemble",
+                "[a]",
+                &active,
+                MenuActive::Assemble,
+                menu_enabled(
+                  MenuActive::Assemble,
+                  fault.is_some(),
+                  !filepath.as_os_str().is_empty(),
+                ),
+              ),
               top_layout[0],
             );
 
@@ -234,6 +712,30 @@ async fn run(
               top_layout[1],
             );
 
+            let search_title = if search_results.is_empty() {
+              "Search [/]".to_owned()
+            } else {
+              format!(
+                "Search [n] {}/{}{}",
+                search_index + 1,
+                search_results.len(),
+                if search_results.len() == SEARCH_LIMIT {
+                  "+"
+                } else {
+                  ""
+                }
+              )
+            };
+            f.render_widget(
+              make_button(
+                search_input.value(),
+                &search_title,
+                &active,
+                MenuActive::Search,
+              ),
+              top_layout[2],
+            );
+
             let middle_layout = Layout::default()
               .direction(Direction::Horizontal)
               .constraints(vec![
@@ -243,7 +745,7 @@ async fn run(
                 Constraint::Length(16),
                 Constraint::Length(16),
                 Constraint::Length(16),
-                Constraint::Length(6),
+                Constraint::Length(40),
                 Constraint::Fill(1),
               ])
               .split(control_layout[1]);
@@ -254,7 +756,7 @@ async fn run(
             );
 
             f.render_widget(
-              make_button(
+              make_button_with(
                 if executor_handler.running.load(Ordering::Relaxed) {
                   "Stop"
                 } else {
@@ -263,6 +765,11 @@ async fn run(
                 "[r]",
                 &active,
                 MenuActive::Run,
+                menu_enabled(
+                  MenuActive::Run,
+                  fault.is_some(),
+                  !filepath.as_os_str().is_empty(),
+                ),
               ),
               middle_layout[1],
             );
@@ -300,80 +807,456 @@ async fn run(
               middle_layout[5],
             );
 
+            let next = match current_instruction(&environment, iar) {
+              Some([instr, a1, a2, a3]) => {
+                Paragraph::new(disasm::disassemble(instr, a1, a2, a3))
+              }
+              None => Paragraph::new("<out of range>")
+                .style(Style::new().fg(Color::DarkGray)),
+            };
             f.render_widget(
-              Paragraph::new("").block(Block::bordered().title("IAR")),
+              next.block(
+                Block::bordered()
+                  .title(format!("IAR {iar} (instr {})", iar / 4)),
+              ),
               middle_layout[6],
             );
 
             f.render_widget(
-              Paragraph::new(
-                if executor_handler.running.load(Ordering::Relaxed) {
-                  "Running"
+              Paragraph::new(format!(
+                "{}{}{}{}{}{}{}",
+                match &fault {
+                  Some((core::StepFatal::Halted, _)) => "Halted".to_owned(),
+                  Some((error, _)) => format!("FAULTED: {error}"),
+                  None if executor_handler.running.load(Ordering::Relaxed) => {
+                    match ips {
+                      Some(ips) => format!("Running ({ips:.0} IPS)"),
+                      None => "Running".to_owned(),
+                    }
+                  }
+                  None => "Stopped".to_owned(),
+                },
+                if device_refs.frozen.load(Ordering::Relaxed) {
+                  " (output frozen [z])"
                 } else {
-                  "Stopped"
+                  ""
                 },
-              ),
+                match arithmetic {
+                  core::ArithmeticMode::Legacy => String::new(),
+                  mode => format!(" ({mode} arithmetic [o])"),
+                },
+                match THROTTLE_RATES[throttle] {
+                  Some(rate) => format!(" ({rate} Hz [t])"),
+                  None => String::new(),
+                },
+                match &fault {
+                  // a halt is where the program meant to be, so how it got
+                  // there isn't interesting
+                  Some((core::StepFatal::Halted, _)) => {
+                    " - reset [ESC]".to_owned()
+                  }
+                  Some((_, backtrace)) =>
+                    format!(" - reset [ESC]\n{backtrace}"),
+                  None => String::new(),
+                },
+                if capture_keys {
+                  "\nKeys to program [F2]"
+                } else {
+                  ""
+                },
+                match &message {
+                  Some(message) => format!("\n{message}"),
+                  None => String::new(),
+                }
+              )),
               middle_layout[7],
             );
 
             let hex_lcd_layout = Layout::default()
               .direction(Direction::Horizontal)
               .constraints(vec![
-                Constraint::Length(6),
-                Constraint::Length(6),
+                // room for five decimal digits
+                Constraint::Length(7),
+                Constraint::Length(7),
+                Constraint::Length(devices::onboard::LED_COUNT as u16 + 2),
                 Constraint::Length(18),
+                Constraint::Length(24),
+                Constraint::Length(14),
                 Constraint::Fill(1),
               ])
               .split(control_layout[2]);
 
             f.render_widget(
-              Paragraph::new(format!(
-                "{:04x}",
-                device_refs.hex1.load(Ordering::Relaxed)
+              Paragraph::new(devices::onboard::format_hex(
+                device_refs.hex1.load(Ordering::Relaxed),
+                device_refs.decimal1.load(Ordering::Relaxed),
               ))
               .block(Block::bordered().title("H7-4")),
               hex_lcd_layout[0],
             );
             f.render_widget(
-              Paragraph::new(format!(
-                "{:04x}",
-                device_refs.hex0.load(Ordering::Relaxed)
+              Paragraph::new(devices::onboard::format_hex(
+                device_refs.hex0.load(Ordering::Relaxed),
+                device_refs.decimal0.load(Ordering::Relaxed),
               ))
               .block(Block::bordered().title("H3-0")),
               hex_lcd_layout[1],
             );
+            // the highest LED on the left, like the bits of the register
+            let leds = device_refs.leds.load(Ordering::Relaxed);
             f.render_widget(
-              Paragraph::new(lcd_text).block(Block::bordered().title("LCD")),
+              Paragraph::new(
+                (0..devices::onboard::LED_COUNT)
+                  .rev()
+                  .map(|led| if leds >> led & 1 == 1 { '■' } else { '□' })
+                  .collect::<String>(),
+              )
+              .block(Block::bordered().title("LEDs")),
               hex_lcd_layout[2],
             );
+            f.render_widget(
+              Paragraph::new(lcd_text.as_str())
+                .block(Block::bordered().title("LCD")),
+              hex_lcd_layout[3],
+            );
 
+            // one line per operand of the instruction at IAR, so the panel
+            // row is tall enough for three
+            let (operands_title, operands) =
+              describe_operands(&environment, iar);
+            f.render_widget(
+              Paragraph::new(operands)
+                .block(Block::bordered().title(operands_title)),
+              hex_lcd_layout[4],
+            );
+            f.render_widget(
+              Paragraph::new(
+                executor_handler.steps.load(Ordering::Relaxed).to_string(),
+              )
+              .block(Block::bordered().title("Executed")),
+              hex_lcd_layout[5],
+            );
+
+            // a fault takes over the hotspot panel with what was running;
+            // the row is tall enough for the whole window
+            match &fault {
+              Some((error, _)) if !matches!(error, core::StepFatal::Halted) => {
+                let context = asm::fault_context(&environment, environment.iar)
+                  .into_iter()
+                  .map(|(addr, text)| {
+                    let marker =
+                      if addr == environment.iar { '>' } else { ' ' };
+                    format!("{marker}{addr:>5}: {text}")
+                  })
+                  .join("\n");
+                f.render_widget(
+                  Paragraph::new(context)
+                    .block(Block::bordered().title("Fault context")),
+                  hex_lcd_layout[6],
+                );
+              }
+              _ => f.render_widget(
+                Paragraph::new(
+                  hotspots
+                    .iter()
+                    .map(|(addr, count)| format!("{addr}: {count}"))
+                    .join("  "),
+                )
+                .wrap(Wrap { trim: true })
+                .block(Block::bordered().title(
+                  if executor_handler.profiling.load(Ordering::Relaxed) {
+                    "Hotspots [h] on"
+                  } else {
+                    "Hotspots [h]"
+                  },
+                )),
+                hex_lcd_layout[6],
+              ),
+            }
+
+            // the scrollbar gets its own column beside the table, trimmed to
+            // line up with the inside of the table's border
+            let memory_layout = Layout::default()
+              .direction(Direction::Horizontal)
+              .constraints(vec![Constraint::Fill(1), Constraint::Length(1)])
+              .split(major_layout[1]);
+
+            let memory_title = match &memory_prompt {
+              Some((MemoryPrompt::Poke, input)) => {
+                format!("Memory - new value: {}", input.value())
+              }
+              Some((MemoryPrompt::Goto, input)) => {
+                format!("Memory - go to: {}", input.value())
+              }
+              None if follow_iar => "Memory - following IAR [i]".to_owned(),
+              None => "Memory".to_owned(),
+            };
+            if follow_iar {
+              let row = iar as usize / memtable_columns;
+              memtable_state.set_row_offset(
+                row.saturating_sub(memtable_state.page_len() / 2),
+              );
+            }
             f.render_stateful_widget(
-              Table::<NoSelection>::new()
-                .data(&environment)
-                .widths([Constraint::Fill(1); 11])
-                .block(Block::bordered().title("Memory")),
-              major_layout[1],
+              Table::<CellSelection>::new()
+                .data(memtable::EnvView {
+                  env: &environment,
+                  columns: memtable_columns,
+                  unsigned: memtable_unsigned,
+                  heatmap: heatmap.as_ref(),
+                  highlight,
+                })
+                .select_cell_style(Some(
+                  Style::new().add_modifier(Modifier::REVERSED),
+                ))
+                .block(Block::bordered().title(memory_title)),
+              memory_layout[0],
               &mut memtable_state,
             );
+
+            f.render_stateful_widget(
+              Scrollbar::new(ScrollbarOrientation::VerticalRight),
+              memory_layout[1].inner(Margin {
+                vertical: 1,
+                horizontal: 0,
+              }),
+              &mut ScrollbarState::new(memtable_state.row_max_offset())
+                .position(memtable_state.row_offset()),
+            );
           })?;
+          last_draw = tokio::time::Instant::now();
+          redraw_due = None;
         }
 
         request_redraw = true;
+        let deadline = redraw_due.unwrap_or(last_draw);
         tokio::select! {
+          _ = tokio::time::sleep_until(deadline), if redraw_due.is_some() => {}
           event = term_event_stream.next() => {
             let event = event.ok_or_eyre("Crossterm event pipe empty")??;
             match event {
+              // while capturing, keys go to the program rather than the menu
+              event::Event::Key(key) if capture_keys => match key.code {
+                event::KeyCode::F(2) | event::KeyCode::Esc => {
+                  capture_keys = false;
+                }
+                code => {
+                  let ascii = match code {
+                    event::KeyCode::Char(c) if c.is_ascii() => Some(c as u8),
+                    event::KeyCode::Enter => Some(b'\n'),
+                    event::KeyCode::Tab => Some(b'\t'),
+                    event::KeyCode::Backspace => Some(0x08),
+                    _ => None,
+                  };
+                  if let Some(ascii) = ascii {
+                    executor_handler
+                      .commands
+                      .send(executor::ExecutorCommand::Input { key: ascii })?;
+                  }
+                  request_redraw = false;
+                }
+              },
+              event::Event::Key(key) if memory_prompt.is_some() => match key.code {
+                event::KeyCode::Esc => memory_prompt = None,
+                event::KeyCode::Enter => match memory_prompt.take() {
+                  Some((MemoryPrompt::Goto, input)) => {
+                    match input.value().parse::<usize>() {
+                      Ok(addr) if addr < core::MEMORY_SIZE => {
+                        follow_iar = false;
+                        memtable_state.move_to(memtable::address_cell(
+                          addr,
+                          memtable_columns,
+                        ));
+                      }
+                      _ => {
+                        message = Some(format!(
+                          "No address {:?} in 0..{}",
+                          input.value(),
+                          core::MEMORY_SIZE
+                        ));
+                      }
+                    }
+                  }
+                  Some((MemoryPrompt::Poke, input)) => {
+                    let addr = memtable::selected_address(
+                      &memtable_state,
+                      memtable_columns,
+                    );
+                    let running =
+                      executor_handler.running.load(Ordering::Acquire);
+                    match (addr, input.value().parse::<i32>()) {
+                      _ if running => {
+                        message = Some(
+                          "Stop the program before editing memory".to_owned(),
+                        );
+                      }
+                      (Some(addr), Ok(value)) => {
+                        environment.memory[addr] = value;
+                        environment.initialized[addr] = true;
+                        // only the one cell, so nothing else the executor has
+                        // done since the last refresh is lost
+                        let mut guard =
+                          executor_handler.environment.lock().await;
+                        guard.memory[addr] = value;
+                        guard.initialized[addr] = true;
+                        std::mem::drop(guard);
+                        message = Some(format!("Set {addr} to {value}"));
+                      }
+                      (None, _) => {
+                        message = Some("No cell selected".to_owned());
+                      }
+                      (_, Err(_)) => {
+                        message =
+                          Some(format!("Invalid value {:?}", input.value()));
+                      }
+                    }
+                  }
+                  None => unreachable!(),
+                },
+                _ => {
+                  if let Some((_, input)) = &mut memory_prompt {
+                    input.handle_event(&event);
+                  }
+                }
+              },
               event::Event::Key(key) => match key.code {
                 event::KeyCode::Char('q') => break Ok(()),
-                event::KeyCode::Tab => active = active.incr(),
-                event::KeyCode::BackTab => active = active.decr(),
+                event::KeyCode::F(2) => capture_keys = true,
+                event::KeyCode::Tab | event::KeyCode::BackTab => {
+                  let has_file = !filepath.as_os_str().is_empty();
+                  active = active.cycle(key.code == event::KeyCode::Tab, |target| {
+                    menu_enabled(target, fault.is_some(), has_file)
+                  });
+                }
                 event::KeyCode::Esc => {
+                  fault = None;
+                  highlight = None;
                   executor_handler.running.store(false, Ordering::SeqCst);
                   let mut guard = executor_handler.environment.lock().await;
-                  guard.iar = 0;
+                  guard.reset();
                   environment = guard.clone();
                   std::mem::drop(guard);
+                  executor_handler
+                    .commands
+                    .send(executor::ExecutorCommand::Restart)?;
+                },
+                event::KeyCode::Char('-' | '&')
+                  if active == MenuActive::Search =>
+                {
+                  search_input.handle_event(&event);
+                }
+                event::KeyCode::Backspace => match active {
+                  MenuActive::Steps => {
+                    steps_input.handle_event(&event);
+                  }
+                  MenuActive::Break => {
+                    break_input.handle_event(&event);
+                  }
+                  MenuActive::Watch => {
+                    watch_input.handle_event(&event);
+                  }
+                  MenuActive::Search => {
+                    search_input.handle_event(&event);
+                  }
+                  _ => {
+                    request_redraw = false;
+                  }
                 },
+                // runs the typed number of steps in the background and
+                // stops, unless stopped first like any run; empty or zero
+                // does nothing
+                event::KeyCode::Enter if active == MenuActive::Steps => {
+                  match steps_input.value().parse::<usize>() {
+                    Ok(0) | Err(_) => request_redraw = false,
+                    Ok(_) if fault.is_some() => {
+                      message = Some(
+                        "Reset before stepping a faulted machine".to_owned(),
+                      );
+                    }
+                    Ok(count) => {
+                      executor_handler
+                        .commands
+                        .send(executor::ExecutorCommand::RunFor { count })?;
+                    }
+                  }
+                }
+                // breaks at the typed address, rounded down to the start of
+                // an instruction; empty stops breaking
+                event::KeyCode::Enter if active == MenuActive::Break => {
+                  let addrs = match break_input.value() {
+                    "" => Some(HashSet::new()),
+                    value => value.parse::<u32>().ok().map(|addr| {
+                      if addr % 4 != 0 {
+                        message = Some(format!(
+                          "Breakpoint {addr} moved to {}",
+                          addr & !3
+                        ));
+                      }
+                      HashSet::from([addr & !3])
+                    }),
+                  };
+                  match addrs {
+                    Some(addrs) => executor_handler.commands.send(
+                      executor::ExecutorCommand::SetBreakpoints { addrs },
+                    )?,
+                    None => {
+                      message = Some(format!(
+                        "Invalid breakpoint {:?}",
+                        break_input.value()
+                      ));
+                    }
+                  }
+                }
+                // watches the typed address, which may be a device
+                // register; empty stops watching
+                event::KeyCode::Enter if active == MenuActive::Watch => {
+                  let addr = match watch_input.value() {
+                    "" => Ok(None),
+                    value => value.parse::<u32>().map(Some),
+                  };
+                  match addr {
+                    Ok(addr) => executor_handler
+                      .commands
+                      .send(executor::ExecutorCommand::SetWatch { addr })?,
+                    Err(_) => {
+                      message = Some(format!(
+                        "Invalid watchpoint {:?}",
+                        watch_input.value()
+                      ));
+                    }
+                  }
+                }
+                event::KeyCode::Enter if active == MenuActive::Search => {
+                  match parse_search(search_input.value()) {
+                    Some((value, mask)) => {
+                      search_results =
+                        environment.find(value, mask, SEARCH_LIMIT);
+                      let selected = memtable::selected_address(
+                        &memtable_state,
+                        memtable_columns,
+                      );
+                      match next_match(&search_results, selected) {
+                        Some(index) => {
+                          search_index = index;
+                          follow_iar = false;
+                          memtable_state.move_to(memtable::address_cell(
+                            search_results[index] as usize,
+                            memtable_columns,
+                          ));
+                        }
+                        None => {
+                          message = Some(format!(
+                            "No cells match {:?}",
+                            search_input.value()
+                          ));
+                        }
+                      }
+                    }
+                    None => {
+                      log::warn!("Invalid search {:?}", search_input.value());
+                    }
+                  }
+                }
                 event::KeyCode::Char(c) => {
                   if c.is_digit(10) {
                     match active {
@@ -386,6 +1269,9 @@ async fn run(
                       MenuActive::Watch => {
                         watch_input.handle_event(&event);
                       }
+                      MenuActive::Search => {
+                        search_input.handle_event(&event);
+                      }
                       _ => {
                         request_redraw = false;
                       }
@@ -394,8 +1280,73 @@ async fn run(
                     match c {
                       'a' => {
                         active = MenuActive::Assemble;
-                        assembled_environment =
-                          Environment::parse(&mut File::open(filepath.clone())?)?;
+                        match load_image(&filepath, dump_format) {
+                          Ok(image) => {
+                            assembled_environment = image;
+                            assembled_environment.arithmetic = arithmetic;
+                          }
+                          Err(error) => {
+                            message = Some(format!(
+                              "Failed to assemble {}: {error}",
+                              filepath.display()
+                            ));
+                          }
+                        }
+                      }
+                      // assemble and load in one go, for picking up edits
+                      // made outside; a file that no longer parses leaves
+                      // both images as they were
+                      'R' => {
+                        executor_handler.running.store(false, Ordering::SeqCst);
+                        let name = filepath.display();
+                        match load_image(&filepath, dump_format) {
+                          Ok(image) => {
+                            fault = None;
+                            highlight = None;
+                            assembled_environment = image;
+                            assembled_environment.arithmetic = arithmetic;
+                            environment = assembled_environment.clone();
+                            let mut guard =
+                              executor_handler.environment.lock().await;
+                            *guard = environment.clone();
+                            std::mem::drop(guard);
+                            executor_handler
+                              .commands
+                              .send(executor::ExecutorCommand::Restart)?;
+                            message = Some(format!("Reloaded {name}"));
+                          }
+                          Err(error) => {
+                            message =
+                              Some(format!("Failed to reload {name}: {error}"));
+                          }
+                        }
+                      }
+                      'D' => {
+                        let path = filepath.with_extension("bin");
+                        let guard = executor_handler.environment.lock().await;
+                        let result = File::create(&path)
+                          .map_err(eyre::Report::from)
+                          .and_then(|mut file| guard.dump(&mut file, dump_format));
+                        std::mem::drop(guard);
+                        if let Err(error) = result {
+                          log::warn!("Failed to dump memory to {:?}: {error}", path);
+                        }
+                      }
+                      // memory as it stands, edits included, over the
+                      // loaded file if that was a MIF
+                      'W' => {
+                        let path = filepath.with_extension("mif");
+                        let guard = executor_handler.environment.lock().await;
+                        let result = File::create(&path)
+                          .map_err(eyre::Report::from)
+                          .and_then(|mut file| guard.to_mif(&mut file));
+                        std::mem::drop(guard);
+                        message = Some(match result {
+                          Ok(()) => format!("Wrote {}", path.display()),
+                          Err(error) => {
+                            format!("Failed to write {}: {error}", path.display())
+                          }
+                        });
                       }
                       'r' => {
                         active = MenuActive::Run;
@@ -404,12 +1355,26 @@ async fn run(
                           let guard = executor_handler.environment.lock().await;
                           environment = guard.clone();
                           std::mem::drop(guard);
+                          if executor_handler.profiling.load(Ordering::Relaxed) {
+                            hotspots = executor_handler
+                              .hotspots(profile::HOTSPOT_COUNT)
+                              .await?;
+                          }
+                          if heatmap.is_some() {
+                            heatmap = Some(memtable::Heatmap::new(
+                              executor_handler.accesses().await?,
+                            ));
+                          }
+                        } else if fault.is_some() {
+                          log::info!("Run is disabled until the machine is reset");
                         } else {
                           executor_handler.running.store(true, Ordering::Release);
                           executor_handler.notify.notify_waiters();
                         }
                       }
                       'l' => {
+                        fault = None;
+                        highlight = None;
                         environment = assembled_environment.clone();
                         active = MenuActive::Load;
                         executor_handler.running.store(false, Ordering::SeqCst);
@@ -418,17 +1383,303 @@ async fn run(
                         log::debug!("executor stopped successfully, lock acquired");
                         *guard = environment.clone();
                         std::mem::drop(guard);
+                        executor_handler
+                          .commands
+                          .send(executor::ExecutorCommand::Restart)?;
+                      }
+                      'p' | 'P' => {
+                        environment = Environment::default();
+                        environment.arithmetic = arithmetic;
+                        environment.fill(if c == 'p' {
+                          core::FillPattern::Incrementing
+                        } else {
+                          core::FillPattern::Constant(-1)
+                        });
+                        fault = None;
+                        highlight = None;
+                        executor_handler.running.store(false, Ordering::SeqCst);
+                        let mut guard = executor_handler.environment.lock().await;
+                        *guard = environment.clone();
+                        std::mem::drop(guard);
+                        executor_handler
+                          .commands
+                          .send(executor::ExecutorCommand::Restart)?;
                       }
                       'f' => {
                         state = MenuState::FileSelection;
                         active = MenuActive::File;
                       }
+                      'u' => memtable_unsigned = !memtable_unsigned,
+                      // undoes the last step, as far back as `--undo-depth`
+                      // steps or the last device write
+                      'b' => {
+                        executor_handler.running.store(false, Ordering::SeqCst);
+                        match executor_handler.step_back().await {
+                          Ok(()) => fault = None,
+                          Err(error) => {
+                            message = Some(format!("Can't step back: {error}"));
+                          }
+                        }
+                        let guard = executor_handler.environment.lock().await;
+                        environment = guard.clone();
+                        std::mem::drop(guard);
+                      }
+                      // one instruction; a fault comes back as a report like
+                      // any other
+                      // like Run, refused until a reset so the fault stays
+                      // on show
+                      's' if fault.is_some() => {
+                        message = Some(
+                          "Reset before stepping a faulted machine".to_owned(),
+                        );
+                      }
+                      's' => {
+                        executor_handler.running.store(false, Ordering::SeqCst);
+                        executor_handler.step_n(1).await?;
+                        let guard = executor_handler.environment.lock().await;
+                        environment = guard.clone();
+                        std::mem::drop(guard);
+                      }
+                      'g' => {
+                        let input = tui_input::Input::default();
+                        memory_prompt = Some((MemoryPrompt::Goto, input));
+                      }
+                      // edits the selected cell, starting from its value;
+                      // the executor's copy is written too, so only while
+                      // it's stopped
+                      'e' => {
+                        let addr = memtable::selected_address(
+                          &memtable_state,
+                          memtable_columns,
+                        );
+                        let running =
+                          executor_handler.running.load(Ordering::Acquire);
+                        match addr {
+                          _ if running => {
+                            message = Some(
+                              "Stop the program before editing memory"
+                                .to_owned(),
+                            );
+                          }
+                          Some(addr) => {
+                            memory_prompt = Some((
+                              MemoryPrompt::Poke,
+                              tui_input::Input::new(
+                                environment.memory[addr].to_string(),
+                              ),
+                            ));
+                          }
+                          None => {
+                            message = Some("Select a cell to edit".to_owned());
+                          }
+                        }
+                      }
+                      'i' => follow_iar = !follow_iar,
+                      't' => {
+                        throttle = (throttle + 1) % THROTTLE_RATES.len();
+                        executor_handler.commands.send(
+                          executor::ExecutorCommand::SetRate {
+                            rate: THROTTLE_RATES[throttle],
+                          },
+                        )?;
+                      }
+                      'o' => {
+                        if executor_handler.running.load(Ordering::Acquire) {
+                          message = Some(
+                            "Stop the program before changing arithmetic"
+                              .to_owned(),
+                          );
+                        } else {
+                          arithmetic = arithmetic.next();
+                          assembled_environment.arithmetic = arithmetic;
+                          environment.arithmetic = arithmetic;
+                          let mut guard =
+                            executor_handler.environment.lock().await;
+                          guard.arithmetic = arithmetic;
+                          std::mem::drop(guard);
+                          message = Some(format!("Arithmetic: {arithmetic}"));
+                        }
+                      }
+                      'z' => {
+                        device_refs.frozen.fetch_xor(true, Ordering::Relaxed);
+                      }
+                      '[' => {
+                        memtable_columns = memtable_columns
+                          .saturating_sub(1)
+                          .max(memtable::MIN_COLUMNS);
+                      }
+                      ']' => {
+                        memtable_columns =
+                          (memtable_columns + 1).min(memtable::MAX_COLUMNS);
+                      }
+                      'S' => {
+                        let path = filepath.with_extension("state");
+                        match executor_handler.save_machine().await {
+                          Ok(data) => {
+                            if let Err(error) = std::fs::write(&path, data) {
+                              log::warn!("Failed to write {:?}: {error}", path);
+                            }
+                          }
+                          Err(error) => {
+                            log::warn!("Failed to save machine: {error}")
+                          }
+                        }
+                      }
+                      'L' => {
+                        executor_handler.running.store(false, Ordering::SeqCst);
+                        let path = filepath.with_extension("state");
+                        let result = match std::fs::read(&path) {
+                          Ok(data) => executor_handler.restore_machine(data).await,
+                          Err(error) => Err(error.into()),
+                        };
+                        match result {
+                          Ok(()) => {
+                            fault = None;
+                            highlight = None;
+                          }
+                          Err(error) => {
+                            log::warn!("Failed to restore {:?}: {error}", path)
+                          }
+                        }
+                        lcd_dirty = true;
+                        let guard = executor_handler.environment.lock().await;
+                        environment = guard.clone();
+                        std::mem::drop(guard);
+                      }
+                      'I' => {
+                        let path = filepath.with_extension("inputs");
+                        let result = match executor_handler.input_log().await {
+                          Ok(events) => File::create(&path)
+                            .map_err(eyre::Report::from)
+                            .and_then(|mut file| {
+                              replay::write_log(&events, &mut file)
+                            }),
+                          Err(error) => Err(error),
+                        };
+                        if let Err(error) = result {
+                          log::warn!("Failed to write {:?}: {error}", path);
+                        }
+                      }
+                      'h' => {
+                        // counts restart with every profiling session
+                        let profiling =
+                          !executor_handler.profiling.load(Ordering::Relaxed);
+                        if profiling {
+                          executor_handler
+                            .commands
+                            .send(executor::ExecutorCommand::ResetProfile)?;
+                          hotspots.clear();
+                        } else {
+                          hotspots = executor_handler
+                            .hotspots(profile::HOTSPOT_COUNT)
+                            .await?;
+                        }
+                        executor_handler
+                          .profiling
+                          .store(profiling, Ordering::Relaxed);
+                      }
+                      // the heatmap needs the profiler's access counts, so
+                      // showing it starts profiling if it wasn't already
+                      'm' if heatmap.is_some() => heatmap = None,
+                      'm' => {
+                        if !executor_handler.profiling.swap(true, Ordering::Relaxed) {
+                          executor_handler
+                            .commands
+                            .send(executor::ExecutorCommand::ResetProfile)?;
+                          hotspots.clear();
+                        }
+                        heatmap = Some(memtable::Heatmap::new(
+                          executor_handler.accesses().await?,
+                        ));
+                      }
+                      'M' => {
+                        executor_handler
+                          .commands
+                          .send(executor::ExecutorCommand::ResetProfile)?;
+                        hotspots.clear();
+                        if heatmap.is_some() {
+                          heatmap = Some(memtable::Heatmap::new(
+                            executor_handler.accesses().await?,
+                          ));
+                        }
+                      }
+                      '/' => {
+                        active = MenuActive::Search;
+                      }
+                      // the next match after the selected cell, so moving the
+                      // selection moves where the search carries on from
+                      'n' => {
+                        let selected = memtable::selected_address(
+                          &memtable_state,
+                          memtable_columns,
+                        );
+                        let next = next_match(&search_results, selected);
+                        if let Some(index) = next {
+                          search_index = index;
+                          follow_iar = false;
+                          memtable_state.move_to(memtable::address_cell(
+                            search_results[index] as usize,
+                            memtable_columns,
+                          ));
+                        }
+                      }
+                      // jumps to the most recently written cell, then to
+                      // older ones on repeated presses until the program
+                      // writes something new; it stops following the IAR,
+                      // so the jump stays put while stepping
+                      'c' => {
+                        let writes = executor_handler.recent_writes().await?;
+                        if writes != recent_writes {
+                          recent_writes = writes;
+                          recent_index = 0;
+                        }
+                        if let Some(addr) = recent_writes.get(recent_index) {
+                          follow_iar = false;
+                          memtable_state
+                            .scroll_to_row(*addr as usize / memtable_columns);
+                          recent_index =
+                            (recent_index + 1) % recent_writes.len();
+                        }
+                      }
+                      '<' | '>' => {
+                        let batch_size =
+                          executor_handler.batch_size.load(Ordering::Relaxed);
+                        let batch_size = if c == '<' {
+                          (batch_size / 2).max(1)
+                        } else {
+                          batch_size.saturating_mul(2)
+                        };
+                        executor_handler
+                          .batch_size
+                          .store(batch_size, Ordering::Relaxed);
+                        log::info!("Executor batch size set to {batch_size}");
+                      }
                       _ => {
                         request_redraw = false;
                       }
                     }
                   }
                 }
+                event::KeyCode::Up
+                | event::KeyCode::Down
+                | event::KeyCode::Left
+                | event::KeyCode::Right
+                | event::KeyCode::PageUp
+                | event::KeyCode::PageDown
+                | event::KeyCode::Home
+                | event::KeyCode::End => {
+                  follow_iar = false;
+                  cellselection::handle_events(
+                    &mut memtable_state,
+                    true,
+                    &event,
+                  );
+                  // the address column can't be edited, so the cursor
+                  // steps over it
+                  if let Some((0, row)) = memtable_state.selected() {
+                    memtable_state.move_to((1, row));
+                  }
+                }
                 _ => {
                   request_redraw = false;
                 }
@@ -439,16 +1690,65 @@ async fn run(
               }
             }
           },
-          Some(report) = executor_handler.rx.recv() => {
+          Some((report, replayed)) =
+            next_report(&mut deferred, &mut executor_handler.rx) =>
+          {
             match report {
-              ExecutorReport::Failure { error } => {
+              ExecutorReport::Failure { error, backtrace } => {
                 log::warn!("Received failure report {:?}", error);
+                // deferred reports rang when they arrived
+                if bell && !replayed {
+                  ring_bell()?;
+                }
+                fault = Some((error, backtrace));
                 let guard = executor_handler.environment.lock().await;
                 environment = guard.clone();
                 std::mem::drop(guard);
+                if executor_handler.profiling.load(Ordering::Relaxed) {
+                  hotspots = executor_handler
+                    .hotspots(profile::HOTSPOT_COUNT)
+                    .await?;
+                }
+                if heatmap.is_some() {
+                  heatmap = Some(memtable::Heatmap::new(
+                    executor_handler.accesses().await?,
+                  ));
+                }
               },
-              ExecutorReport::Redraw => {
-                log::info!("Redrawing per executor request");
+              ExecutorReport::Redraw { devices, changed } => {
+                log::info!("Redrawing per executor request for {:?}", devices);
+                highlight = changed.or(highlight);
+                lcd_dirty |= devices.contains(&devices::DeviceKind::Lcd);
+                if last_draw.elapsed() < REDRAW_INTERVAL {
+                  redraw_due.get_or_insert(last_draw + REDRAW_INTERVAL);
+                  request_redraw = false;
+                }
+              },
+              ExecutorReport::Stats { steps, elapsed } => {
+                ips = Some(steps as f64 / elapsed.as_secs_f64());
+                if heatmap.is_some() {
+                  heatmap = Some(memtable::Heatmap::new(
+                    executor_handler.accesses().await?,
+                  ));
+                }
+              },
+              ExecutorReport::BreakpointHit { iar } => {
+                message = Some(format!("Breakpoint hit at {iar}"));
+                let guard = executor_handler.environment.lock().await;
+                environment = guard.clone();
+                std::mem::drop(guard);
+              },
+              ExecutorReport::WatchHit { addr, old, new } => {
+                message = Some(watch_message(addr, old, new));
+                let guard = executor_handler.environment.lock().await;
+                environment = guard.clone();
+                std::mem::drop(guard);
+              },
+              ExecutorReport::StepsDone { stepped } => {
+                message = Some(format!("Ran {stepped} steps"));
+                let guard = executor_handler.environment.lock().await;
+                environment = guard.clone();
+                std::mem::drop(guard);
               },
             }
           },
@@ -456,23 +1756,69 @@ async fn run(
       }
       MenuState::FileSelection => {
         terminal.draw(|f| {
-          f.render_widget(&file_explorer.widget(), f.area());
+          let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+              Constraint::Fill(1),
+              Constraint::Length(notice.is_some() as u16),
+            ])
+            .split(f.area());
+          f.render_widget(&file_explorer.widget(), layout[0]);
+          if let Some(notice) = &notice {
+            f.render_widget(
+              Paragraph::new(notice.as_str())
+                .style(Style::new().fg(Color::Black).bg(Color::Yellow)),
+              layout[1],
+            );
+          }
         })?;
 
-        let event = term_event_stream
-          .next()
-          .await
-          .ok_or_eyre("Crossterm event pipe disconnected")??;
+        // the executor keeps running behind the picker, so its reports are
+        // still drained here; only the reports that stop it are held for
+        // later, as the displays catch up on their own once the view is back
+        let event = tokio::select! {
+          event = term_event_stream.next() => {
+            event.ok_or_eyre("Crossterm event pipe disconnected")??
+          }
+          Some(report) = executor_handler.rx.recv() => {
+            match report {
+              ExecutorReport::Failure { ref error, .. } => {
+                notice = Some(match error {
+                  core::StepFatal::Halted => "Program halted".to_owned(),
+                  error => format!("Program faulted: {error}"),
+                });
+                if bell {
+                  ring_bell()?;
+                }
+                deferred.push_back(report);
+              }
+              ExecutorReport::BreakpointHit { iar } => {
+                notice = Some(format!("Breakpoint hit at {iar}"));
+                deferred.push_back(report);
+              }
+              ExecutorReport::WatchHit { addr, old, new } => {
+                notice = Some(watch_message(addr, old, new));
+                deferred.push_back(report);
+              }
+              ExecutorReport::StepsDone { .. } => deferred.push_back(report),
+              ExecutorReport::Redraw { .. } => lcd_dirty = true,
+              ExecutorReport::Stats { .. } => {}
+            }
+            continue;
+          }
+        };
         if let event::Event::Key(key) = event {
           match key.code {
             event::KeyCode::Char('q') => break Ok(()),
             event::KeyCode::Esc => {
               state = MenuState::Normal;
+              notice = None;
               continue;
             }
             event::KeyCode::Enter => {
               filepath = file_explorer.current().path().to_owned();
               state = MenuState::Normal;
+              notice = None;
               continue;
             }
             _ => {}
@@ -492,23 +1838,194 @@ async fn run(
   result
 }
 
+/// The next report from the executor, taking any deferred while the file
+/// picker was open first. The flag is set for deferred reports.
+async fn next_report(
+  deferred: &mut VecDeque<ExecutorReport>,
+  rx: &mut mpsc::UnboundedReceiver<ExecutorReport>,
+) -> Option<(ExecutorReport, bool)> {
+  match deferred.pop_front() {
+    Some(report) => Some((report, true)),
+    None => rx.recv().await.map(|report| (report, false)),
+  }
+}
+
+/// Describes a watchpoint stop, with `?` for values memory doesn't hold.
+fn watch_message(addr: u32, old: Option<i32>, new: Option<i32>) -> String {
+  let show = |value: Option<i32>| match value {
+    Some(value) => value.to_string(),
+    None => "?".to_owned(),
+  };
+  format!("Watchpoint at {addr}: {} -> {}", show(old), show(new))
+}
+
+/// Rings the terminal bell, for `--bell`.
+fn ring_bell() -> eyre::Result<()> {
+  let mut stdout = std::io::stdout();
+  stdout.write_all(b"\x07")?;
+  stdout.flush()?;
+  Ok(())
+}
+
+/// Reads the image at `path`: a raw dump in `dump_format` for `.bin`, which
+/// loads back with the format it was written in, a segment manifest for
+/// `.segs`, and MIF otherwise.
+fn load_image(
+  path: &Path,
+  dump_format: core::DumpFormat,
+) -> eyre::Result<Environment> {
+  match path.extension().and_then(|ext| ext.to_str()) {
+    Some("bin") => Environment::undump(&mut File::open(path)?, dump_format),
+    Some("segs") => load_segments(path, dump_format),
+    _ => Environment::parse(&mut File::open(path)?),
+  }
+}
+
+/// Builds an environment from a manifest listing one `base path` pair per
+/// line, with paths relative to the manifest. Segments are MIF files, or raw
+/// dumps in `dump_format` if they end in `.bin`. They load in order and
+/// overlaps are logged rather than rejected.
+fn load_segments(
+  manifest: &std::path::Path,
+  dump_format: core::DumpFormat,
+) -> eyre::Result<Environment> {
+  let dir = manifest.parent().unwrap_or(std::path::Path::new(""));
+  let mut environment = Environment::default();
+  for line in std::fs::read_to_string(manifest)?.lines() {
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+
+    let Some((base, path)) = line.split_once(char::is_whitespace) else {
+      eyre::bail!("expected `base path` in segment manifest, got {line:?}");
+    };
+    let path = dir.join(path.trim());
+    let mut file = File::open(&path)?;
+    let segment = if path.extension().is_some_and(|ext| ext == "bin") {
+      Environment::undump(&mut file, dump_format)?
+    } else {
+      Environment::parse(&mut file)?
+    };
+    let overlaps = environment.load_segment(&segment, base.parse()?)?;
+    if let (Some(first), Some(last)) = (overlaps.first(), overlaps.last()) {
+      log::warn!(
+        "{:?} overwrote {} cells between {first} and {last}",
+        path,
+        overlaps.len()
+      );
+    }
+  }
+
+  Ok(environment)
+}
+
+/// The index of the first of `matches` (in address order) after `selected`,
+/// wrapping round to the first if none are, or the first outright when
+/// nothing is selected.
+fn next_match(matches: &[u32], selected: Option<usize>) -> Option<usize> {
+  let after = selected.map_or(0, |addr| addr + 1);
+  let index = matches
+    .iter()
+    .position(|addr| *addr as usize >= after)
+    .unwrap_or(0);
+  (!matches.is_empty()).then_some(index)
+}
+
+/// Parses a search query of the form `value` or `value&mask`.
+fn parse_search(query: &str) -> Option<(i32, i32)> {
+  let (value, mask) = query.split_once('&').unwrap_or((query, "-1"));
+  Some((value.parse().ok()?, mask.parse().ok()?))
+}
+
+/// The instruction at `iar`, the live IAR, in the last memory fetched, which
+/// only matters to self-modifying programs. Every panel showing what's about
+/// to run decodes it from here so they agree. `None` past the last whole
+/// instruction, which `step` refuses to fetch from.
+fn current_instruction(
+  environment: &Environment,
+  iar: u32,
+) -> Option<[i32; 4]> {
+  let iar = iar as usize;
+  if iar > core::MEMORY_SIZE - 4 {
+    return None;
+  }
+  environment.memory[iar..iar + 4].try_into().ok()
+}
+
+/// Titles the operand panel with the mnemonic at `iar`, the live IAR, and
+/// lists each operand address with the value currently stored there.
+fn describe_operands(environment: &Environment, iar: u32) -> (String, String) {
+  let Some([opcode, ref operands @ ..]) = current_instruction(environment, iar)
+  else {
+    return ("Operands".to_owned(), String::new());
+  };
+  let Some((mnemonic, _, count)) =
+    asm::INSTRUCTIONS.iter().find(|(_, op, _)| *op == opcode)
+  else {
+    return (format!("Operands: {opcode}?"), String::new());
+  };
+
+  let values = operands[..*count]
+    .iter()
+    .map(
+      |operand| match environment.memory.get(*operand as u32 as usize) {
+        Some(value) => format!("{operand} = {value}"),
+        None if *operand as u32 >= core::DEVICE_BASE => {
+          format!("{operand} = (device)")
+        }
+        None => format!("{operand} = (unmapped)"),
+      },
+    )
+    .join("\n");
+  (format!("Operands: {mnemonic}"), values)
+}
+
 fn make_button<'a>(
   text: &'a str,
   title: &'a str,
   active: &'a MenuActive,
   target: MenuActive,
+) -> impl Widget + use<'a> {
+  make_button_with(text, title, active, target, true)
+}
+
+fn make_button_with<'a>(
+  text: &'a str,
+  title: &'a str,
+  active: &'a MenuActive,
+  target: MenuActive,
+  enabled: bool,
 ) -> impl Widget + use<'a> {
   Paragraph::new(text).block(
     Block::bordered()
       .title(title)
-      .style(Style::new().fg(get_colour(active, target))),
+      .style(Style::new().fg(get_colour(active, target, enabled))),
   )
 }
 
-fn get_colour(active: &MenuActive, target: MenuActive) -> Color {
-  if active == &target {
+/// Disabled buttons are greyed out even when selected, so a highlighted
+/// button is always one that will do something.
+fn get_colour(active: &MenuActive, target: MenuActive, enabled: bool) -> Color {
+  if !enabled {
+    Color::DarkGray
+  } else if active == &target {
     Color::Green
   } else {
     Color::Blue
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tab_skips_disabled_buttons() {
+    let enabled = |target| menu_enabled(target, true, false);
+    assert_eq!(MenuActive::Search.cycle(true, enabled), MenuActive::File);
+    assert_eq!(MenuActive::File.cycle(false, enabled), MenuActive::Search);
+    assert_eq!(MenuActive::Load.cycle(true, enabled), MenuActive::Reset);
+    assert_eq!(MenuActive::Reset.cycle(false, enabled), MenuActive::Load);
+  }
+}
@@ -1,11 +1,106 @@
-use rat_ftable::TableData;
-use ratatui::widgets::{Paragraph, Widget};
+use rat_ftable::{selection::CellSelection, TableData, TableState};
+use ratatui::{
+  layout::Constraint,
+  style::Color,
+  widgets::{Paragraph, Widget},
+};
 
-use crate::core::Environment;
+use crate::core::{Environment, MEMORY_SIZE};
 
-impl<'a> TableData<'a> for &'a Environment {
+pub const DEFAULT_COLUMNS: usize = 10;
+pub const MIN_COLUMNS: usize = 1;
+pub const MAX_COLUMNS: usize = 32;
+
+/// Background colours for the heatmap, coolest first.
+const HEAT_COLOURS: [Color; 5] = [
+  Color::Blue,
+  Color::Cyan,
+  Color::Green,
+  Color::Yellow,
+  Color::Red,
+];
+
+/// Access counts per cell, scaled for tinting the memory table.
+///
+/// Counts are bucketed on a log scale against the hottest cell, so a cell
+/// touched a handful of times still shows up next to a loop counter touched
+/// millions of times: a cell's colour is
+/// `HEAT_COLOURS[log(count) / log(max) * (len - 1)]`, and untouched cells
+/// keep the table's own style.
+pub struct Heatmap {
+  counts: Box<[u64; MEMORY_SIZE]>,
+  /// log2 of the hottest count plus one, so a single access still divides.
+  scale: f64,
+}
+
+impl Heatmap {
+  pub fn new(counts: Box<[u64; MEMORY_SIZE]>) -> Self {
+    let max = counts.iter().copied().max().unwrap_or(0);
+    Heatmap {
+      counts,
+      scale: ((max + 1) as f64).log2(),
+    }
+  }
+
+  pub fn colour(&self, addr: usize) -> Option<Color> {
+    let count = *self.counts.get(addr)?;
+    if count == 0 {
+      return None;
+    }
+
+    let heat = ((count + 1) as f64).log2() / self.scale;
+    let bucket = (heat * (HEAT_COLOURS.len() - 1) as f64).round() as usize;
+    Some(HEAT_COLOURS[bucket.min(HEAT_COLOURS.len() - 1)])
+  }
+}
+
+pub struct EnvView<'a> {
+  pub env: &'a Environment,
+  pub columns: usize,
+  /// Show cells as unsigned rather than signed decimal.
+  pub unsigned: bool,
+  /// Tints each cell's background by how often it has been accessed.
+  pub heatmap: Option<&'a Heatmap>,
+  /// The cell written most recently, drawn over any heatmap tint.
+  pub highlight: Option<u32>,
+}
+
+impl EnvView<'_> {
+  pub fn address(&self, column: usize, row: usize) -> usize {
+    row * self.columns + column - 1
+  }
+}
+
+/// The table cell `(column, row)` that shows `addr`.
+pub fn address_cell(addr: usize, columns: usize) -> (usize, usize) {
+  (addr % columns + 1, addr / columns)
+}
+
+/// The memory address in the selected cell, if one is selected.
+pub fn selected_address(
+  state: &TableState<CellSelection>,
+  columns: usize,
+) -> Option<usize> {
+  cell_address(state.selected()?, columns)
+}
+
+/// The memory address shown in table cell `(column, row)`, or `None` for the
+/// address column and the cells past the end of memory.
+pub fn cell_address(
+  (column, row): (usize, usize),
+  columns: usize,
+) -> Option<usize> {
+  let addr = (row * columns + column).checked_sub(1)?;
+  (column > 0 && addr < MEMORY_SIZE).then_some(addr)
+}
+
+impl<'a> TableData<'a> for EnvView<'a> {
   fn rows(&self) -> usize {
-    (crate::core::MEMORY_SIZE / 10) + 1
+    MEMORY_SIZE.div_ceil(self.columns)
+  }
+
+  fn widths(&self) -> Vec<Constraint> {
+    vec![Constraint::Fill(1); self.columns + 1]
   }
 
   fn render_cell(
@@ -18,15 +113,154 @@ impl<'a> TableData<'a> for &'a Environment {
   ) {
     // left side is addresses
     if column == 0 {
-      Paragraph::new(format!("{} ", row * 10))
+      Paragraph::new(format!("{} ", row * self.columns))
         .style(ctx.style)
         .right_aligned()
         .render(area, buf);
     } else {
-      Paragraph::new(format!("{}", self.memory[row * 10 + column - 1]))
-        .style(ctx.style)
+      // the final row is only partially backed by memory
+      let addr = self.address(column, row);
+      let Some(value) = self.env.memory.get(addr) else {
+        return;
+      };
+
+      let text = if self.unsigned {
+        (*value as u32).to_string()
+      } else {
+        value.to_string()
+      };
+      let colour = if self.highlight == Some(addr as u32) {
+        Some(Color::Magenta)
+      } else {
+        self.heatmap.and_then(|heatmap| heatmap.colour(addr))
+      };
+      // the selection wins over any tint so the cursor never disappears
+      let style = match (ctx.selected_cell, ctx.select_style, colour) {
+        (true, Some(select), _) => select,
+        (_, _, Some(colour)) => ctx.style.bg(colour).fg(Color::Black),
+        _ => ctx.style,
+      };
+      Paragraph::new(text)
+        .style(style)
         .left_aligned()
         .render(area, buf);
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn cells_map_to_addresses_at_any_width() {
+    let env = Environment::default();
+    let view = EnvView {
+      env: &env,
+      columns: 7,
+      unsigned: false,
+      heatmap: None,
+      highlight: None,
+    };
+
+    assert_eq!(view.address(1, 0), 0);
+    assert_eq!(view.address(7, 0), 6);
+    assert_eq!(view.address(1, 1), 7);
+    assert_eq!(view.address(3, 5), 37);
+    for addr in [0, 6, 7, 37, MEMORY_SIZE - 1] {
+      let (column, row) = address_cell(addr, 7);
+      assert_eq!(view.address(column, row), addr);
+      assert_eq!(cell_address((column, row), 7), Some(addr));
+    }
+    assert_eq!(cell_address((0, 3), 7), None);
+  }
+
+  #[test]
+  fn rows_cover_memory_without_a_spare() {
+    let env = Environment::default();
+    let view = |columns| EnvView {
+      env: &env,
+      columns,
+      unsigned: false,
+      heatmap: None,
+      highlight: None,
+    };
+
+    // a width that divides memory leaves no partial last row
+    assert_eq!(view(16).rows(), MEMORY_SIZE / 16);
+    assert_eq!(view(DEFAULT_COLUMNS).rows(), MEMORY_SIZE / 10 + 1);
+    assert_eq!(view(7).rows(), MEMORY_SIZE.div_ceil(7));
+  }
+
+  #[test]
+  fn last_row_leaves_cells_past_memory_blank() {
+    use rat_ftable::Table;
+    use ratatui::{buffer::Buffer, layout::Rect, widgets::StatefulWidget};
+
+    let mut env = Environment::default();
+    env.fill(crate::core::FillPattern::Constant(7));
+    let area = Rect::new(0, 0, 11 * 8, 1);
+    let mut buf = Buffer::empty(area);
+    let mut state = TableState::<CellSelection>::new();
+    // the first pass works out the page size the offset is clamped to
+    for _ in 0..2 {
+      let view = EnvView {
+        env: &env,
+        columns: DEFAULT_COLUMNS,
+        unsigned: false,
+        heatmap: None,
+        highlight: None,
+      };
+      state.set_row_offset(view.rows() - 1);
+      Table::<CellSelection>::new()
+        .data(view)
+        .render(area, &mut buf, &mut state);
+    }
+
+    // 16384 leaves four cells on the last row of ten
+    let text = buf
+      .content
+      .iter()
+      .map(|cell| cell.symbol())
+      .collect::<String>();
+    assert_eq!(
+      text.split_whitespace().collect::<Vec<_>>(),
+      ["16380", "7", "7", "7", "7"]
+    );
+  }
+
+  #[test]
+  fn unsigned_mode_shows_negatives_as_u32() {
+    use rat_ftable::Table;
+    use ratatui::{buffer::Buffer, layout::Rect, widgets::StatefulWidget};
+
+    let mut env = Environment::default();
+    env.memory[0] = -1;
+    let area = Rect::new(0, 0, 40, 1);
+    let render = |unsigned| {
+      let mut buf = Buffer::empty(area);
+      let mut state = TableState::<CellSelection>::new();
+      let view = EnvView {
+        env: &env,
+        columns: 1,
+        unsigned,
+        heatmap: None,
+        highlight: None,
+      };
+      Table::<CellSelection>::new()
+        .data(view)
+        .render(area, &mut buf, &mut state);
+      buf
+        .content
+        .iter()
+        .map(|cell| cell.symbol())
+        .collect::<String>()
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(render(false), ["0", "-1"]);
+    assert_eq!(render(true), ["0", "4294967295"]);
+  }
+}
@@ -0,0 +1,91 @@
+use crate::core::{StepReport, MEMORY_SIZE};
+
+/// How many of the hottest instructions the UI shows.
+pub const HOTSPOT_COUNT: usize = 4;
+
+/// Execution counts per instruction address, for finding inner loops, and
+/// read plus write counts per cell, for finding hot data.
+pub struct Profile {
+  counts: Box<[u64; MEMORY_SIZE]>,
+  accesses: Box<[u64; MEMORY_SIZE]>,
+}
+
+impl Default for Profile {
+  fn default() -> Self {
+    Profile {
+      counts: Box::new([0; MEMORY_SIZE]),
+      accesses: Box::new([0; MEMORY_SIZE]),
+    }
+  }
+}
+
+impl Profile {
+  /// Records the step that ran at `iar` and the cells it touched. Device
+  /// registers aren't counted.
+  pub fn record(&mut self, iar: u32, report: &StepReport) {
+    if let Some(count) = self.counts.get_mut(iar as usize) {
+      *count += 1;
+    }
+    for addr in report.reads.iter().chain([&report.changed]).flatten() {
+      if let Some(count) = self.accesses.get_mut(*addr as usize) {
+        *count += 1;
+      }
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.counts.fill(0);
+    self.accesses.fill(0);
+  }
+
+  pub fn accesses(&self) -> &[u64; MEMORY_SIZE] {
+    &self.accesses
+  }
+
+  /// The `limit` most executed addresses with their counts, hottest first.
+  /// Ties go to the lower address.
+  pub fn hottest(&self, limit: usize) -> Vec<(u32, u64)> {
+    let mut hot = self
+      .counts
+      .iter()
+      .enumerate()
+      .filter(|(_, count)| **count > 0)
+      .map(|(addr, count)| (addr as u32, *count))
+      .collect::<Vec<_>>();
+    hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    hot.truncate(limit);
+    hot
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn hottest_ranks_by_count_then_address() {
+    let mut profile = Profile::default();
+    let step = |changed| StepReport {
+      changed,
+      reads: [Some(10), Some(0x80000080), None],
+      ..Default::default()
+    };
+    for iar in [8, 4, 8, 0, 4, 8] {
+      profile.record(iar, &step(Some(11)));
+    }
+    profile.record(12, &step(None));
+
+    assert_eq!(
+      profile.hottest(HOTSPOT_COUNT),
+      [(8, 3), (4, 2), (0, 1), (12, 1)]
+    );
+    assert_eq!(profile.hottest(2), [(8, 3), (4, 2)]);
+    // device registers fall outside memory and go uncounted
+    assert_eq!(profile.accesses()[10], 7);
+    assert_eq!(profile.accesses()[11], 6);
+
+    profile.reset();
+    assert!(profile.hottest(HOTSPOT_COUNT).is_empty());
+    assert_eq!(profile.accesses()[10], 0);
+  }
+}
@@ -0,0 +1,195 @@
+use std::{
+  fs::File,
+  io::{BufRead, BufReader, Read, Write},
+  path::Path,
+  sync::atomic::Ordering,
+};
+
+use color_eyre::eyre;
+use tokio::sync::mpsc;
+
+use crate::{
+  core::Environment,
+  devices::{
+    kbd::TermKbdDevice,
+    onboard::{format_hex, HexDisplayDevice, LcdDisplayDevice},
+    scratch::ScratchDevice,
+    DeviceArray,
+  },
+  headless,
+};
+
+/// A key delivered to `TermKbdDevice`, stamped with the number of steps the
+/// executor had completed when it arrived.
+#[derive(Debug, Clone, Copy)]
+pub struct InputEvent {
+  pub cycle: u64,
+  pub key: u8,
+}
+
+/// Forwards keys to `TermKbdDevice`, logging the cycle each one arrived at.
+pub struct InputRecorder {
+  term_keys: mpsc::UnboundedSender<u8>,
+  /// Steps completed since the log was last reset.
+  cycles: u64,
+  events: Vec<InputEvent>,
+}
+
+impl InputRecorder {
+  pub fn new(term_keys: mpsc::UnboundedSender<u8>) -> Self {
+    InputRecorder {
+      term_keys,
+      cycles: 0,
+      events: Vec::new(),
+    }
+  }
+
+  pub fn tick(&mut self) {
+    self.cycles += 1;
+  }
+
+  pub fn record(&mut self, key: u8) {
+    self.events.push(InputEvent {
+      cycle: self.cycles,
+      key,
+    });
+    if self.term_keys.send(key).is_err() {
+      log::warn!("Terminal keyboard is gone, dropping key {key}");
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.cycles = 0;
+    self.events.clear();
+  }
+
+  /// Steps completed since the log was last reset.
+  pub fn cycles(&self) -> u64 {
+    self.cycles
+  }
+
+  pub fn events(&self) -> &[InputEvent] {
+    &self.events
+  }
+}
+
+/// Writes one `cycle key` pair per line.
+pub fn write_log(
+  events: &[InputEvent],
+  writer: &mut impl Write,
+) -> eyre::Result<()> {
+  for event in events.iter() {
+    writeln!(writer, "{} {}", event.cycle, event.key)?;
+  }
+  Ok(())
+}
+
+pub fn read_log(reader: &mut impl Read) -> eyre::Result<Vec<InputEvent>> {
+  let mut events = Vec::new();
+  for (index, line) in BufReader::new(reader).lines().enumerate() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    let Some((cycle, key)) = line.split_once(' ') else {
+      eyre::bail!("line {}: expected `cycle key`", index + 1);
+    };
+    events.push(InputEvent {
+      cycle: cycle.trim().parse()?,
+      key: key.trim().parse()?,
+    });
+  }
+  Ok(events)
+}
+
+/// Runs `program` headless, feeding each recorded key in just before the
+/// step it originally arrived at, then prints what the displays show.
+pub fn replay(
+  program: &Path,
+  inputs: &Path,
+  step_limit: u64,
+) -> eyre::Result<()> {
+  let mut environment = Environment::parse(&mut File::open(program)?)?;
+  let events = read_log(&mut File::open(inputs)?)?;
+
+  let lcd_device = LcdDisplayDevice::default();
+  let hex_device = HexDisplayDevice::default();
+  let (term_kbd_device, term_key_tx) = TermKbdDevice::new();
+  let lcd = lcd_device.lcd.clone();
+  let (hex0, hex1) = (hex_device.hex0.clone(), hex_device.hex1.clone());
+  let (decimal0, decimal1) =
+    (hex_device.decimal0.clone(), hex_device.decimal1.clone());
+
+  let mut device_array = DeviceArray::default();
+  device_array.register_device(Box::new(lcd_device))?;
+  device_array.register_device(Box::new(hex_device))?;
+  device_array.register_device(Box::new(term_kbd_device))?;
+  device_array.register_device(Box::new(ScratchDevice::default()))?;
+
+  let mut pending = events.iter().peekable();
+  let outcome = headless::run_with(
+    &mut environment,
+    &mut device_array,
+    step_limit,
+    |cycle| {
+      while let Some(event) = pending.next_if(|event| event.cycle <= cycle) {
+        // the receiver lives in the device array, which outlives the run
+        let _ = term_key_tx.send(event.key);
+      }
+    },
+  );
+  println!("{outcome}");
+
+  for line in lcd.lock().unwrap().iter() {
+    println!("lcd: {}", line.iter().collect::<String>());
+  }
+  println!(
+    "hex: {} {}",
+    format_hex(
+      hex1.load(Ordering::Relaxed),
+      decimal1.load(Ordering::Relaxed)
+    ),
+    format_hex(
+      hex0.load(Ordering::Relaxed),
+      decimal0.load(Ordering::Relaxed)
+    )
+  );
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn recorded_keys_round_trip_through_the_log() {
+    let (term_key_tx, mut term_key_rx) = mpsc::unbounded_channel();
+    let mut recorder = InputRecorder::new(term_key_tx);
+    recorder.record(b'a');
+    recorder.tick();
+    recorder.tick();
+    recorder.record(b'b');
+    assert_eq!(recorder.cycles(), 2);
+    assert_eq!(term_key_rx.try_recv().unwrap(), b'a');
+    assert_eq!(term_key_rx.try_recv().unwrap(), b'b');
+
+    let mut log = Vec::new();
+    write_log(recorder.events(), &mut log).unwrap();
+    assert_eq!(log, b"0 97\n2 98\n");
+    let events = read_log(&mut &log[..]).unwrap();
+    assert_eq!(
+      events
+        .iter()
+        .map(|event| (event.cycle, event.key))
+        .collect::<Vec<_>>(),
+      [(0, b'a'), (2, b'b')]
+    );
+
+    recorder.reset();
+    assert_eq!(recorder.cycles(), 0);
+    assert!(recorder.events().is_empty());
+    assert!(read_log(&mut &b"12\n"[..]).is_err());
+  }
+}
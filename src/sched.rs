@@ -0,0 +1,44 @@
+use tokio::runtime::{Builder, Runtime};
+
+/// Passes over the ready tasks per `settle`. One is enough for a task that
+/// only needs a single poll; more let a task wake another that then finishes
+/// in the same settle.
+const SETTLE_PASSES: usize = 4;
+
+/// A single-threaded runtime that only runs device background tasks (like
+/// the keyboard handshake) when `settle` is called, so a headless run sees
+/// them land at the same step every time instead of whenever a worker thread
+/// gets to them.
+///
+/// It has no timer or IO drivers, so nothing scheduled on it can depend on
+/// the wall clock.
+pub struct Scheduler {
+  runtime: Runtime,
+}
+
+impl Scheduler {
+  pub fn new() -> Self {
+    Scheduler {
+      runtime: Builder::new_current_thread()
+        .build()
+        .expect("a current-thread runtime without drivers can't fail"),
+    }
+  }
+
+  /// Runs `f` with tasks spawned inside it queued on this scheduler. They
+  /// make no progress until `settle`.
+  pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+    let _guard = self.runtime.enter();
+    f()
+  }
+
+  /// Polls every task that is ready to run, in the order they were woken,
+  /// then returns.
+  pub fn settle(&self) {
+    self.runtime.block_on(async {
+      for _ in 0..SETTLE_PASSES {
+        tokio::task::yield_now().await;
+      }
+    });
+  }
+}
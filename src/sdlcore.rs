@@ -1,3 +1,5 @@
+use std::sync::{Arc, RwLock};
+
 use color_eyre::eyre;
 use sdl3::{
   event::Event, keyboard::Keycode, mouse::MouseButton, render::WindowCanvas,
@@ -14,21 +16,37 @@ pub struct SdlExecutor {
   sdl: Sdl,
   canvas: WindowCanvas,
   pipes: SdlPipesBack,
+  viewport: (i32, i32),
+  /// Holds presenting back until a `Present`.
+  buffered: bool,
 }
 
-pub fn create_pipes() -> (SdlPipesBack, SdlPipesFront) {
-  let (draw_cmd_tx, draw_cmd_rx) = mpsc::channel(10);
-  // let (tscr_ev_tx, tscr_ev_rx) = watch::channel(SdlTscrEvent::default());
+/// Draw commands that can queue up before the SDL side falls behind.
+pub const DEFAULT_DRAW_QUEUE: usize = 64;
+
+/// Size of the window, which touches are clamped to.
+pub const WINDOW_WIDTH: u32 = 640;
+pub const WINDOW_HEIGHT: u32 = 480;
+
+pub fn create_pipes(draw_queue: usize) -> (SdlPipesBack, SdlPipesFront) {
+  let (draw_cmd_tx, draw_cmd_rx) = mpsc::channel(draw_queue);
+  let (tscr_ev_tx, tscr_ev_rx) = watch::channel(SdlTscrEvent::default());
   let (mouse_ev_tx, mouse_ev_rx) = watch::channel(SdlMouseEvent::default());
   let (kbd_ev_tx, kbd_ev_rx) = watch::channel(SdlKbdEvent::default());
+  let framebuffer =
+    Arc::new(RwLock::new(Framebuffer::new(WINDOW_WIDTH, WINDOW_HEIGHT)));
   (
     SdlPipesBack {
       draw_cmd_rx,
+      framebuffer: framebuffer.clone(),
+      tscr_ev_tx,
       mouse_ev_tx,
       kbd_ev_tx,
     },
     SdlPipesFront {
       draw_cmd_tx,
+      framebuffer,
+      tscr_ev_rx,
       mouse_ev_rx,
       kbd_ev_rx,
     },
@@ -37,26 +55,130 @@ pub fn create_pipes() -> (SdlPipesBack, SdlPipesFront) {
 
 pub struct SdlPipesBack {
   draw_cmd_rx: mpsc::Receiver<SdlDrawCommand>,
+  framebuffer: Arc<RwLock<Framebuffer>>,
+  tscr_ev_tx: watch::Sender<SdlTscrEvent>,
   mouse_ev_tx: watch::Sender<SdlMouseEvent>,
   kbd_ev_tx: watch::Sender<SdlKbdEvent>,
 }
 
 pub struct SdlPipesFront {
   pub draw_cmd_tx: mpsc::Sender<SdlDrawCommand>,
+  pub framebuffer: Arc<RwLock<Framebuffer>>,
+  pub tscr_ev_rx: watch::Receiver<SdlTscrEvent>,
   pub mouse_ev_rx: watch::Receiver<SdlMouseEvent>,
   pub kbd_ev_rx: watch::Receiver<SdlKbdEvent>,
 }
 
 #[derive(Clone)]
-pub struct SdlDrawCommand {
-  pub x1: i32,
-  pub y1: i32,
-  pub x2: i32,
-  pub y2: i32,
-  pub colour: i32,
+pub enum SdlDrawCommand {
+  Rect {
+    x1: i32,
+    y1: i32,
+    x2: i32,
+    y2: i32,
+    colour: i32,
+  },
+  /// A one-pixel line from (x1, y1) to (x2, y2), both ends included.
+  Line {
+    x1: i32,
+    y1: i32,
+    x2: i32,
+    y2: i32,
+    colour: i32,
+  },
+  Resize {
+    width: u32,
+    height: u32,
+  },
+  /// Offset added to every later `Rect` and `Line`.
+  Viewport {
+    x: i32,
+    y: i32,
+  },
+  /// Whether later draws wait for a `Present` to show. Going back to
+  /// drawing straight away presents whatever was waiting.
+  Buffered(bool),
+  Present,
+}
+
+/// What the window shows, kept on the CPU side so the VGA device can read
+/// pixels back. Drawn in step with the window, so commands still queued
+/// aren't in it yet.
+pub struct Framebuffer {
+  width: u32,
+  height: u32,
+  pixels: Vec<i32>,
+  /// Draw commands sent but not yet drawn here, counted by the sender.
+  pub pending: usize,
+}
+
+impl Framebuffer {
+  fn new(width: u32, height: u32) -> Self {
+    let mut framebuffer = Framebuffer {
+      width: 0,
+      height: 0,
+      pixels: Vec::new(),
+      pending: 0,
+    };
+    framebuffer.resize(width, height);
+    framebuffer
+  }
+
+  /// A black canvas of the new size, as the window is cleared on resizing.
+  fn resize(&mut self, width: u32, height: u32) {
+    self.width = width;
+    self.height = height;
+    self.pixels = vec![0; (width * height) as usize];
+  }
+
+  /// The colour at (x, y), or `None` off the canvas.
+  pub fn get(&self, x: i32, y: i32) -> Option<i32> {
+    let x = u32::try_from(x).ok().filter(|x| *x < self.width)?;
+    let y = u32::try_from(y).ok().filter(|y| *y < self.height)?;
+    Some(self.pixels[(y * self.width + x) as usize])
+  }
+
+  fn set(&mut self, x: i32, y: i32, colour: i32) {
+    if (0..self.width as i32).contains(&x)
+      && (0..self.height as i32).contains(&y)
+    {
+      self.pixels[(y as u32 * self.width + x as u32) as usize] = colour;
+    }
+  }
+
+  /// Fills `[x1, x2) x [y1, y2)`, as `fill_rect` does with the same corners.
+  fn fill_rect(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, colour: i32) {
+    for y in y1.max(0)..y2.min(self.height as i32) {
+      for x in x1.max(0)..x2.min(self.width as i32) {
+        self.set(x, y, colour);
+      }
+    }
+  }
+
+  /// Bresenham's line from (x1, y1) to (x2, y2), both ends included.
+  fn draw_line(&mut self, x1: i32, y1: i32, x2: i32, y2: i32, colour: i32) {
+    let (dx, dy) = ((x2 - x1).abs(), -(y2 - y1).abs());
+    let (sx, sy) = ((x2 - x1).signum(), (y2 - y1).signum());
+    let (mut x, mut y, mut error) = (x1, y1, dx + dy);
+    loop {
+      self.set(x, y, colour);
+      if x == x2 && y == y2 {
+        break;
+      }
+      if 2 * error >= dy {
+        error += dy;
+        x += sx;
+      }
+      if 2 * error <= dx {
+        error += dx;
+        y += sy;
+      }
+    }
+  }
 }
 
-#[derive(Default)]
+/// The left mouse button standing in for a finger on the screen.
+#[derive(Clone, Copy, Default)]
 pub struct SdlTscrEvent {
   pub x: i32,
   pub y: i32,
@@ -66,6 +188,8 @@ pub struct SdlTscrEvent {
 #[derive(Clone, Copy)]
 pub enum SdlMouseEvent {
   Motion {
+    x: i32,
+    y: i32,
     dx: i32,
     dy: i32,
   },
@@ -79,7 +203,12 @@ pub enum SdlMouseEvent {
 
 impl Default for SdlMouseEvent {
   fn default() -> Self {
-    SdlMouseEvent::Motion { dx: 0, dy: 0 }
+    SdlMouseEvent::Motion {
+      x: 0,
+      y: 0,
+      dx: 0,
+      dy: 0,
+    }
   }
 }
 
@@ -99,7 +228,9 @@ impl Default for SdlKbdEvent {
 }
 
 impl SdlExecutor {
-  pub async fn run(pipes: SdlPipesBack) {
+  /// Runs the window on its own thread until the draw queue closes, after
+  /// drawing everything that was still queued.
+  pub fn run(pipes: SdlPipesBack) -> std::thread::JoinHandle<eyre::Result<()>> {
     let rt = Builder::new_current_thread().enable_all().build().unwrap();
     std::thread::spawn(move || -> eyre::Result<()> {
       let local = LocalSet::new();
@@ -108,7 +239,7 @@ impl SdlExecutor {
         let video_subsystem = sdl.video()?;
 
         let window = video_subsystem
-          .window("VGA Buffer", 640, 480)
+          .window("VGA Buffer", WINDOW_WIDTH, WINDOW_HEIGHT)
           .position_centered()
           .build()?;
 
@@ -117,14 +248,31 @@ impl SdlExecutor {
         canvas.clear();
         canvas.present();
 
-        let exec = SdlExecutor { sdl, canvas, pipes };
+        let exec = SdlExecutor {
+          sdl,
+          canvas,
+          pipes,
+          viewport: (0, 0),
+          buffered: false,
+        };
 
         exec.process().await
       });
 
       rt.block_on(local);
       Ok(())
-    });
+    })
+  }
+
+  /// Publishes a touch at window coordinates, clamped to the window since
+  /// a drag can carry on past its edge.
+  fn touch(&self, x: f32, y: f32, pressed: bool) -> eyre::Result<()> {
+    self.pipes.tscr_ev_tx.send(SdlTscrEvent {
+      x: (x.round() as i32).clamp(0, WINDOW_WIDTH as i32 - 1),
+      y: (y.round() as i32).clamp(0, WINDOW_HEIGHT as i32 - 1),
+      pressed,
+    })?;
+    Ok(())
   }
 
   async fn process(mut self) -> eyre::Result<()> {
@@ -142,15 +290,59 @@ impl SdlExecutor {
     loop {
       //tracing::info!("SDL process loop");
       tokio::select! {
-        Some(SdlDrawCommand { x1, y1 , x2 , y2 , colour } ) = self.pipes.draw_cmd_rx.recv() => {
-          tracing::info!("Received draw command {x1} {y1} -- {x2} {y2} ({colour})");
-          self.canvas.set_draw_color(value_to_colour(colour));
-          self.canvas.fill_rect(Some((x1, y1, (x2 - x1) as u32, (y2 - y1) as u32).into()))?;
-          self.canvas.present();
+        command = self.pipes.draw_cmd_rx.recv() => {
+          // the VGA device going away means the machine has shut down
+          let Some(command) = command else {
+            break;
+          };
+          let flip = matches!(command, SdlDrawCommand::Present);
+          match command {
+            SdlDrawCommand::Rect { x1, y1, x2, y2, colour } => {
+              tracing::info!("Received draw command {x1} {y1} -- {x2} {y2} ({colour})");
+              let (x1, y1) = (x1 + self.viewport.0, y1 + self.viewport.1);
+              let (x2, y2) = (x2 + self.viewport.0, y2 + self.viewport.1);
+              self.pipes.framebuffer.write().unwrap()
+                .fill_rect(x1, y1, x2, y2, colour);
+              self.canvas.set_draw_color(value_to_colour(colour));
+              self.canvas.fill_rect(Some((x1, y1, (x2 - x1) as u32, (y2 - y1) as u32).into()))?;
+            }
+            SdlDrawCommand::Line { x1, y1, x2, y2, colour } => {
+              let (x1, y1) = (x1 + self.viewport.0, y1 + self.viewport.1);
+              let (x2, y2) = (x2 + self.viewport.0, y2 + self.viewport.1);
+              self.pipes.framebuffer.write().unwrap()
+                .draw_line(x1, y1, x2, y2, colour);
+              self.canvas.set_draw_color(value_to_colour(colour));
+              self.canvas.draw_line((x1, y1), (x2, y2))?;
+            }
+            SdlDrawCommand::Resize { width, height } => {
+              tracing::info!("Resizing canvas to {width}x{height}");
+              self.canvas.window_mut().set_size(width, height)?;
+              self.canvas.set_draw_color(sdl3::pixels::Color::BLACK);
+              self.canvas.clear();
+              self.pipes.framebuffer.write().unwrap().resize(width, height);
+            }
+            SdlDrawCommand::Viewport { x, y } => {
+              tracing::info!("Moving viewport to {x}, {y}");
+              self.viewport = (x, y);
+            }
+            SdlDrawCommand::Buffered(buffered) => self.buffered = buffered,
+            // drawn below
+            SdlDrawCommand::Present => {}
+          }
+          // only now is the command in the framebuffer
+          let mut framebuffer = self.pipes.framebuffer.write().unwrap();
+          framebuffer.pending = framebuffer.pending.saturating_sub(1);
+          std::mem::drop(framebuffer);
+          if flip || !self.buffered {
+            self.canvas.present();
+          }
         }
         Some(event) = event_stream.next() => {
           match event {
             Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+              if mouse_btn == MouseButton::Left {
+                self.touch(x, y, true)?;
+              }
               self.pipes.mouse_ev_tx.send(
                 SdlMouseEvent::Button {
                   mouse_btn,
@@ -161,6 +353,9 @@ impl SdlExecutor {
               )?;
             }
             Event::MouseButtonUp { mouse_btn, x, y, .. } => {
+              if mouse_btn == MouseButton::Left {
+                self.touch(x, y, false)?;
+              }
               self.pipes.mouse_ev_tx.send(
                 SdlMouseEvent::Button {
                   mouse_btn,
@@ -170,9 +365,15 @@ impl SdlExecutor {
                 }
               )?;
             }
-            Event::MouseMotion { xrel, yrel, .. } => {
+            Event::MouseMotion { mousestate, x, y, xrel, yrel, .. } => {
+              // dragging a finger across the screen
+              if mousestate.left() {
+                self.touch(x, y, true)?;
+              }
               self.pipes.mouse_ev_tx.send(
                 SdlMouseEvent::Motion {
+                  x: x.round() as i32,
+                  y: y.round() as i32,
                   dx: xrel.round() as i32,
                   dy: yrel.round() as i32
                 }